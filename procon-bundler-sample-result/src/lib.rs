@@ -29,7 +29,7 @@ mod procon_bundler_sample {
             my_macro2! {}
         }
     }
-    mod small_module {
+    pub mod small_module {
         #[allow(dead_code)]
         pub type A = ();
     }
@@ -42,9 +42,14 @@ mod procon_bundler_sample {
     }
     #[allow(dead_code)]
     enum A {}
-    #[cfg(test)]
     #[allow(dead_code)]
-    enum OnlyForTest {}
+    struct D;
+    impl D {
+        #[allow(dead_code)]
+        fn new() -> Self {
+            D
+        }
+    }
     #[allow(dead_code)]
     fn f() {
         my_macro! {}
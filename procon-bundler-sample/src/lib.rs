@@ -27,13 +27,27 @@ outer doc-comment block です。
 enum A {}
 
 /**
-#[cfg(test)] のついたアイテムは、消えていただきたいところですが、
-アイテムをパースするのが大変なので現状残してあります。
+#[cfg(test)] のついたアイテムも消えます。
 */
 #[cfg(test)]
 #[allow(dead_code)]
 enum OnlyForTest {}
 
+#[allow(dead_code)]
+struct D;
+
+impl D {
+    /// impl の中の #[cfg(test)] つきのアイテムも消えます。
+    #[cfg(test)]
+    #[allow(dead_code)]
+    fn only_for_test() {}
+
+    #[allow(dead_code)]
+    fn new() -> Self {
+        D
+    }
+}
+
 #[allow(dead_code)]
 fn f() {
     my_macro! {}
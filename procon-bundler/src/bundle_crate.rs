@@ -1,186 +1,1034 @@
-use crate::parse_line;
+use crate::{cfg, diagnostic, features, lexer, parse_item, parse_line, paths, prettify, resolver, types};
 use std::mem::take;
 
 use {
-    crate::{ConfigToml, Crate, Module, Resolve, Result, Span, TAB_LENGTH},
-    parse_line::{
-        parse_block_doc_comments_end, parse_block_doc_comments_start, parse_block_end,
-        parse_cfg_test, parse_module_block_begin, parse_module_decl, parse_oneline_doc_comments,
-        remove_indentation, substitute_path,
+    crate::{
+        BundleOptions, BundlerError, ConfigToml, Crate, CrateInfo, DroppedAttr, ItemSpan, ItemSpanKind, Module,
+        OverlayResolver, Resolve, Result, Span,
     },
+    cfg::{eval_attr, eval_inner_attr, is_cfg_test_with, parse_cfg_if, select_cfg_if_branch, AttrEval, CfgSet, InnerAttrEval},
+    diagnostic::Location,
+    features::{resolve_features, FeatureRequest},
+    lexer::{Delim, TokenKind},
+    parse_item::{parse_items, Item, ItemKind, Tokens},
+    parse_line::{indentation_width, remove_indentation},
+    prettify::format_module_lines,
+    paths::{PathContext, PathEdit},
+    types::{push_lines, LiteralLines, Origin, Origins},
+    resolver::{normalize_path, pick_module_file},
     std::{
+        cell::RefCell,
+        collections::{BTreeMap, HashMap, HashSet},
         io::BufRead,
+        ops::Range,
         path::{Path, PathBuf},
     },
 };
 
-pub fn bundle_crate<R: Resolve>(crate_name: &str, resolver: R, config_toml: ConfigToml) -> Result<Crate> {
-    CrateBundler::new(crate_name, resolver, config_toml).bundle_crate()
+// クレートをひとつだけ、`resolver` を通して読みながらバンドルします。パス依存はバンドルしません。
+// feature の指定 `request` から有効な feature を定め、`cfg` を評価します。
+pub fn bundle_crate<R: Resolve>(
+    crate_name: &str,
+    resolver: R,
+    config_toml: ConfigToml,
+    request: &FeatureRequest,
+    options: &BundleOptions,
+) -> Result<Crate> {
+    let cfg = CfgSet {
+        features: resolve_features(crate_name, &config_toml, request)?.enabled,
+    };
+    bundle_crate_with_cfg(crate_name, resolver, config_toml, cfg, options)
+}
+
+// 有効な feature の集合のもとで `cfg` を評価しながらバンドルします。
+pub(crate) fn bundle_crate_with_cfg<R: Resolve>(
+    crate_name: &str,
+    resolver: R,
+    config_toml: ConfigToml,
+    cfg: CfgSet,
+    options: &BundleOptions,
+) -> Result<Crate> {
+    CrateBundler::new(crate_name, resolver, config_toml, cfg, options).bundle_crate()
+}
+
+// `roots` のクレートを起点にパス依存を推移的にたどり、到達可能なクレートをすべてバンドルします。
+// 依存先が依存元よりも前に来るトポロジカル順で返し、各クレートはちょうど一度ずつ現れます。
+// 起点どうしが共通の依存を持っていても、それぞれ一度だけ現れます。
+// 同じクレートへの feature の指定は、Cargo と同様に合併します。
+// 起点を依存として宣言した位置があれば、その依存のエラーに添えます。
+// ファイルの内容は、ディスク上のパスをキーとする `buffers` にあればそれで置き換えます。
+pub(crate) fn bundle_crates_with_deps<'a>(
+    roots: impl IntoIterator<Item = (&'a str, &'a Path, FeatureRequest, Option<Location>)>,
+    options: &BundleOptions,
+    buffers: &BTreeMap<PathBuf, String>,
+) -> Result<Vec<Crate>> {
+    let mut walker = DependencyWalker {
+        options: options.clone(),
+        buffers: buffers.clone(),
+        ..DependencyWalker::default()
+    };
+    let mut root_keys = Vec::new();
+    for (crate_name, crate_root, request, site) in roots {
+        root_keys.push(walker.collect(crate_name, crate_root, request, site.as_ref())?);
+    }
+    for key in root_keys {
+        walker.visit(&key, None)?;
+    }
+    Ok(walker.crates)
+}
+
+// 依存グラフのひとつのクレートです。
+#[derive(Debug)]
+struct CrateNode {
+    name: String,
+    root: PathBuf,
+    config: ConfigToml,
+    request: FeatureRequest,
+    // 有効な依存クレートの正規化済みのクレートルートと、その依存を宣言した位置（名前順）
+    deps: Vec<(PathBuf, Option<Location>)>,
+    enabled: CfgSet,
+}
+
+#[derive(Debug, Default)]
+struct DependencyWalker {
+    // 正規化済みのクレートルート → クレート
+    nodes: HashMap<PathBuf, CrateNode>,
+    // 正規化済みのクレートルート → バンドル済みかどうか
+    visited: HashSet<PathBuf>,
+    // 探索中（帰りがけ前）のクレートルート
+    in_progress: HashSet<PathBuf>,
+    // クレート名 → 正規化済みのクレートルート
+    names: HashMap<String, PathBuf>,
+    // トポロジカル順に並んだ結果
+    crates: Vec<Crate>,
+    options: BundleOptions,
+    // ディスク上のパス → 保存されていないファイルの内容
+    buffers: BTreeMap<PathBuf, String>,
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
+impl DependencyWalker {
+    // feature の指定を伝播させながら依存グラフを集めます。指定が変わらなくなるまで繰り返します。
+    // このクレートについてのエラーには、依存を宣言した位置 `site` を添えます。
+    fn collect(
+        &mut self,
+        crate_name: &str,
+        crate_root: &Path,
+        request: FeatureRequest,
+        site: Option<&Location>,
+    ) -> Result<PathBuf> {
+        let at = |e: BundlerError| e.or_at(|| site.cloned());
+        let key = crate_root
+            .canonicalize()
+            .map_err(|e| BundlerError::CrateRootNotFound {
+                path: crate_root.to_path_buf(),
+                location: site.cloned(),
+                source: e,
+            })?;
+        match self.nodes.get_mut(&key) {
+            Some(node) => {
+                if !node.request.merge(&request) {
+                    return Ok(key);
+                }
+            }
+            None => {
+                if let Some(first) = self.names.insert(crate_name.replace('-', "_"), key.clone()) {
+                    return Err(BundlerError::DuplicateCrateName {
+                        name: crate_name.to_owned(),
+                        first,
+                        second: key,
+                        location: site.cloned().map(Box::new),
+                    });
+                }
+                let node = CrateNode {
+                    name: crate_name.to_owned(),
+                    root: crate_root.to_path_buf(),
+                    config: ConfigToml::from_crate_root(crate_root).map_err(at)?,
+                    request,
+                    deps: Vec::new(),
+                    enabled: CfgSet::default(),
+                };
+                self.nodes.insert(key.clone(), node);
+            }
+        }
+
+        // 依存をたどる間に循環を通ってこのクレートへの指定が増えたら、やり直します。
+        loop {
+            let node = &self.nodes[&key];
+            let request = node.request.clone();
+            let resolved = resolve_features(&node.name, &node.config, &request).map_err(at)?;
+            let deps = resolved
+                .deps
+                .into_iter()
+                .map(|(name, request)| {
+                    // 別名ではなく本来のパッケージ名でバンドルします。
+                    let dep = &node.config.deps[&name];
+                    let site = node.config.dep_locations.get(&name).cloned();
+                    (node.root.join(&dep.path), dep.package.clone(), request, site)
+                })
+                .collect::<Vec<_>>();
+            let mut dep_keys = Vec::new();
+            for (path, name, request, site) in deps {
+                let dep_key = self.collect(&name, &path, request, site.as_ref())?;
+                dep_keys.push((dep_key, site));
+            }
+            let node = self.nodes.get_mut(&key).unwrap();
+            if node.request == request {
+                node.deps = dep_keys;
+                node.enabled.features = resolved.enabled;
+                break;
+            }
+        }
+        Ok(key)
+    }
+    // 集めた依存グラフを帰りがけ順にたどってバンドルします。`site` はこのクレートへの依存を宣言した位置です。
+    fn visit(&mut self, key: &PathBuf, site: Option<&Location>) -> Result<()> {
+        if self.visited.contains(key) {
+            return Ok(());
+        }
+        let node = &self.nodes[key];
+        if !self.in_progress.insert(key.clone()) {
+            return Err(BundlerError::DependencyCycle {
+                path: node.root.clone(),
+                location: site.cloned(),
+            });
+        }
+        for (dep, site) in node.deps.clone() {
+            self.visit(&dep, site.as_ref())?;
+        }
+
+        let node = &self.nodes[key];
+        let resolver = OverlayResolver::with_buffers(node.root.clone(), &self.buffers);
+        let krate = bundle_crate_with_cfg(
+            &node.name,
+            resolver,
+            node.config.clone(),
+            node.enabled.clone(),
+            &self.options,
+        )?;
+        self.crates.push(krate);
+        self.in_progress.remove(key);
+        self.visited.insert(key.clone());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 struct CrateBundler<'a, R> {
     crate_name: &'a str,
     resolver: R,
     config_toml: ConfigToml,
+    cfg: CfgSet,
+    options: &'a BundleOptions,
+    // クレートのルートにしかつけられないために消した内部属性
+    dropped_attrs: RefCell<Vec<DroppedAttr>>,
 }
 
 impl<'a, R: Resolve> CrateBundler<'a, R> {
-    fn new(crate_name: &'a str, resolver: R, config_toml: ConfigToml) -> Self {
+    fn new(
+        crate_name: &'a str,
+        resolver: R,
+        config_toml: ConfigToml,
+        cfg: CfgSet,
+        options: &'a BundleOptions,
+    ) -> Self {
         Self {
             crate_name,
             resolver,
             config_toml,
+            cfg,
+            options,
+            dropped_attrs: RefCell::new(Vec::new()),
         }
     }
-    fn bundle_crate(&mut self) -> Result<Crate> {
-        let reader = self.resolver.resolve(Path::new("."))?;
+    fn bundle_crate(&self) -> Result<Crate> {
+        let file = SourceFile {
+            path: self.resolver.file_path(Path::new("."))?,
+            detached: false,
+            mod_rs: true,
+        };
+        let reader = self.resolver.resolve_file(&file.path)?;
+        // `#![cfg(...)]` で無効なクレートは、空のモジュールとします。
+        let root = self
+            .bundle_module(reader, PathBuf::from("."), &file)?
+            .unwrap_or_else(|| Module::new(PathBuf::from(".")));
         Ok(Crate {
             name: self.crate_name.to_owned(),
-            root: self.bundle_module(reader, PathBuf::from("."))?,
+            root,
+            info: CrateInfo {
+                version: self.config_toml.version.clone(),
+                root_dir: self.resolver.root_dir().map(Path::to_path_buf),
+                output: self.config_toml.output.clone(),
+                dropped_attrs: self.dropped_attrs.take(),
+            },
         })
     }
-    fn bundle_module(&mut self, reader: impl BufRead, mut current_module_path: PathBuf) -> Result<Module> {
-        fn push_line_to_stack<R>(me: &CrateBundler<R>, stack: &mut [Module], line: &str) {
-            let stack_len = stack.len();
-            let spans = &mut stack.last_mut().unwrap().spans;
-            if !matches!(spans.last(), Some(Span::Lines(_))) {
-                spans.push(Span::Lines(Vec::new()));
-            }
-            match spans.last_mut().unwrap() {
-                Span::Lines(ref mut lines) => {
-                    lines.push(remove_indentation(
-                        substitute_path(line, me.crate_name, &me.config_toml).as_ref(),
-                        stack_len - 1,
-                    ));
-                }
-                Span::Module(_) => unreachable!(),
-            }
-        }
-        // 結果がモジュール別に格納されるスタック
-        // （関数終了時には、要素数 1 になっているべきです。）
-        let mut stack = vec![Module::new(current_module_path.clone())];
-        // 未解決 #[cfg(test)] フラグ
-        let mut unresolved_cfg_test = None::<UnresolvedCfgTest>;
-        // doc comments 内フラグ
-        let mut in_doc_comments = false;
-
-        for line in reader.lines().map(|line| {
-            line.unwrap_or_else(|e| {
-                panic!(
-                    "Couldn't read a new line because of an IO error. e = {:?}",
-                    e
-                )
-            })
-        }) {
-            // この行を使う必要があるときに立てるフラグ
-            let mut needs_current_line = false;
-
-            // 正規表現によるパースをトライ
-            //
-            // どの `Case \d` にも合致しないときには、
-            // `needs_current_line` フラグが立つので、
-            // 直後に回収します。
-            if in_doc_comments {
-                // Case 1: ブロック doc comments の終了
-                // NOTE: `*/` は通常のブロックコメントの終了にも使われるので、
-                // フラグをチェックしています。
-                if parse_block_doc_comments_end(&line) {
-                    in_doc_comments = false;
+    // ファイルモジュール `mod name;` のファイルを探します。
+    //
+    // * `#[path]` があれば、rustc と同じ規則でそのファイルを使います。
+    // * 祖先に `#[path]` がなければ、モジュールパスから探します。
+    // * そうでなければ、ファイルの位置から `name.rs` と `name/mod.rs` を探します。
+    fn resolve_module_file(
+        &self,
+        file: &SourceFile,
+        inline_dirs: &[InlineDir],
+        module_path: &Path,
+        path_attr: Option<&str>,
+    ) -> Result<SourceFile> {
+        let inline_dir = inline_dirs.iter().map(|dir| &dir.path).collect::<PathBuf>();
+        if let Some(path_attr) = path_attr {
+            // インラインモジュールの外ならばファイルのあるディレクトリから、
+            // 中ならば子モジュールのディレクトリにインラインモジュールの分を足したところからの相対パスです。
+            let base = if inline_dirs.is_empty() {
+                file.path.parent().unwrap_or(Path::new("")).to_path_buf()
+            } else {
+                file.child_dir().join(inline_dir)
+            };
+            return Ok(SourceFile {
+                path: normalize_path(&base.join(path_attr)),
+                detached: true,
+                mod_rs: true,
+            });
+        }
+        if !file.detached && inline_dirs.iter().all(|dir| !dir.from_path_attr) {
+            let path = self.resolver.file_path(module_path)?;
+            let mod_rs = path.file_name().is_some_and(|name| name == "mod.rs");
+            return Ok(SourceFile {
+                path,
+                detached: false,
+                mod_rs,
+            });
+        }
+        let name = module_path.file_name().unwrap_or_default();
+        let dir = file.child_dir().join(inline_dir);
+        let candidates = vec![
+            normalize_path(&dir.join(name).with_extension("rs")),
+            normalize_path(&dir.join(name).join("mod.rs")),
+        ];
+        let path = pick_module_file(&self.resolver, module_path, candidates)?;
+        let mod_rs = path.file_name().is_some_and(|name| name == "mod.rs");
+        Ok(SourceFile {
+            path,
+            detached: true,
+            mod_rs,
+        })
+    }
+    fn bundle_module(
+        &self,
+        mut reader: impl BufRead,
+        module_path: PathBuf,
+        file: &SourceFile,
+    ) -> Result<Option<Module>> {
+        let mut src = String::new();
+        reader
+            .read_to_string(&mut src)
+            .map_err(|e| BundlerError::ModuleFileReadError {
+                path: self.resolver.display_path(&file.path),
+                location: None,
+                source: e,
+            })?;
+        let tokens = Tokens::new(&src).map_err(|e| BundlerError::Syntax {
+            location: Location::new(&self.resolver.display_path(&file.path), &src, e.offset..e.offset + 1),
+            message: e.message,
+        })?;
+        self.bundle_items(&tokens, 0..tokens.len(), 0..src.len(), module_path, file, &mut Vec::new())
+    }
+    // トークン範囲 `range`（ソース上ではバイト範囲 `text`）に並ぶアイテムを、ひとつのモジュールにまとめます。
+    //
+    // * モジュール以外のアイテムは、doc comments を除いて元のテキストのまま行に書き出します。
+    // * ファイルモジュールは、ファイルを探して再帰呼出しします。
+    // * インラインモジュールは、中括弧の内側について再帰呼出しします。
+    // * `cfg_if!` などのマクロ呼出しの中のモジュールも読み込みます（`bundle_macro` を見てください）。
+    // * `#[cfg(test)]` のついたモジュールは、ファイルを探さずに属性ごと読み飛ばします。
+    // * それ以外のテスト専用のアイテムは読み飛ばします。
+    // * feature が与えられていれば `cfg` を評価し、無効なアイテムを読み飛ばして、属性を書き換えます。
+    // * 内部属性は `eval_inner_attr` で評価し、`#![cfg(...)]` で無効なモジュールならば `None` を返します。
+    fn bundle_items(
+        &self,
+        tokens: &Tokens,
+        range: Range<usize>,
+        text: Range<usize>,
+        module_path: PathBuf,
+        file: &SourceFile,
+        inline_dirs: &mut Vec<InlineDir>,
+    ) -> Result<Option<Module>> {
+        let mut module = Module::new(module_path.clone());
+        // 深さではなく、実際のインデントの分だけ消します。
+        let indent = body_indentation(tokens, range.clone(), self.options.tab_width);
+        let paths = self.paths();
+        let mut lines = LineBuilder::new(tokens, range.clone(), text.start, self.options.remove_doc_comments, &paths);
+        for item in parse_items(tokens, range) {
+            if let ItemKind::Module { .. } = item.kind {
+                let child = self.bundle_module_item(tokens, &item, &mut lines, &mut module, indent, file, inline_dirs)?;
+                module.spans.extend(child.map(|child| Span::Module(Box::new(child))));
+                continue;
+            }
+            let (kind, name, vis) = tokens.item_signature(&item);
+            lines.outline(ItemOutline {
+                span: tokens.span(item.start()..item.end),
+                kind,
+                name,
+                vis,
+                attrs: self.kept_attrs(tokens, &item),
+                split: false,
+            });
+            if item.kind == ItemKind::InnerAttr {
+                let span = tokens.span(item.head..item.end);
+                match eval_inner_attr(tokens, item.head..item.end, Some(&self.cfg)) {
+                    InnerAttrEval::Keep => {}
+                    InnerAttrEval::Remove => lines.rewrite(span, &AttrEval::Remove),
+                    InnerAttrEval::Replace(text) => lines.rewrite(span, &AttrEval::Replace(text)),
+                    InnerAttrEval::CrateOnly(rewritten) => {
+                        self.dropped_attrs.borrow_mut().push(DroppedAttr {
+                            attr: tokens.src[span.clone()].to_owned(),
+                            origin: Origin {
+                                path: self.resolver.display_path(&file.path),
+                                line: lines.line_of(span.start),
+                            },
+                        });
+                        lines.rewrite(span, &rewritten.map_or(AttrEval::Remove, AttrEval::Replace));
+                    }
+                    InnerAttrEval::TestOnly => module.is_test = true,
+                    InnerAttrEval::DropModule => return Ok(None),
                 }
-            } else if let Some(name) = parse_module_decl(&line) {
-                // Case 2: モジュール宣言
-                //
-                // * モジュールパスを変更して再帰呼出し
-                // * モジュールパスを戻す
-                // * テストフラグが立っていればモジュールに反映
-                //
-                current_module_path.push(name);
-                let reader = self.resolver.resolve(&current_module_path)?;
-                let mut module = self.bundle_module(reader, current_module_path.clone())?;
-                module.is_test = take(&mut unresolved_cfg_test).is_some();
-                stack
-                    .last_mut()
-                    .unwrap()
-                    .spans
-                    .push(Span::Module(Box::new(module)));
-                current_module_path.pop();
-            } else if let Some(name) = parse_module_block_begin(&line) {
-                // Case 3: インラインモジュールの開始
-                //
-                // * モジュールパスを変更
-                // * スタックに新しいモジュールを積む
-                // * テストフラグが立っていればモジュールに反映
-                //
-                current_module_path.push(name);
-                let mut module = Module::new(current_module_path.clone());
-                module.is_test = take(&mut unresolved_cfg_test).is_some();
-                stack.push(module);
-            } else if let Some(space_count) = parse_block_end(&line) {
-                if 2 <= stack.len() && space_count == (stack.len() - 2) * TAB_LENGTH {
-                    // Case 4: インラインモジュールの終了
-                    //
-                    // * 終了したモジュールをスタックから取り出してスカッシュ
-                    // * モジュールパスを戻す
-                    //
-                    current_module_path.pop();
-                    let module = stack.pop().unwrap();
-                    stack
-                        .last_mut()
-                        .unwrap()
-                        .spans
-                        .push(Span::Module(Box::new(module)));
-                } else {
-                    needs_current_line = true;
+                continue;
+            }
+            match tokens.macro_block(&item) {
+                Some((name, body)) if !self.is_test_only(tokens, &item) && self.eval_attrs(tokens, &item).is_some() => {
+                    self.bundle_macro(tokens, &item, name, body, &mut lines, &mut module, indent, file, inline_dirs)?
                 }
+                _ => self.filter_item(tokens, &mut lines, &item),
+            }
+        }
+        lines.emit_until(text.end);
+        self.push_lines(&mut module, &mut lines, indent, file);
+        Ok(Some(module))
+    }
+    // モジュールの宣言 `item` の手前までを書き出して、モジュールを読み込みます。無効なモジュールならば `None` を返します。
+    #[allow(clippy::too_many_arguments)]
+    fn bundle_module_item(
+        &self,
+        tokens: &Tokens,
+        item: &Item,
+        lines: &mut LineBuilder,
+        module: &mut Module,
+        indent: usize,
+        file: &SourceFile,
+        inline_dirs: &mut Vec<InlineDir>,
+    ) -> Result<Option<Module>> {
+        let ItemKind::Module { name, body } = item.kind.clone() else {
+            unreachable!("not a module");
+        };
+        let span = tokens.span(item.start()..item.end);
+        let is_test = self.is_test_only(tokens, item);
+        let Some(evals) = self.eval_attrs(tokens, item).filter(|_| !is_test) else {
+            // 無効なモジュールとテスト専用のモジュールは、ファイルを探さずに読み飛ばします。
+            lines.emit_until(span.start);
+            lines.skip_to(span.end);
+            return Ok(None);
+        };
+        let path_attr = item.attrs.iter().find_map(|attr| tokens.path_attr(attr.clone()));
+
+        let child_path = module.path.join(&name);
+        let child = match body {
+            None => {
+                // 見つからないときなどは、モジュール宣言の位置を添えます。
+                let path = self.resolver.display_path(&file.path);
+                let location = || Some(Location::new(&path, tokens.src, tokens.span(item.head..item.end)));
+                let child_file = self
+                    .resolve_module_file(file, inline_dirs, &child_path, path_attr.as_deref())
+                    .map_err(|e| e.or_at(location))?;
+                let reader = self
+                    .resolver
+                    .resolve_file(&child_file.path)
+                    .map_err(|e| e.or_at(location))?;
+                self.bundle_module(reader, child_path, &child_file)
+                    .map_err(|e| e.or_at(location))?
+            }
+            Some(body) => {
+                inline_dirs.push(match path_attr {
+                    Some(path_attr) => InlineDir {
+                        path: PathBuf::from(path_attr),
+                        from_path_attr: true,
+                    },
+                    None => InlineDir {
+                        path: PathBuf::from(&name),
+                        from_path_attr: false,
+                    },
+                });
+                // 中括弧の内側のバイト範囲です。
+                let body_text = tokens.tokens[body.start - 1].end..tokens.tokens[body.end].start;
+                let child = self.bundle_items(tokens, body, body_text, child_path, file, inline_dirs)?;
+                inline_dirs.pop();
+                child
+            }
+        };
+        // 中身の `#![cfg(...)]` で無効なモジュールも、属性ごと読み飛ばします。
+        let Some(mut child) = child else {
+            lines.emit_until(span.start);
+            lines.skip_to(span.end);
+            return Ok(None);
+        };
+        let is_test = child.is_test;
+        // 可視性は `mod name {` の行に書き出します。`pub(in crate::a)` のパスも書き換えます。
+        let (_, _, vis) = tokens.item_signature(item);
+        child.vis = vis.map(|vis| self.paths().substitute(&vis).into_owned());
+
+        // モジュールの直前までと、残す属性を書き出します。
+        if let (false, Some(last)) = (is_test, item.attrs.last()) {
+            lines.outline(ItemOutline {
+                span: tokens.span(item.start()..last.end),
+                kind: ItemSpanKind::Attrs,
+                name: None,
+                vis: None,
+                attrs: self.kept_attrs(tokens, item),
+                split: false,
+            });
+        }
+        lines.emit_until(tokens.tokens[item.start()].start);
+        for (attr, eval) in item.attrs.iter().zip(&evals) {
+            let span = tokens.span(attr.clone());
+            if is_test || tokens.path_attr(attr.clone()).is_some() {
+                lines.emit_until(span.start);
+                lines.skip_to(span.end);
             } else {
-                // 前回ループの Case 5 で見た #[cfg(test)] が、この時点で解決していないならば、
-                // モジュールではなかったので、遅ればせながらプッシュします。
-                if let Some(UnresolvedCfgTest::Unknown(cfg_test)) = unresolved_cfg_test {
-                    unresolved_cfg_test = Some(UnresolvedCfgTest::Module);
-                    push_line_to_stack(self, &mut stack, &cfg_test);
+                lines.rewrite(span, eval);
+            }
+        }
+        lines.emit_until(tokens.tokens[item.head].start);
+        self.push_lines(module, lines, indent, file);
+
+        lines.skip_to(tokens.tokens[item.end - 1].end);
+        Ok(Some(child))
+    }
+    // マクロ呼出し `name! { body }` の中のモジュールを読み込みます。
+    //
+    // * `cfg_if!` の分岐がわかっている設定で定まれば、マクロの代わりにその分岐の中身を展開します。
+    // * それ以外はマクロをそのまま残し、中括弧の中にあるファイルモジュールをその場に `mod name { ... }` として展開します。
+    #[allow(clippy::too_many_arguments)]
+    fn bundle_macro(
+        &self,
+        tokens: &Tokens,
+        item: &Item,
+        name: &str,
+        body: Range<usize>,
+        lines: &mut LineBuilder,
+        module: &mut Module,
+        indent: usize,
+        file: &SourceFile,
+        inline_dirs: &mut Vec<InlineDir>,
+    ) -> Result<()> {
+        let selected = match name {
+            "cfg_if" if item.attrs.is_empty() => parse_cfg_if(tokens, body.clone())
+                .and_then(|branches| select_cfg_if_branch(&branches, &self.cfg).map(|branch| branch.cloned())),
+            _ => None,
+        };
+        let Some(selected) = selected else {
+            self.filter_item(tokens, lines, item);
+            return self.bundle_modules_in_macro(tokens, body, lines, module, indent, file, inline_dirs);
+        };
+        let span = tokens.span(item.start()..item.end);
+        lines.emit_until(span.start);
+        if let Some(branch) = selected {
+            self.push_lines(module, lines, indent, file);
+            let body_text = tokens.tokens[branch.body.start - 1].end..tokens.tokens[branch.body.end].start;
+            let child = self.bundle_items(tokens, branch.body, body_text, module.path.clone(), file, inline_dirs)?;
+            for span in child.into_iter().flat_map(|child| child.spans) {
+                match span {
+                    Span::Lines(texts, origins, literal_lines) => {
+                        push_lines(&mut module.spans, texts, origins, literal_lines)
+                    }
+                    span => module.spans.push(span),
                 }
-                if parse_cfg_test(&line) {
-                    // Case 5: #[cfg(test)]
-                    unresolved_cfg_test = Some(UnresolvedCfgTest::Unknown(line.to_owned()));
-                } else if parse_block_doc_comments_start(&line) {
-                    // Case 6: ブロック doc comments の開始
-                    assert!(!in_doc_comments);
-                    in_doc_comments = true;
-                } else if parse_oneline_doc_comments(&line) || line.is_empty() {
-                    // Case 7: oneline doc comments or 空行
-                    //
-                    // * なにもしません
-                } else {
-                    needs_current_line = true;
+            }
+        }
+        lines.skip_to(span.end);
+        Ok(())
+    }
+    // マクロの中のトークン範囲 `range` から、中括弧の中まで再帰的にファイルモジュールを探して、その場に展開します。
+    // インラインモジュールはそのまま残します。
+    #[allow(clippy::too_many_arguments)]
+    fn bundle_modules_in_macro(
+        &self,
+        tokens: &Tokens,
+        range: Range<usize>,
+        lines: &mut LineBuilder,
+        module: &mut Module,
+        indent: usize,
+        file: &SourceFile,
+        inline_dirs: &mut Vec<InlineDir>,
+    ) -> Result<()> {
+        for item in parse_items(tokens, range) {
+            match item.kind {
+                ItemKind::Module { body: None, .. } => {
+                    let child = self.bundle_module_item(tokens, &item, lines, module, indent, file, inline_dirs)?;
+                    let Some(child) = child.filter(|child| !child.is_test) else {
+                        continue;
+                    };
+                    // 宣言の行のインデントにそろえ、宣言と閉じ括弧の行は宣言の位置とします。
+                    let start = tokens.tokens[item.head].start;
+                    let line_start = tokens.src[..start].rfind('\n').map_or(0, |i| i + 1);
+                    let column = indentation_width(&tokens.src[line_start..start], self.options.tab_width)
+                        .saturating_sub(indent);
+                    let origin = Origin {
+                        path: self.resolver.display_path(&file.path),
+                        line: tokens.src[..start].matches('\n').count() + 1,
+                    };
+                    // リテラルの途中から始まる行にはインデントを足しません。
+                    let (texts, origins, literal_lines) = format_module_lines(&child, self.options.tab_width);
+                    let texts = texts
+                        .into_iter()
+                        .enumerate()
+                        .map(|(k, text)| {
+                            if literal_lines.contains(k) {
+                                text
+                            } else {
+                                format!("{}{}", " ".repeat(column), text)
+                            }
+                        })
+                        .collect();
+                    push_lines(
+                        &mut module.spans,
+                        texts,
+                        Origins(origins.into_iter().map(|o| o.unwrap_or_else(|| origin.clone())).collect()),
+                        literal_lines,
+                    );
                 }
+                ItemKind::Module { body: Some(_), .. } => {}
+                _ => {
+                    let mut i = item.head;
+                    while i < item.end {
+                        match tokens.tokens[i].kind {
+                            TokenKind::Open(Delim::Brace) => {
+                                let close = tokens.matching(i);
+                                let range = i + 1..close;
+                                self.bundle_modules_in_macro(tokens, range, lines, module, indent, file, inline_dirs)?;
+                                i = close + 1;
+                            }
+                            TokenKind::Open(_) => i = tokens.matching(i) + 1,
+                            _ => i += 1,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    // テスト専用のアイテムと `cfg` で無効なアイテムを読み飛ばし、残すアイテムの `cfg` の属性を書き換えます。
+    // impl, trait の中の関連アイテムも見ます。
+    fn filter_item(&self, tokens: &Tokens, lines: &mut LineBuilder, item: &Item) {
+        let evals = match self.is_test_only(tokens, item) {
+            true => None,
+            false => self.eval_attrs(tokens, item),
+        };
+        let Some(evals) = evals else {
+            let span = tokens.span(item.start()..item.end);
+            lines.emit_until(span.start);
+            lines.skip_to(span.end);
+            return;
+        };
+        for (attr, eval) in item.attrs.iter().zip(&evals) {
+            lines.rewrite(tokens.span(attr.clone()), eval);
+        }
+        if let ItemKind::ImplOrTrait { body } = &item.kind {
+            for assoc in parse_items(tokens, body.clone()) {
+                self.filter_item(tokens, lines, &assoc);
             }
+        }
+    }
+    // アイテムがテスト専用であるかを判定します。`cfg` を評価したあとの条件でも判定します。
+    fn is_test_only(&self, tokens: &Tokens, item: &Item) -> bool {
+        tokens.is_test_only(item) || item.attrs.iter().any(|attr| is_cfg_test_with(tokens, attr.clone(), &self.cfg))
+    }
+    // アイテムの属性をそれぞれ評価します。アイテムが無効ならば `None` を返します。
+    fn eval_attrs(&self, tokens: &Tokens, item: &Item) -> Option<Vec<AttrEval>> {
+        item.attrs
+            .iter()
+            .map(|attr| match eval_attr(tokens, attr.clone(), &self.cfg) {
+                AttrEval::DropItem => None,
+                eval => Some(eval),
+            })
+            .collect()
+    }
+    // パスを `crate::name::` などに書き換えるための情報です。
+    fn paths(&self) -> PathContext<'_> {
+        PathContext {
+            crate_name: Some(self.crate_name.replace('-', "_")),
+            config: &self.config_toml,
+        }
+    }
+    // 属性を評価したあとに残る属性です。
+    fn kept_attrs(&self, tokens: &Tokens, item: &Item) -> Vec<String> {
+        let evals = self.eval_attrs(tokens, item).unwrap_or_default();
+        item.attrs
+            .iter()
+            .zip(evals)
+            .filter(|(attr, _)| tokens.path_attr((*attr).clone()).is_none())
+            .filter_map(|(attr, eval)| match eval {
+                AttrEval::Keep | AttrEval::DropItem => Some(tokens.src[tokens.span(attr.clone())].to_owned()),
+                AttrEval::Replace(text) => Some(text),
+                AttrEval::Remove => None,
+            })
+            .collect()
+    }
+    // 書き出された行を、インデントを調整し、元の位置を添えてアイテムに分け、モジュールに追加します。
+    // 空行は捨てますが、複数行にわたるリテラルの途中の行はそのまま残します。
+    fn push_lines(&self, module: &mut Module, builder: &mut LineBuilder, indent: usize, file: &SourceFile) {
+        let lines = builder.take();
+        let path = self.resolver.display_path(&file.path);
+        let mut texts = Vec::new();
+        let mut origins = Vec::new();
+        let mut in_literal = Vec::new();
+        let mut last_line = 0;
+        for line in lines {
+            if !line.in_literal && line.text.trim().is_empty() {
+                continue;
+            }
+            // 空白だけのリテラルの途中の行は、直前の行の次の行とします。
+            last_line = line.line.unwrap_or(last_line + 1);
+            origins.push(Origin {
+                path: path.clone(),
+                line: last_line,
+            });
+            texts.push(if line.in_literal {
+                line.text
+            } else {
+                remove_indentation(&line.text, indent, self.options.tab_width)
+            });
+            in_literal.push(line.in_literal);
+        }
+        for (texts, origins, literal_lines, outline) in builder.split_items(texts, origins, in_literal) {
+            match outline {
+                Some(outline) => module.spans.push(Span::Item(Box::new(ItemSpan {
+                    kind: outline.kind,
+                    name: outline.name,
+                    vis: outline.vis,
+                    attrs: outline.attrs,
+                    lines: texts,
+                    origins: Origins(origins),
+                    literal_lines,
+                }))),
+                None => push_lines(&mut module.spans, texts, Origins(origins), literal_lines),
+            }
+        }
+    }
+}
 
-            // 「この行を使う必要があるときに立てるフラグ」回収です。
-            if needs_current_line {
-                push_line_to_stack(self, &mut stack, &line);
+// トークン範囲のうち、行頭にあるトークンのインデントの幅の最小値です。
+// 行頭にトークンがなければ（一行に書かれたモジュールなど）、インデントをすべて消すように `usize::MAX` を返します。
+fn body_indentation(tokens: &Tokens, range: Range<usize>, tab_width: usize) -> usize {
+    range
+        .filter_map(|i| {
+            let gap_start = i.checked_sub(1).map_or(0, |j| tokens.tokens[j].end);
+            let gap = &tokens.src[gap_start..tokens.tokens[i].start];
+            match gap.rfind('\n') {
+                Some(j) => Some(indentation_width(&gap[j + 1..], tab_width)),
+                None if gap_start == 0 => Some(indentation_width(gap, tab_width)),
+                None => None,
             }
+        })
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+// モジュールのソースファイルです。
+#[derive(Clone, Debug, Hash, PartialEq)]
+struct SourceFile {
+    // `src` からの相対パス
+    path: PathBuf,
+    // モジュールパスからはファイルが定まらないかどうか（`#[path]` で読み込まれたファイルとその子孫）
+    detached: bool,
+    // lib.rs, mod.rs, `#[path]` で読み込まれたファイルのように、子モジュールを同じディレクトリに持つかどうか
+    mod_rs: bool,
+}
+
+impl SourceFile {
+    // 子モジュールのファイルが置かれるディレクトリです。
+    fn child_dir(&self) -> PathBuf {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        match self.path.file_stem() {
+            Some(stem) if !self.mod_rs => dir.join(stem),
+            _ => dir.to_path_buf(),
         }
-        let res = stack.pop().unwrap();
-        assert!(stack.is_empty());
-        Ok(res)
     }
 }
 
+// インラインモジュールに対応するディレクトリです。
 #[derive(Clone, Debug, Hash, PartialEq)]
-enum UnresolvedCfgTest {
-    Unknown(String), // まだ mod の次がモジュールかどうかわからない状態
-    Module,          // モジュールと確定した状態
+struct InlineDir {
+    path: PathBuf,
+    from_path_attr: bool,
+}
+
+// 書き出し途中の行です。
+#[derive(Clone, Debug, Default, Hash, PartialEq)]
+struct RawLine {
+    text: String,
+    // 行頭が複数行にわたるリテラルの途中であるかどうか
+    in_literal: bool,
+    // 行の最初の空白でない文字の、ソース上の行番号（1 始まり）
+    line: Option<usize>,
+}
+
+// ソースを先頭から順に、（設定により）doc comments を除き、パスを書き換えながら行に書き出していくものです。
+struct LineBuilder<'a, 'b> {
+    tokens: &'b Tokens<'a>,
+    paths: &'b PathContext<'b>,
+    // パスの書き換え（位置の順）
+    edits: Vec<PathEdit<'a>>,
+    // ソースの各行の先頭のバイト位置
+    line_starts: Vec<usize>,
+    // ここまで書き出し（または読み飛ばし）済みのバイト位置
+    cursor: usize,
+    lines: Vec<RawLine>,
+    current: RawLine,
+    // 行頭の doc comments を読み飛ばした直後であれば、続く空白も読み飛ばします。
+    skip_spaces: bool,
+    remove_doc_comments: bool,
+    // 書き出す行をアイテムに分けるための、アイテムの範囲（位置の順）
+    outlines: Vec<ItemOutline>,
+}
+
+// アイテムのソース上の範囲と、その情報です。
+#[derive(Clone, Debug)]
+struct ItemOutline {
+    // 属性を含めたバイト範囲
+    span: Range<usize>,
+    kind: ItemSpanKind,
+    name: Option<String>,
+    vis: Option<String>,
+    attrs: Vec<String>,
+    // 途中で行を取り出した（マクロの中のモジュールを展開した）ために、ひとつのまとまりにならないかどうか
+    split: bool,
+}
+
+impl<'a, 'b> LineBuilder<'a, 'b> {
+    fn new(
+        tokens: &'b Tokens<'a>,
+        range: Range<usize>,
+        cursor: usize,
+        remove_doc_comments: bool,
+        paths: &'b PathContext<'b>,
+    ) -> Self {
+        Self {
+            tokens,
+            paths,
+            edits: paths.edits(&tokens.tokens[range], tokens.src),
+            line_starts: std::iter::once(0)
+                .chain(tokens.src.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            cursor,
+            lines: Vec::new(),
+            current: RawLine::default(),
+            skip_spaces: false,
+            remove_doc_comments,
+            outlines: Vec::new(),
+        }
+    }
+    // バイト位置 `until` までを書き出します。
+    fn emit_until(&mut self, until: usize) {
+        let tokens = self.tokens;
+        let mut index = tokens.tokens.partition_point(|token| token.start < self.cursor);
+        while let Some(token) = tokens.tokens.get(index).filter(|token| token.end <= until) {
+            self.push_src(self.cursor..token.start, false);
+            if let Some(edit) = self.edit_at(token.start).filter(|edit| edit.range.end <= until) {
+                let (text, end) = (edit.text.clone(), edit.range.end);
+                self.push_text(&text, token.start);
+                self.cursor = end;
+                index = tokens.tokens.partition_point(|token| token.start < end);
+                continue;
+            }
+            if self.remove_doc_comments && token.is_doc_comment() {
+                if self.current.text.trim().is_empty() {
+                    self.skip_spaces = true;
+                } else {
+                    self.trim_end();
+                }
+            } else {
+                self.push_src(token.range(), token.kind == TokenKind::Literal);
+            }
+            self.cursor = token.end;
+            index += 1;
+        }
+        if self.cursor < until {
+            self.push_src(self.cursor..until, false);
+            self.cursor = until;
+        }
+    }
+    fn edit_at(&self, start: usize) -> Option<&PathEdit<'a>> {
+        let index = self.edits.binary_search_by_key(&start, |edit| edit.range.start).ok()?;
+        Some(&self.edits[index])
+    }
+    // バイト位置 `until` までを読み飛ばします。
+    fn skip_to(&mut self, until: usize) {
+        self.trim_end();
+        self.cursor = until;
+    }
+    // 属性の評価結果にしたがって、属性を書き出すか、消すか、書き換えます。
+    fn rewrite(&mut self, span: Range<usize>, eval: &AttrEval) {
+        match eval {
+            AttrEval::Keep | AttrEval::DropItem => self.emit_until(span.end),
+            AttrEval::Remove => {
+                self.emit_until(span.start);
+                // 行頭の属性を消したときは、続く空白も読み飛ばします。
+                if self.current.text.trim().is_empty() {
+                    self.skip_spaces = true;
+                } else {
+                    self.trim_end();
+                }
+                self.cursor = span.end;
+            }
+            AttrEval::Replace(text) => {
+                self.emit_until(span.start);
+                let text = self.paths.substitute(text);
+                self.push_text(&text, span.start);
+                self.cursor = span.end;
+            }
+        }
+    }
+    // ソースのバイト範囲 `range` をそのまま書き出します。
+    fn push_src(&mut self, range: Range<usize>, is_literal: bool) {
+        let src = self.tokens.src;
+        self.push(&src[range.clone()], |i| range.start + i, is_literal);
+    }
+    // ソースのバイト位置 `offset` の部分を書き換えたテキストを書き出します。
+    fn push_text(&mut self, text: &str, offset: usize) {
+        self.push(text, |_| offset, false);
+    }
+    // `offset(i)` は、`text` の `i` バイト目のソース上の位置です。
+    fn push(&mut self, text: &str, offset: impl Fn(usize) -> usize, is_literal: bool) {
+        let mut start = 0;
+        if take(&mut self.skip_spaces) {
+            start = text.len() - text.trim_start_matches([' ', '\t']).len();
+        }
+        for (k, part) in text[start..].split('\n').enumerate() {
+            if k > 0 {
+                let next = RawLine {
+                    in_literal: is_literal,
+                    ..RawLine::default()
+                };
+                let mut line = std::mem::replace(&mut self.current, next);
+                if line.text.ends_with('\r') {
+                    line.text.pop();
+                }
+                self.lines.push(line);
+            }
+            if self.current.line.is_none() {
+                if let Some(i) = part.find(|c: char| !c.is_whitespace()) {
+                    let line = self.line_starts.partition_point(|&line_start| line_start <= offset(start + i));
+                    self.current.line = Some(line);
+                }
+            }
+            self.current.text.push_str(part);
+            start += part.len() + 1;
+        }
+    }
+    fn trim_end(&mut self) {
+        let len = self.current.text.trim_end_matches([' ', '\t']).len();
+        self.current.text.truncate(len);
+    }
+    // アイテムの範囲を記録します。
+    fn outline(&mut self, outline: ItemOutline) {
+        self.outlines.push(outline);
+    }
+    // ソースのバイト位置の行番号（1 始まり）です。
+    fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&line_start| line_start <= offset)
+    }
+    // 取り出した行（と元の行番号、リテラルの途中から始まるかどうか）を、アイテムごとのまとまりに分けます。
+    //
+    // 元の行がひとつのアイテムの範囲だけに入る行の並びは、そのアイテムの行のすべてであればアイテムとします。
+    // どのアイテムにも入らない行の並びはコメントとし、それ以外（一行に複数のアイテムがあるときなど）はただの行とします。
+    #[allow(clippy::type_complexity)]
+    fn split_items(
+        &mut self,
+        texts: Vec<String>,
+        origins: Vec<Origin>,
+        in_literal: Vec<bool>,
+    ) -> Vec<(Vec<String>, Vec<Origin>, LiteralLines, Option<ItemOutline>)> {
+        // 書き出しの途中のアイテムは、ひとつのまとまりになりません。
+        let cursor = self.cursor;
+        for outline in &mut self.outlines {
+            if outline.span.start < cursor && cursor < outline.span.end {
+                outline.split = true;
+            }
+        }
+        let ranges = self
+            .outlines
+            .iter()
+            .map(|outline| self.line_of(outline.span.start)..=self.line_of(outline.span.end - 1))
+            .collect::<Vec<_>>();
+        let owners = |line: usize| (0..ranges.len()).filter(|&k| ranges[k].contains(&line)).collect::<Vec<_>>();
+        // 持ち主の同じ行の並びです。
+        let mut groups = Vec::<(Vec<usize>, Vec<String>, Vec<Origin>, Vec<bool>)>::new();
+        for ((text, origin), literal) in texts.into_iter().zip(origins).zip(in_literal) {
+            let owner = owners(origin.line);
+            match groups.last_mut() {
+                Some((last, texts, origins, in_literal)) if *last == owner => {
+                    texts.push(text);
+                    origins.push(origin);
+                    in_literal.push(literal);
+                }
+                _ => groups.push((owner, vec![text], vec![origin], vec![literal])),
+            }
+        }
+        let mut count = vec![0; ranges.len()];
+        for &k in groups.iter().flat_map(|(owner, ..)| owner) {
+            count[k] += 1;
+        }
+        groups
+            .into_iter()
+            .map(|(owner, texts, origins, in_literal)| {
+                let outline = match owner[..] {
+                    [] => Some(ItemOutline {
+                        span: 0..0,
+                        kind: ItemSpanKind::Comment,
+                        name: None,
+                        vis: None,
+                        attrs: Vec::new(),
+                        split: false,
+                    }),
+                    [k] if count[k] == 1 && !self.outlines[k].split => Some(self.outlines[k].clone()),
+                    _ => None,
+                };
+                let literal_lines = LiteralLines((0..in_literal.len()).filter(|&k| in_literal[k]).collect());
+                (texts, origins, literal_lines, outline)
+            })
+            .collect()
+    }
+    // ここまでに書き出した行を取り出します。書きかけの行も一行として扱います。
+    fn take(&mut self) -> Vec<RawLine> {
+        self.trim_end();
+        let current = take(&mut self.current);
+        let mut lines = take(&mut self.lines);
+        lines.push(current);
+        lines
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::{bundle_crate, Crate, Module, Span},
-        crate::{manual_resolver, ConfigToml},
-        std::path::PathBuf,
+        super::{bundle_crate, bundle_crate_with_cfg, bundle_crates_with_deps, Crate, Module, Span},
+        crate::{
+            cfg::CfgSet, diagnostic::render, features::FeatureRequest, manual_resolver,
+            prettify::format_crates_with_source_map, types::{LiteralLines, Origins}, BundleOptions, BundlerError, ConfigToml,
+            CrateInfo, ItemSpan, ItemSpanKind, OutputConfig,
+        },
+        std::{
+            collections::BTreeMap,
+            path::{Path, PathBuf},
+        },
+        test_case::test_case,
     };
 
+    // アイテムを行のまとまりに戻して、行だけを比べます。
+    fn lines_only(my_crate: Crate) -> Crate {
+        Crate {
+            root: my_crate.root.into_lines(),
+            ..my_crate
+        }
+    }
+
     #[test]
     fn test_bundle_single_module() {
         manual_resolver! {
@@ -191,14 +1039,16 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![Span::Lines(vec!["hi,".to_owned(), "hello!".to_owned()])],
+                spans: vec![Span::lines(vec!["hi,".to_owned(), "hello!".to_owned()])],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -233,13 +1083,14 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![Span::Lines(vec![
+                spans: vec![Span::lines(vec![
                     "start".to_owned(),
                     "/* block comments */".to_owned(),
                     "// line comments".to_owned(),
@@ -250,6 +1101,7 @@ mod tests {
                     "end".to_owned(),
                 ])],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -269,25 +1121,28 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["hi,".to_owned()]),
+                    Span::lines(vec!["hi,".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
-                        spans: vec![Span::Lines(vec![
+                        spans: vec![Span::lines(vec![
                             "a also says: hi,".to_owned(),
                             "a also says: hello!".to_owned(),
                         ])],
                     })),
-                    Span::Lines(vec!["hello!".to_owned()]),
+                    Span::lines(vec!["hello!".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -302,31 +1157,19 @@ mod tests {
                     "mod a;\n",
                     "hello!\n",
                 ),
-                "./a" => concat!(
-                    "a also says: hi,\n",
-                    "a also says: hello!\n",
-                ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        // テスト専用のモジュールはファイルを探さないので、ファイルがなくてもエラーになりません。
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![
-                    Span::Lines(vec!["hi,".to_owned()]),
-                    Span::Module(Box::new(Module {
-                        is_test: true,
-                        path: PathBuf::from("./a"),
-                        spans: vec![Span::Lines(vec![
-                            "a also says: hi,".to_owned(),
-                            "a also says: hello!".to_owned(),
-                        ])],
-                    })),
-                    Span::Lines(vec!["hello!".to_owned()]),
-                ],
+                spans: vec![Span::lines(vec!["hi,".to_owned(), "hello!".to_owned()])],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -346,26 +1189,29 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["hi,".to_owned()]),
+                    Span::lines(vec!["hi,".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
-                        spans: vec![Span::Lines(vec![
-                            "hey".to_owned(),
+                        spans: vec![Span::lines(vec![
+                            " hey".to_owned(),
                             "shallow".to_owned(),
-                            " deep".to_owned(),
+                            "  deep".to_owned(),
                         ])],
                     })),
-                    Span::Lines(vec!["hello!".to_owned()]),
+                    Span::lines(vec!["hello!".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -386,26 +1232,16 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![
-                    Span::Lines(vec!["hi,".to_owned()]),
-                    Span::Module(Box::new(Module {
-                        is_test: true,
-                        path: PathBuf::from("./a"),
-                        spans: vec![Span::Lines(vec![
-                            "hey".to_owned(),
-                            "shallow".to_owned(),
-                            " deep".to_owned(),
-                        ])],
-                    })),
-                    Span::Lines(vec!["hello!".to_owned()]),
-                ],
+                spans: vec![Span::lines(vec!["hi,".to_owned(), "hello!".to_owned()])],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -436,49 +1272,55 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["begin .".to_owned()]),
+                    Span::lines(vec!["begin .".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
                         spans: vec![
-                            Span::Lines(vec!["begin a".to_owned()]),
+                            Span::lines(vec!["begin a".to_owned()]),
                             Span::Module(Box::new(Module {
                                 is_test: false,
+                                vis: None,
                                 path: PathBuf::from("./a/b"),
                                 spans: vec![
-                                    Span::Lines(vec!["begin b".to_owned()]),
+                                    Span::lines(vec!["begin b".to_owned()]),
                                     Span::Module(Box::new(Module {
                                         is_test: false,
+                                        vis: None,
                                         path: PathBuf::from("./a/b/c"),
                                         spans: vec![
-                                            Span::Lines(vec!["begin c".to_owned()]),
+                                            Span::lines(vec!["begin c".to_owned()]),
                                             Span::Module(Box::new(Module {
                                                 is_test: false,
+                                                vis: None,
                                                 path: PathBuf::from("./a/b/c/d"),
-                                                spans: vec![Span::Lines(vec![
+                                                spans: vec![Span::lines(vec![
                                                     "begin d".to_owned(),
                                                     "end d".to_owned(),
                                                 ])],
                                             })),
-                                            Span::Lines(vec!["end c".to_owned()]),
+                                            Span::lines(vec!["end c".to_owned()]),
                                         ],
                                     })),
-                                    Span::Lines(vec!["end b".to_owned()]),
+                                    Span::lines(vec!["end b".to_owned()]),
                                 ],
                             })),
-                            Span::Lines(vec!["end a".to_owned()]),
+                            Span::lines(vec!["end a".to_owned()]),
                         ],
                     })),
-                    Span::Lines(vec!["end .".to_owned()]),
+                    Span::lines(vec!["end .".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -513,49 +1355,55 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["begin .".to_owned()]),
+                    Span::lines(vec!["begin .".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
                         spans: vec![
-                            Span::Lines(vec!["begin a".to_owned()]),
+                            Span::lines(vec!["begin a".to_owned()]),
                             Span::Module(Box::new(Module {
                                 is_test: false,
+                                vis: None,
                                 path: PathBuf::from("./a/b"),
                                 spans: vec![
-                                    Span::Lines(vec!["begin b".to_owned()]),
+                                    Span::lines(vec!["begin b".to_owned()]),
                                     Span::Module(Box::new(Module {
                                         is_test: false,
+                                        vis: None,
                                         path: PathBuf::from("./a/b/c"),
                                         spans: vec![
-                                            Span::Lines(vec!["begin c".to_owned()]),
+                                            Span::lines(vec!["begin c".to_owned()]),
                                             Span::Module(Box::new(Module {
                                                 is_test: false,
+                                                vis: None,
                                                 path: PathBuf::from("./a/b/c/d"),
-                                                spans: vec![Span::Lines(vec![
+                                                spans: vec![Span::lines(vec![
                                                     "begin d".to_owned(),
                                                     "end d".to_owned(),
                                                 ])],
                                             })),
-                                            Span::Lines(vec!["end c".to_owned()]),
+                                            Span::lines(vec!["end c".to_owned()]),
                                         ],
                                     })),
-                                    Span::Lines(vec!["end b".to_owned()]),
+                                    Span::lines(vec!["end b".to_owned()]),
                                 ],
                             })),
-                            Span::Lines(vec!["end a".to_owned()]),
+                            Span::lines(vec!["end a".to_owned()]),
                         ],
                     })),
-                    Span::Lines(vec!["end .".to_owned()]),
+                    Span::lines(vec!["end .".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -588,42 +1436,47 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["begin .".to_owned()]),
+                    Span::lines(vec!["begin .".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
-                        spans: vec![Span::Lines(vec!["begin a".to_owned(), "end a".to_owned()])],
+                        spans: vec![Span::lines(vec!["begin a".to_owned(), "end a".to_owned()])],
                     })),
-                    Span::Lines(vec![
+                    Span::lines(vec![
                         "between a and b".to_owned(),
                         "between a and b".to_owned(),
                         "between a and b".to_owned(),
                     ]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./b"),
-                        spans: vec![Span::Lines(vec!["begin b".to_owned(), "end b".to_owned()])],
+                        spans: vec![Span::lines(vec!["begin b".to_owned(), "end b".to_owned()])],
                     })),
-                    Span::Lines(vec![
+                    Span::lines(vec![
                         "between b and c".to_owned(),
                         "between b and c".to_owned(),
                         "between b and c".to_owned(),
                     ]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./c"),
-                        spans: vec![Span::Lines(vec!["begin c".to_owned(), "end c".to_owned()])],
+                        spans: vec![Span::lines(vec!["begin c".to_owned(), "end c".to_owned()])],
                     })),
-                    Span::Lines(vec!["end .".to_owned()]),
+                    Span::lines(vec!["end .".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -658,50 +1511,58 @@ mod tests {
                 "./a/b/c/d/g" => "in g",
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![Span::Module(Box::new(Module {
                     is_test: false,
+                    vis: None,
                     path: PathBuf::from("./a"),
                     spans: vec![Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a/b"),
                         spans: vec![Span::Module(Box::new(Module {
                             is_test: false,
+                            vis: None,
                             path: PathBuf::from("./a/b/c"),
                             spans: vec![Span::Module(Box::new(Module {
                                 is_test: false,
+                                vis: None,
                                 path: PathBuf::from("./a/b/c/d"),
                                 spans: vec![
-                                    Span::Lines(vec![
+                                    Span::lines(vec![
                                         "before e".to_owned(),
                                         "before e".to_owned(),
                                         "before e".to_owned(),
                                     ]),
                                     Span::Module(Box::new(Module {
                                         is_test: false,
+                                        vis: None,
                                         path: PathBuf::from("./a/b/c/d/e"),
                                         spans: vec![Span::Module(Box::new(Module {
                                             is_test: false,
+                                            vis: None,
                                             path: PathBuf::from("./a/b/c/d/e/f"),
-                                            spans: vec![Span::Lines(vec!["in f".to_owned()])],
+                                            spans: vec![Span::lines(vec!["in f".to_owned()])],
                                         }))],
                                     })),
-                                    Span::Lines(vec![
+                                    Span::lines(vec![
                                         "between e and g".to_owned(),
                                         "between e and g".to_owned(),
                                         "between e and g".to_owned(),
                                     ]),
                                     Span::Module(Box::new(Module {
                                         is_test: false,
+                                        vis: None,
                                         path: PathBuf::from("./a/b/c/d/g"),
-                                        spans: vec![Span::Lines(vec!["in g".to_owned()])],
+                                        spans: vec![Span::lines(vec!["in g".to_owned()])],
                                     })),
-                                    Span::Lines(vec![
+                                    Span::lines(vec![
                                         "after g".to_owned(),
                                         "after g".to_owned(),
                                         "after g".to_owned(),
@@ -712,11 +1573,13 @@ mod tests {
                     }))],
                 }))],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
 
     #[test]
+    #[allow(clippy::useless_concat)]
     fn test_simple_substitution() {
         manual_resolver! {
             struct ManualResolver {
@@ -725,14 +1588,16 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, build_sample_config_toml()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, build_sample_config_toml(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![Span::Lines(vec!["use crate::crate_a::item_a;".to_owned()])],
+                spans: vec![Span::lines(vec!["use crate::crate_a::item_a;".to_owned()])],
             },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -748,18 +1613,57 @@ mod tests {
                 ),
             }
         }
-        let result = bundle_crate("my_crate", ManualResolver {}, build_sample_config_toml()).unwrap();
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, build_sample_config_toml(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
         let expected = Crate {
             name: "my_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![Span::Module(Box::new(Module {
                     is_test: false,
+                    vis: None,
                     path: PathBuf::from("./a"),
-                    spans: vec![Span::Lines(vec!["use crate::crate_a::item_a;".to_owned()])],
+                    spans: vec![Span::lines(vec!["use crate::crate_a::item_a;".to_owned()])],
                 }))],
             },
+            info: CrateInfo::default(),
+        };
+        assert_eq!(result, expected);
+    }
+
+    // 複数行の use tree も書き換え、リテラルの中は書き換えません。
+    #[test]
+    fn test_substitute_use_tree_across_lines() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "extern crate crate_b;\n",
+                    "use {\n",
+                    "    crate_a::item_a,\n",
+                    "    std::io,\n",
+                    "};\n",
+                    "const S: &str = \"crate_a::item_a\";\n",
+                ),
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, build_sample_config_toml(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        let expected = Crate {
+            name: "my_crate".to_owned(),
+            root: Module {
+                is_test: false,
+                vis: None,
+                path: PathBuf::from("."),
+                spans: vec![Span::lines(vec![
+                    "use crate::crate_b;".to_owned(),
+                    "use {".to_owned(),
+                    "    crate::crate_a::item_a,".to_owned(),
+                    "    std::io,".to_owned(),
+                    "};".to_owned(),
+                    "const S: &str = \"crate_a::item_a\";".to_owned(),
+                ])],
+            },
+            info: CrateInfo::default(),
         };
         assert_eq!(result, expected);
     }
@@ -775,4 +1679,935 @@ mod tests {
         "#,
         ).unwrap()
     }
+
+    #[test]
+    fn test_bundle_with_deps_in_topological_order() {
+        let crates = bundle_crates_with_deps([("root", Path::new("../testcase/deps/root"), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        let crates = crates.into_iter().map(lines_only).collect::<Vec<_>>();
+        let names = crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        // leaf は mid と root の両方から依存されていますが、一度だけ現れます。
+        assert_eq!(names, vec!["leaf", "mid", "root"]);
+        assert_eq!(
+            crates[1].root.spans,
+            vec![Span::lines(vec![
+                "pub use crate::leaf::Leaf;".to_owned(),
+                "pub struct Mid(pub Leaf);".to_owned(),
+            ])]
+        );
+    }
+
+    // 別名で依存されたクレートは、本来のパッケージ名で一度だけバンドルされます。
+    #[test]
+    fn test_bundle_with_renamed_deps() {
+        let crates = bundle_crates_with_deps([("app", Path::new("../testcase/renamed/app"), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        let crates = crates.into_iter().map(lines_only).collect::<Vec<_>>();
+        let names = crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["fenwick-tree", "user", "app"]);
+        assert_eq!(
+            crates[1].root.spans,
+            vec![Span::lines(vec!["pub struct User(pub crate::fenwick_tree::Fenwick);".to_owned()])]
+        );
+        assert_eq!(
+            crates[2].root.spans,
+            vec![Span::lines(vec![
+                "use crate::fenwick_tree::Fenwick;".to_owned(),
+                "pub fn make() -> crate::user::User {".to_owned(),
+                "    crate::user::User(Fenwick)".to_owned(),
+                "}".to_owned(),
+            ])]
+        );
+    }
+
+    // ワークスペースから継承した依存も、ルートからのパスでたどります。
+    #[test]
+    fn test_bundle_with_workspace_deps() {
+        let crates = bundle_crates_with_deps([("app", Path::new("../testcase/inherit/crates/app"), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        let crates = crates.into_iter().map(lines_only).collect::<Vec<_>>();
+        let names = crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["fenwick-tree", "seg-tree", "app"]);
+        assert_eq!(
+            crates[2].root.spans,
+            vec![Span::lines(vec!["pub struct App(pub crate::fenwick_tree::Fenwick, pub crate::seg_tree::Seg);".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn test_bundle_with_deps_detects_cycle() {
+        let err = bundle_crates_with_deps([("cyclic_a", Path::new("../testcase/deps/cyclic_a"), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new()).unwrap_err();
+        assert!(matches!(err, BundlerError::DependencyCycle { .. }));
+        assert_eq!(
+            render(&err),
+            concat!(
+                "error: Cyclic path dependency detected at \"../testcase/deps/cyclic_a\"\n",
+                " --> ../testcase/deps/cyclic_a/../cyclic_b/Cargo.toml:7:1\n",
+                "  |\n",
+                "7 | cyclic_a = { path = \"../cyclic_a\" }\n",
+                "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+            )
+        );
+    }
+
+    // 依存クレートについてのエラーのスニペットは、依存を宣言した行を指します。
+    // エラーメッセージには環境によるもの（OS のエラーや絶対パス）が入るので、スニペットだけを比べます。
+    #[test_case("missing_root" => concat!(
+        " --> ../testcase/errors/missing_root/Cargo.toml:7:1\n",
+        "  |\n",
+        "7 | gone = { path = \"../gone\" }\n",
+        "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+    ); "crate root not found")]
+    #[test_case("missing_manifest" => concat!(
+        " --> ../testcase/errors/missing_manifest/Cargo.toml:7:1\n",
+        "  |\n",
+        "7 | no_manifest = { path = \"../no_manifest\" }\n",
+        "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+    ); "cargo toml not found")]
+    #[test_case("unreadable" => concat!(
+        " --> ../testcase/errors/unreadable/Cargo.toml:7:1\n",
+        "  |\n",
+        "7 | bad_utf8 = { path = \"../bad_utf8\" }\n",
+        "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+    ); "cargo toml read error")]
+    #[test_case("duplicate" => concat!(
+        " --> ../testcase/errors/duplicate/Cargo.toml:8:1\n",
+        "  |\n",
+        "8 | dup-renamed = { path = \"../dup_2\", package = \"dup\" }\n",
+        "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+    ); "duplicate crate name")]
+    #[test_case("unknown_feature" => concat!(
+        " --> ../testcase/errors/unknown_feature/Cargo.toml:7:1\n",
+        "  |\n",
+        "7 | base = { path = \"../../features/base\", features = [\"huge\"] }\n",
+        "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+    ); "unknown feature")]
+    fn test_bundle_with_deps_error_location(crate_name: &str) -> String {
+        let crate_root = Path::new("../testcase/errors").join(crate_name);
+        let err = bundle_crates_with_deps([(crate_name, crate_root.as_path(), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new())
+            .unwrap_err();
+        render(&err).split_once('\n').unwrap().1.to_owned()
+    }
+
+    #[test]
+    fn test_module_visibility() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "pub mod a {\n",
+                    "    pub(in crate::a) mod b {}\n",
+                    "}\n",
+                    "pub(crate) mod c;\n",
+                    "mod d {}\n",
+                ),
+                "./c" => "",
+            }
+        }
+        let result = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap();
+        let vis = |span: &Span| match span {
+            Span::Module(module) => module.vis.clone(),
+            _ => panic!("not a module: {:?}", span),
+        };
+        let Span::Module(a) = &result.root.spans[0] else {
+            panic!("not a module");
+        };
+        assert_eq!(vis(&result.root.spans[0]).as_deref(), Some("pub"));
+        // `pub(in path)` のパスもクレートの中のパスに書き換えます。
+        assert_eq!(vis(&a.spans[0]).as_deref(), Some("pub(in crate::my_crate::a)"));
+        assert_eq!(vis(&result.root.spans[1]).as_deref(), Some("pub(crate)"));
+        assert_eq!(vis(&result.root.spans[2]), None);
+    }
+
+    #[test]
+    fn test_path_attribute() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#[path = \"gen/table.rs\"]\n",
+                    "mod table;\n",
+                    "mod a;\n",
+                ),
+                "gen/table.rs" => "mod inner;\n",
+                "gen/inner.rs" => "in gen/inner.rs\n",
+                "./a" => concat!(
+                    "#[path = \"shared.rs\"]\n",
+                    "mod top;\n",
+                    "mod b {\n",
+                    "    #[path = \"x.rs\"]\n",
+                    "    mod c;\n",
+                    "}\n",
+                ),
+                "shared.rs" => "in shared.rs\n",
+                "a/b/x.rs" => "in a/b/x.rs\n",
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        let expected = Crate {
+            name: "my_crate".to_owned(),
+            root: Module {
+                is_test: false,
+                vis: None,
+                path: PathBuf::from("."),
+                spans: vec![
+                    Span::Module(Box::new(Module {
+                        is_test: false,
+                        vis: None,
+                        path: PathBuf::from("./table"),
+                        spans: vec![Span::Module(Box::new(Module {
+                            is_test: false,
+                            vis: None,
+                            path: PathBuf::from("./table/inner"),
+                            spans: vec![Span::lines(vec!["in gen/inner.rs".to_owned()])],
+                        }))],
+                    })),
+                    Span::Module(Box::new(Module {
+                        is_test: false,
+                        vis: None,
+                        path: PathBuf::from("./a"),
+                        spans: vec![
+                            Span::Module(Box::new(Module {
+                                is_test: false,
+                                vis: None,
+                                path: PathBuf::from("./a/top"),
+                                spans: vec![Span::lines(vec!["in shared.rs".to_owned()])],
+                            })),
+                            Span::Module(Box::new(Module {
+                                is_test: false,
+                                vis: None,
+                                path: PathBuf::from("./a/b"),
+                                spans: vec![Span::Module(Box::new(Module {
+                                    is_test: false,
+                                    vis: None,
+                                    path: PathBuf::from("./a/b/c"),
+                                    spans: vec![Span::lines(vec!["in a/b/x.rs".to_owned()])],
+                                }))],
+                            })),
+                        ],
+                    })),
+                ],
+            },
+            info: CrateInfo::default(),
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_path_attribute_not_on_module_is_kept() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#[path = \"x.rs\"]\n",
+                    "fn f() {}\n",
+                ),
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![Span::lines(vec!["#[path = \"x.rs\"]".to_owned(), "fn f() {}".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn test_inline_module_with_other_indentation() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "mod a {\n",
+                    "  mod b {\n",
+                    "    fn f() {\n",
+                    "      x\n",
+                    "    }\n",
+                    "  }\n",
+                    "}\n",
+                    "mod c {\n",
+                    "\tmod d {\n",
+                    "\t\tfn g() {\n",
+                    "\t\t\ty\n",
+                    "\t\t}\n",
+                    "\t}\n",
+                    "}\n",
+                ),
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        let nested = |outer: &str, inner: &str, lines: [&str; 3]| {
+            Span::Module(Box::new(Module {
+                is_test: false,
+                vis: None,
+                path: PathBuf::from(outer),
+                spans: vec![Span::Module(Box::new(Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from(inner),
+                    spans: vec![Span::lines(lines.iter().map(|line| line.to_string()).collect())],
+                }))],
+            }))
+        };
+        assert_eq!(
+            result.root.spans,
+            vec![
+                // 2 つの空白のインデントは、そのまま残します。
+                nested("./a", "./a/b", ["fn f() {", "  x", "}"]),
+                // タブは `tab_width` 個の空白に置き換えます。
+                nested("./c", "./c/d", ["fn g() {", "    y", "}"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_one_line_inline_module() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "mod a { fn f() {} }\n",
+                    "mod b { mod c; }\n",
+                ),
+                "./b/c" => "in c\n",
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        let expected = Crate {
+            name: "my_crate".to_owned(),
+            root: Module {
+                is_test: false,
+                vis: None,
+                path: PathBuf::from("."),
+                spans: vec![
+                    Span::Module(Box::new(Module {
+                        is_test: false,
+                        vis: None,
+                        path: PathBuf::from("./a"),
+                        spans: vec![Span::lines(vec!["fn f() {}".to_owned()])],
+                    })),
+                    Span::Module(Box::new(Module {
+                        is_test: false,
+                        vis: None,
+                        path: PathBuf::from("./b"),
+                        spans: vec![Span::Module(Box::new(Module {
+                            is_test: false,
+                            vis: None,
+                            path: PathBuf::from("./b/c"),
+                            spans: vec![Span::lines(vec!["in c".to_owned()])],
+                        }))],
+                    })),
+                ],
+            },
+            info: CrateInfo::default(),
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_origins() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "use a::f;\n",
+                    "\n",
+                    "mod a;\n",
+                    "fn g() {\n",
+                    "    f()\n",
+                    "}\n",
+                ),
+                "./a" => "/// doc\npub fn f() {}\n",
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        // 比較では元の位置は無視されるので、直接確かめます。
+        let origins = |span: &Span| match span {
+            Span::Lines(_, origins, _) => origins.0.iter().map(|origin| (origin.path.to_str().unwrap().to_owned(), origin.line)).collect::<Vec<_>>(),
+            _ => panic!("not lines"),
+        };
+        let spans = &result.root.spans;
+        assert_eq!(origins(&spans[0]), vec![("lib.rs".to_owned(), 1)]);
+        let Span::Module(a) = &spans[1] else { panic!("not a module") };
+        assert_eq!(origins(&a.spans[0]), vec![("a.rs".to_owned(), 2)]);
+        assert_eq!(
+            origins(&spans[2]),
+            vec![("lib.rs".to_owned(), 4), ("lib.rs".to_owned(), 5), ("lib.rs".to_owned(), 6)]
+        );
+    }
+
+    #[test]
+    fn test_braces_in_literals_and_module_across_lines() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "pub mod a {\n",
+                    "    const S: &str = r#\"\n",
+                    "}\n",
+                    "\n",
+                    "    \"#;\n",
+                    "    const C: char = '}';\n",
+                    "    pub const M: &str = \"multi\n",
+                    "line }\";\n",
+                    "}\n",
+                    "pub mod\n",
+                    "    b\n",
+                    "    ;\n",
+                    "modules! {\n",
+                    "    mod c;\n",
+                    "}\n",
+                ),
+                "./b" => "in b\n",
+                "./c" => "const L: &str = \"x\n  y\";\n",
+            }
+        }
+        manual_resolver! {
+            struct DepResolver {
+                "." => "pub const T: &str = \"a\nb\";\n",
+            }
+        }
+        let options = BundleOptions {
+            output: OutputConfig {
+                header: Some(String::new()),
+                footer: Some(String::new()),
+                allow: Some(Vec::new()),
+            },
+            ..BundleOptions::default()
+        };
+        let my_crate = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &options).unwrap();
+        let dep = bundle_crate("dep", DepResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &options).unwrap();
+        let (result, _) = format_crates_with_source_map(vec![my_crate, dep], &options);
+        // 複数行にわたるリテラルの途中から始まる行には、インデントを足しません。
+        let expected = concat!(
+            "mod my_crate {\n",
+            "    pub mod a {\n",
+            "        const S: &str = r#\"\n",
+            "}\n",
+            "\n",
+            "    \"#;\n",
+            "        const C: char = '}';\n",
+            "        pub const M: &str = \"multi\n",
+            "line }\";\n",
+            "    }\n",
+            "    pub mod b {\n",
+            "        in b\n",
+            "    }\n",
+            "    modules! {\n",
+            "        mod c {\n",
+            "            const L: &str = \"x\n",
+            "  y\";\n",
+            "        }\n",
+            "    }\n",
+            "}\n",
+            "mod dep {\n",
+            "    pub const T: &str = \"a\n",
+            "b\";\n",
+            "}",
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_doc_comments_closed_mid_line() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "/** doc */ fn f() {}\n",
+                    "/**\n",
+                    " * doc\n",
+                    " */ fn g() {} /// trailing doc\n",
+                    "fn h() {} // line comments stay\n",
+                ),
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![Span::lines(vec![
+                "fn f() {}".to_owned(),
+                "fn g() {}".to_owned(),
+                "fn h() {} // line comments stay".to_owned(),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "fn f() {}\n",
+                    "mod a {\n",
+                ),
+            }
+        }
+        let err = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            BundlerError::Syntax { ref location, .. } if (location.line, location.column) == (2, 7)
+        ));
+    }
+
+    #[test]
+    fn test_module_file_not_found_location() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "fn f() {}\n",
+                    "mod a {\n",
+                    "    pub mod missing;\n",
+                    "}\n",
+                ),
+            }
+        }
+        let err = bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap_err();
+        let BundlerError::ModuleFileNotFound {
+            location: Some(location),
+            ..
+        } = err
+        else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(location.path, PathBuf::from("lib.rs"));
+        assert_eq!((location.line, location.column, location.width), (3, 5, 16));
+    }
+
+    #[test]
+    fn test_bundle_with_deps_propagates_features() {
+        let crates = bundle_crates_with_deps([("app", Path::new("../testcase/features/app"), FeatureRequest::default(), None)], &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        let crates = crates.into_iter().map(lines_only).collect::<Vec<_>>();
+        let names = crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "app"]);
+        // default-features = false なので small は無効で、app の default から fast が有効になります。
+        assert_eq!(crates[0].root.spans, vec![Span::lines(vec!["pub fn fast() {}".to_owned()])]);
+        assert_eq!(crates[1].root.spans, vec![Span::lines(vec!["pub use crate::base::*;".to_owned()])]);
+
+        let request = FeatureRequest {
+            features: ["big".to_owned()].into_iter().collect(),
+            ..FeatureRequest::default()
+        };
+        let crates = bundle_crates_with_deps([("app", Path::new("../testcase/features/app"), request, None)], &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        let crates = crates.into_iter().map(lines_only).collect::<Vec<_>>();
+        let names = crates.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "extra", "app"]);
+        assert_eq!(
+            crates[0].root.spans,
+            vec![Span::lines(vec!["pub fn fast() {}".to_owned(), "pub fn big() {}".to_owned()])]
+        );
+        assert_eq!(
+            crates[2].root.spans,
+            vec![Span::lines(vec![
+                "pub use crate::base::*;".to_owned(),
+                "pub use crate::extra::Extra;".to_owned(),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_bundle_with_deps_unknown_feature() {
+        let request = FeatureRequest {
+            features: ["huge".to_owned()].into_iter().collect(),
+            ..FeatureRequest::default()
+        };
+        let err = bundle_crates_with_deps([("app", Path::new("../testcase/features/app"), request, None)], &BundleOptions::default(), &BTreeMap::new()).unwrap_err();
+        assert!(matches!(err, BundlerError::UnknownFeature { .. }));
+    }
+
+    #[test]
+    fn test_cfg_feature() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#[cfg(feature = \"a\")]\n",
+                    "use std::io;\n",
+                    "#[cfg(feature = \"b\")]\n",
+                    "use std::fmt;\n",
+                    "#[cfg(not(feature = \"b\"))]\n",
+                    "mod a;\n",
+                    "#[cfg(feature = \"b\")]\n",
+                    "mod missing;\n",
+                    "#[cfg_attr(feature = \"a\", derive(Debug, Clone))]\n",
+                    "#[cfg_attr(feature = \"b\", derive(Hash))]\n",
+                    "struct A;\n",
+                    "impl A {\n",
+                    "    #[cfg(any(feature = \"b\", unix))]\n",
+                    "    fn unix() {}\n",
+                    "    #[cfg(feature = \"b\")] fn b() {}\n",
+                    "    #[cfg(feature = \"a\")] fn a() {}\n",
+                    "}\n",
+                    "#[cfg(test)]\n",
+                    "mod tests {}\n",
+                ),
+                "./a" => "pub struct B;\n",
+            }
+        }
+        let cfg = CfgSet {
+            features: ["a".to_owned()].into_iter().collect(),
+        };
+        let result = lines_only(bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![
+                Span::lines(vec!["use std::io;".to_owned()]),
+                Span::Module(Box::new(Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("./a"),
+                    spans: vec![Span::lines(vec!["pub struct B;".to_owned()])],
+                })),
+                Span::lines(vec![
+                    "#[derive(Debug, Clone)]".to_owned(),
+                    "struct A;".to_owned(),
+                    "impl A {".to_owned(),
+                    "    #[cfg(unix)]".to_owned(),
+                    "    fn unix() {}".to_owned(),
+                    "    fn a() {}".to_owned(),
+                    "}".to_owned(),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_test_only_after_cfg() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#[cfg(any(feature = \"x\", test))]\n",
+                    "fn t() {}\n",
+                    "#[cfg(any(feature = \"x\", test))]\n",
+                    "mod missing;\n",
+                    "impl A {\n",
+                    "    #[cfg(any(feature = \"x\", test))] fn u() {}\n",
+                    "}\n",
+                    "#[cfg(any(feature = \"a\", test))]\n",
+                    "fn kept() {}\n",
+                ),
+            }
+        }
+        let cfg = CfgSet {
+            features: ["a".to_owned()].into_iter().collect(),
+        };
+        // `cfg` を評価したあとにテストでしか有効にならないアイテムとモジュールは、ファイルを探さずに消します。
+        let result = lines_only(bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![Span::lines(vec!["impl A {".to_owned(), "}".to_owned(), "fn kept() {}".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn test_remove_test_items() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "use std::io;\n",
+                    "#[cfg(test)]\n",
+                    "use std::collections::HashMap;\n",
+                    "#[cfg(test)]\n",
+                    "#[derive(Debug)]\n",
+                    "struct OnlyForTest { a: i32 }\n",
+                    "#[cfg(test)] enum E {}\n",
+                    "#[cfg(test)]\n",
+                    "macro_rules! m { () => {}; }\n",
+                    "#[test]\n",
+                    "fn stray_test() {}\n",
+                    "struct A;\n",
+                    "impl A {\n",
+                    "    #[cfg(test)]\n",
+                    "    fn helper() {}\n",
+                    "    fn new() -> Self { A }\n",
+                    "}\n",
+                    "trait T {\n",
+                    "    #[cfg(test)]\n",
+                    "    const X: usize = 0;\n",
+                    "}\n",
+                    "#[cfg(test)]\n",
+                    "impl T for A {}\n",
+                    "fn f() {}\n",
+                ),
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![Span::lines(vec![
+                "use std::io;".to_owned(),
+                "struct A;".to_owned(),
+                "impl A {".to_owned(),
+                "    fn new() -> Self { A }".to_owned(),
+                "}".to_owned(),
+                "trait T {".to_owned(),
+                "}".to_owned(),
+                "fn f() {}".to_owned(),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_test_module_attributes() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#[cfg(test)]\n",
+                    "#[allow(dead_code)]\n",
+                    "mod tests {\n",
+                    "    fn f() {}\n",
+                    "}\n",
+                    "#[cfg(test)] mod external;\n",
+                    "#[cfg(all(test, feature = \"x\"))]\n",
+                    "mod compound {}\n",
+                    "#[cfg(feature = \"x\")] #[cfg(test)] fn helper() {}\n",
+                    "#[cfg(any(test, feature = \"x\"))]\n",
+                    "mod kept {}\n",
+                ),
+            }
+        }
+        let cfg = CfgSet {
+            features: ["x".to_owned()].into_iter().collect(),
+        };
+        let result = lines_only(bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap());
+        assert_eq!(
+            result.root.spans,
+            vec![
+                Span::Module(Box::new(Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("./kept"),
+                    spans: vec![],
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modules_in_macros() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "cfg_if::cfg_if! {\n",
+                    "    if #[cfg(unix)] {\n",
+                    "        mod imp;\n",
+                    "    } else {\n",
+                    "        #[path = \"other.rs\"]\n",
+                    "        mod fallback;\n",
+                    "    }\n",
+                    "}\n",
+                    "modules! {\n",
+                    "    mod listed;\n",
+                    "    #[cfg(test)]\n",
+                    "    mod tests;\n",
+                    "}\n",
+                ),
+                "./imp" => "pub fn f() {\n    0\n}\n",
+                "other.rs" => "mod inner {\n    fn g() {}\n}\n",
+                "./listed" => "pub struct L;\n",
+                "./tests" => "fn t() {}\n",
+            }
+        }
+        let result = lines_only(bundle_crate("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), &FeatureRequest::default(), &BundleOptions::default()).unwrap());
+        // マクロは残し、中のファイルモジュールを宣言の位置に展開します。
+        assert_eq!(
+            result.root.spans,
+            vec![Span::lines(
+                [
+                    "cfg_if::cfg_if! {",
+                    "    if #[cfg(unix)] {",
+                    "        mod imp {",
+                    "            pub fn f() {",
+                    "                0",
+                    "            }",
+                    "        }",
+                    "    } else {",
+                    "        mod fallback {",
+                    "            mod inner {",
+                    "                fn g() {}",
+                    "            }",
+                    "        }",
+                    "    }",
+                    "}",
+                    "modules! {",
+                    "    mod listed {",
+                    "        pub struct L;",
+                    "    }",
+                    "}",
+                ]
+                .iter()
+                .map(|line| line.to_string())
+                .collect()
+            )]
+        );
+        let Span::Lines(_, origins, _) = &result.root.spans[0] else {
+            unreachable!();
+        };
+        let origin = |k: usize| {
+            let origin = origins.get(k).unwrap();
+            (origin.path.to_str().unwrap(), origin.line)
+        };
+        assert_eq!(origin(2), ("lib.rs", 3));
+        assert_eq!(origin(3), ("imp.rs", 1));
+        assert_eq!(origin(8), ("lib.rs", 6));
+        assert_eq!(origin(10), ("other.rs", 2));
+    }
+
+    #[test]
+    fn test_cfg_if_with_cfg() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "cfg_if! {\n",
+                    "    if #[cfg(feature = \"a\")] {\n",
+                    "        mod imp;\n",
+                    "        pub use imp::f;\n",
+                    "    } else {\n",
+                    "        mod missing;\n",
+                    "    }\n",
+                    "}\n",
+                    "cfg_if! {\n",
+                    "    if #[cfg(feature = \"b\")] {\n",
+                    "        mod also_missing;\n",
+                    "    }\n",
+                    "}\n",
+                    "cfg_if! {\n",
+                    "    if #[cfg(unix)] {\n",
+                    "        mod unix;\n",
+                    "    }\n",
+                    "}\n",
+                ),
+                "./imp" => "pub fn f() {}\n",
+                "./unix" => "pub fn u() {}\n",
+            }
+        }
+        let cfg = CfgSet {
+            features: ["a".to_owned()].into_iter().collect(),
+        };
+        let result = lines_only(bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap());
+        // 選ばれた分岐はマクロの外に展開し、どの分岐も選ばれなければマクロごと消します。
+        // 分岐の定まらないマクロは、そのまま残します。
+        assert_eq!(
+            result.root.spans,
+            vec![
+                Span::Module(Box::new(Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("./imp"),
+                    spans: vec![Span::lines(vec!["pub fn f() {}".to_owned()])],
+                })),
+                Span::lines(vec![
+                    "pub use imp::f;".to_owned(),
+                    "cfg_if! {".to_owned(),
+                    "    if #[cfg(unix)] {".to_owned(),
+                    "        mod unix {".to_owned(),
+                    "            pub fn u() {}".to_owned(),
+                    "        }".to_owned(),
+                    "    }".to_owned(),
+                    "}".to_owned(),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crate_level_inner_attributes() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "#![no_std]\n",
+                    "#![warn(missing_docs)]\n",
+                    "#![cfg_attr(not(test), no_main)]\n",
+                    "#[allow(unused)]\n",
+                    "mod disabled;\n",
+                    "mod enabled;\n",
+                    "mod testing;\n",
+                    "mod inline {\n",
+                    "    #![cfg(feature = \"c\")]\n",
+                    "}\n",
+                ),
+                "./disabled" => "#![cfg(feature = \"c\")]\npub fn f() {}\n",
+                "./enabled" => "#![cfg(feature = \"a\")]\n#![allow(unused)]\npub fn g() {}\n",
+                "./testing" => "#![cfg(test)]\npub fn h() {}\n",
+            }
+        }
+        let cfg = CfgSet {
+            features: ["a".to_owned()].into_iter().collect(),
+        };
+        let result = bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap();
+        // クレートのルートにしかつけられない属性は消して報告し、`#![cfg(...)]` は外部属性と同じように評価します。
+        let dropped = result.info.dropped_attrs.iter().map(|dropped| (dropped.attr.as_str(), dropped.origin.line)).collect::<Vec<_>>();
+        assert_eq!(dropped, vec![("#![no_std]", 1), ("#![cfg_attr(not(test), no_main)]", 3)]);
+        assert_eq!(
+            lines_only(result).root.spans,
+            vec![
+                Span::lines(vec!["#![warn(missing_docs)]".to_owned()]),
+                Span::Module(Box::new(Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("./enabled"),
+                    spans: vec![Span::lines(vec!["#![allow(unused)]".to_owned(), "pub fn g() {}".to_owned()])],
+                })),
+                Span::Module(Box::new(Module {
+                    is_test: true,
+                    vis: None,
+                    path: PathBuf::from("./testing"),
+                    spans: vec![Span::lines(vec!["#![cfg(test)]".to_owned(), "pub fn h() {}".to_owned()])],
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_item_spans() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => concat!(
+                    "// leading comment\n",
+                    "use std::io;\n",
+                    "#[cfg(feature = \"a\")]\n",
+                    "pub fn f() {\n",
+                    "    g()\n",
+                    "}\n",
+                    "#[derive(Debug)]\n",
+                    "pub(crate) struct A; // trailing comment\n",
+                    "impl A {}\n",
+                    "macro_rules! m {\n",
+                    "    () => {};\n",
+                    "}\n",
+                    "const N: usize = 1; static S: u32 = 0;\n",
+                    "#[allow(dead_code)]\n",
+                    "mod a {}\n",
+                ),
+            }
+        }
+        let cfg = CfgSet {
+            features: ["a".to_owned()].into_iter().collect(),
+        };
+        let result = bundle_crate_with_cfg("my_crate", ManualResolver {}, ConfigToml::new("").unwrap(), cfg, &BundleOptions::default()).unwrap();
+        let item = |kind, name: Option<&str>, vis: Option<&str>, attrs: &[&str], lines: &[&str]| {
+            Span::Item(Box::new(ItemSpan {
+                kind,
+                name: name.map(str::to_owned),
+                vis: vis.map(str::to_owned),
+                attrs: attrs.iter().map(|attr| attr.to_string()).collect(),
+                lines: lines.iter().map(|line| line.to_string()).collect(),
+                origins: Origins::default(),
+                literal_lines: LiteralLines::default(),
+            }))
+        };
+        assert_eq!(
+            result.root.spans,
+            vec![
+                item(ItemSpanKind::Comment, None, None, &[], &["// leading comment"]),
+                item(ItemSpanKind::Use, None, None, &[], &["use std::io;"]),
+                // 消えた属性は、属性にも行にも残りません。
+                item(ItemSpanKind::Fn, Some("f"), Some("pub"), &[], &["pub fn f() {", "    g()", "}"]),
+                item(
+                    ItemSpanKind::Struct,
+                    Some("A"),
+                    Some("pub(crate)"),
+                    &["#[derive(Debug)]"],
+                    &["#[derive(Debug)]", "pub(crate) struct A; // trailing comment"],
+                ),
+                item(ItemSpanKind::Impl, None, None, &[], &["impl A {}"]),
+                item(ItemSpanKind::MacroRules, Some("m"), None, &[], &["macro_rules! m {", "    () => {};", "}"]),
+                // 一行に並んだアイテムは分けません。
+                Span::lines(vec!["const N: usize = 1; static S: u32 = 0;".to_owned()]),
+                item(ItemSpanKind::Attrs, None, None, &["#[allow(dead_code)]"], &["#[allow(dead_code)]"]),
+                Span::Module(Box::new(Module::new(PathBuf::from("./a")))),
+            ]
+        );
+        let Span::Item(f) = &result.root.spans[2] else {
+            unreachable!();
+        };
+        assert_eq!(f.origins.0.iter().map(|origin| origin.line).collect::<Vec<_>>(), vec![4, 5, 6]);
+    }
 }
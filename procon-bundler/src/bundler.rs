@@ -0,0 +1,246 @@
+use {
+    crate::{
+        bundle_crate::{bundle_crate, bundle_crates_with_deps},
+        expand::expand_solution_with_buffers,
+        features::FeatureRequest,
+        options::BundleOptions,
+        prettify::format_crates_with_source_map,
+        resolver::{OverlayResolver, Resolve},
+        shake::shake_crates,
+        source_map::SourceMap,
+        workspace::find_crate,
+        ConfigToml, Crate, DroppedAttr, Result,
+    },
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    },
+};
+
+// クレートを、オンラインジャッジに提出できるひとつのファイルにまとめるものです。
+//
+// 設定はビルダー形式で与え、`bundle`, `find`, `expand` のそれぞれが `Bundle` を返します。
+//
+// ```
+// let bundle = Bundler::new()
+//     .options(BundleOptions { tab_width: 2, ..BundleOptions::default() })
+//     .keep(["seg_tree::SegTree"])
+//     .find(Path::new("path/to/workspace"), "seg-tree")?;
+// println!("{}", bundle.code);
+// ```
+#[derive(Clone, Debug, Default)]
+pub struct Bundler {
+    options: BundleOptions,
+    request: FeatureRequest,
+    no_deps: bool,
+    keep: Vec<String>,
+    shake: bool,
+    buffers: BTreeMap<PathBuf, String>,
+}
+
+// バンドルしたコードと、その各行の元の位置です。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    // バンドルしたコード（末尾の改行はつけません。）
+    pub code: String,
+    // `code` の各行の元の位置
+    pub source_map: SourceMap,
+    // モジュールにはつけられないために消した、クレートの内部属性（`#![no_std]` など）
+    pub dropped_attrs: Vec<DroppedAttr>,
+}
+
+impl Bundler {
+    // 既定の設定で作ります。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // バンドルと出力の設定です。
+    pub fn options(mut self, options: BundleOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // バンドルするクレート（`expand` では解答のクレート）の feature の指定です。
+    pub fn features(mut self, request: FeatureRequest) -> Self {
+        self.request = request;
+        self
+    }
+
+    // パス依存もバンドルするかどうか（既定は `true`）です。`expand` は常にバンドルします。
+    pub fn deps(mut self, deps: bool) -> Self {
+        self.no_deps = !deps;
+        self
+    }
+
+    // これらのアイテムのパス（`seg_tree::SegTree` など）や解答のソースから到達可能なアイテムだけを残します。
+    // 空（既定）ならば何も消しません。
+    pub fn keep<S: Into<String>>(mut self, roots: impl IntoIterator<Item = S>) -> Self {
+        self.keep = roots.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // `expand` で、解答から到達可能なアイテムだけを残すかどうか（既定は `false`）です。
+    pub fn shake(mut self, shake: bool) -> Self {
+        self.shake = shake;
+        self
+    }
+
+    // ディスク上の `path` のファイルの代わりに `content` を読みます（エディタで保存していないバッファなど）。
+    // バンドルするクレートや解答のどのソースファイルでもよく、まだディスク上になくても構いません。
+    pub fn buffer(mut self, path: impl Into<PathBuf>, content: impl Into<String>) -> Self {
+        self.buffers.insert(path.into(), content.into());
+        self
+    }
+
+    // `crate_root` のクレートを `crate_name` という名前でバンドルします。
+    pub fn bundle(&self, crate_name: &str, crate_root: &Path) -> Result<Bundle> {
+        if self.no_deps {
+            let resolver = OverlayResolver::with_buffers(crate_root.to_path_buf(), &self.buffers);
+            return self.bundle_with_config(crate_name, resolver, ConfigToml::from_crate_root(crate_root)?);
+        }
+        let roots = [(crate_name, crate_root, self.request.clone(), None)];
+        let crates = bundle_crates_with_deps(roots, &self.options, &self.buffers)?;
+        Ok(self.format(crates))
+    }
+
+    // ファイルを `resolver` を通して読むクレートをバンドルします。パス依存はバンドルしません。
+    // Cargo.toml は `Resolve::root_dir` から読み、それがなければ Cargo.toml が空であるものとします。
+    pub fn bundle_with_resolver<R: Resolve>(&self, crate_name: &str, resolver: R) -> Result<Bundle> {
+        let config = match resolver.root_dir() {
+            Some(root_dir) => ConfigToml::from_crate_root(root_dir)?,
+            None => ConfigToml::new("")?,
+        };
+        self.bundle_with_config(crate_name, resolver, config)
+    }
+
+    fn bundle_with_config<R: Resolve>(&self, crate_name: &str, resolver: R, config: ConfigToml) -> Result<Bundle> {
+        let my_crate = bundle_crate(crate_name, resolver, config, &self.request, &self.options)?;
+        Ok(self.format(vec![my_crate]))
+    }
+
+    // ワークスペースから `crate_name`（ハイフンつなぎでもアンダースコアつなぎでも可）のメンバーを探してバンドルします。
+    pub fn find(&self, workspace_root: &Path, crate_name: &str) -> Result<Bundle> {
+        let member = find_crate(workspace_root, crate_name)?;
+        self.bundle(&member.name, &member.root)
+    }
+
+    // 解答ファイルの後ろに、解答が使うライブラリクレートを貼り付けて展開します。
+    // ライブラリは `manifest_dir` の Cargo.toml のパス依存で、`manifest_dir` の既定は
+    // 解答の祖先のうち Cargo.toml を含む最も近いディレクトリです。
+    pub fn expand(&self, solution_path: &Path, manifest_dir: Option<&Path>) -> Result<Bundle> {
+        expand_solution_with_buffers(
+            solution_path,
+            manifest_dir,
+            &self.request,
+            self.shake,
+            &self.options,
+            &self.buffers,
+        )
+    }
+
+    fn format(&self, crates: Vec<Crate>) -> Bundle {
+        let crates = if self.keep.is_empty() {
+            crates
+        } else {
+            shake_crates(crates, &self.keep.iter().map(String::as_str).collect::<Vec<_>>())
+        };
+        let dropped_attrs = crates.iter().flat_map(|krate| krate.info.dropped_attrs.clone()).collect();
+        let (code, source_map) = format_crates_with_source_map(crates, &self.options);
+        Bundle {
+            code,
+            source_map,
+            dropped_attrs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Bundler,
+        crate::{discover_options, manual_resolver, normalize_path},
+        difference::assert_diff,
+        std::path::Path,
+    };
+
+    #[test]
+    fn test_bundle_by_crate_path() {
+        let result = Bundler::new()
+            .deps(false)
+            .bundle("procon-bundler-sample", Path::new("../procon-bundler-sample"))
+            .unwrap()
+            .code;
+        let expected = include_str!("../../procon-bundler-sample-result/src/lib.rs");
+        let result = result.as_ref();
+        let expected = expected[..expected.len() - 1].as_ref();
+        assert_diff!(result, expected, "\n", 0);
+    }
+
+    #[test]
+    fn test_bundle_with_deps_without_deps_is_unchanged() {
+        let path = Path::new("../procon-bundler-sample");
+        let result = Bundler::new().bundle("procon-bundler-sample", path).unwrap();
+        let expected = Bundler::new().deps(false).bundle("procon-bundler-sample", path).unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_bundle_with_discovered_options() {
+        let path = Path::new("../testcase/options/workspace/crates/inner");
+        let options = discover_options(path).unwrap().into_options();
+        let result = Bundler::new().options(options).deps(false).bundle("inner", path).unwrap().code;
+        let expected = concat!(
+            "// inner from procon-bundler.toml\n",
+            "#[allow(dead_code)]\n",
+            "mod inner {\n",
+            "  //! Two-space indented crate.\n",
+            "  /// A module.\n",
+            "  pub mod a {\n",
+            "    /// A function.\n",
+            "    pub fn f() -> u32 {\n",
+            "      42\n",
+            "    }\n",
+            "  }\n",
+            "}\n",
+            concat!("// }", "}}"),
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_bundle_with_resolver_and_keep() {
+        manual_resolver! {
+            struct ManualResolver {
+                "." => "pub fn used() {}\npub fn unused() {}\n",
+            }
+        }
+        let bundle = Bundler::new()
+            .keep(["my_crate::used"])
+            .bundle_with_resolver("my_crate", ManualResolver {})
+            .unwrap();
+        assert!(bundle.code.contains("pub fn used() {}"));
+        assert!(!bundle.code.contains("fn unused"));
+        assert_eq!(bundle.source_map.lines.len(), bundle.code.lines().count());
+    }
+
+    #[test]
+    fn test_buffers() {
+        let leaf = Path::new("../testcase/deps/leaf/src/lib.rs");
+        let bundle = Bundler::new()
+            .buffer(leaf, "pub struct Leaf(pub u32);\n")
+            .bundle("root", Path::new("../testcase/deps/root"))
+            .unwrap();
+        assert!(bundle.code.contains("    pub struct Leaf(pub u32);\n"));
+        let line = bundle.code.lines().position(|line| line.contains("Leaf(pub u32)")).unwrap() + 1;
+        let origin = bundle.source_map.locate(line).unwrap().unwrap();
+        assert_eq!((normalize_path(&origin.path), origin.line), (leaf.to_path_buf(), 1));
+
+        let solution = Path::new("../testcase/expand/src/main.rs");
+        let bundle = Bundler::new()
+            .buffer(solution, "use leaf::Leaf;\nfn main() {}\n")
+            .expand(solution, None)
+            .unwrap();
+        assert!(bundle.code.starts_with("use crate::leaf::Leaf;\nfn main() {}\n"));
+    }
+}
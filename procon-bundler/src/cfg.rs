@@ -0,0 +1,646 @@
+use {
+    crate::{
+        lexer::{Delim, TokenKind},
+        parse_item::Tokens,
+    },
+    std::{
+        collections::BTreeSet,
+        fmt::{self, Display, Formatter},
+        ops::Range,
+    },
+};
+
+// cfg の評価に使う、値のわかっている設定です。
+// ここにない設定（`test` や `target_os` など）は評価せずにそのまま残します。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CfgSet {
+    // 有効な feature の集合（ここにない feature は無効として評価します。）
+    pub features: BTreeSet<String>,
+}
+
+// `cfg(...)` の中身です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum CfgExpr {
+    Name(String),
+    KeyValue(String, String),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+// 部分評価の結果です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Simplified {
+    True,
+    False,
+    // 値のわからない設定が残ったもの
+    Residual(CfgExpr),
+}
+
+impl CfgExpr {
+    // トークン範囲（括弧の内側）をパースします。
+    pub fn parse(tokens: &Tokens, range: Range<usize>) -> Option<Self> {
+        let significant = range
+            .filter(|&i| !tokens.tokens[i].is_comment())
+            .collect::<Vec<_>>();
+        let (expr, rest) = parse_expr(tokens, &significant)?;
+        rest.is_empty().then_some(expr)
+    }
+    pub fn simplify(&self, cfg: &CfgSet) -> Simplified {
+        match self {
+            CfgExpr::KeyValue(key, value) if key == "feature" => {
+                if cfg.features.contains(value) {
+                    Simplified::True
+                } else {
+                    Simplified::False
+                }
+            }
+            CfgExpr::Name(_) | CfgExpr::KeyValue(..) => Simplified::Residual(self.clone()),
+            CfgExpr::All(exprs) => {
+                let mut residuals = Vec::new();
+                for expr in exprs {
+                    match expr.simplify(cfg) {
+                        Simplified::True => {}
+                        Simplified::False => return Simplified::False,
+                        Simplified::Residual(expr) => residuals.push(expr),
+                    }
+                }
+                match residuals.len() {
+                    0 => Simplified::True,
+                    1 => Simplified::Residual(residuals.pop().unwrap()),
+                    _ => Simplified::Residual(CfgExpr::All(residuals)),
+                }
+            }
+            CfgExpr::Any(exprs) => {
+                let mut residuals = Vec::new();
+                for expr in exprs {
+                    match expr.simplify(cfg) {
+                        Simplified::True => return Simplified::True,
+                        Simplified::False => {}
+                        Simplified::Residual(expr) => residuals.push(expr),
+                    }
+                }
+                match residuals.len() {
+                    0 => Simplified::False,
+                    1 => Simplified::Residual(residuals.pop().unwrap()),
+                    _ => Simplified::Residual(CfgExpr::Any(residuals)),
+                }
+            }
+            CfgExpr::Not(expr) => match expr.simplify(cfg) {
+                Simplified::True => Simplified::False,
+                Simplified::False => Simplified::True,
+                Simplified::Residual(expr) => Simplified::Residual(CfgExpr::Not(Box::new(expr))),
+            },
+        }
+    }
+    // `test` が偽のとき、つまりテスト以外でビルドしたときに、つねに偽になるかを判定します。
+    // `test` 以外の設定は、値がわからないものとして扱います。
+    pub fn requires_test(&self) -> bool {
+        self.eval_without_test() == Some(false)
+    }
+    // `test` を偽としたときの値です。値が定まらなければ `None` を返します。
+    fn eval_without_test(&self) -> Option<bool> {
+        match self {
+            CfgExpr::Name(name) if name == "test" => Some(false),
+            CfgExpr::Name(_) | CfgExpr::KeyValue(..) => None,
+            CfgExpr::All(exprs) => {
+                let values = exprs.iter().map(CfgExpr::eval_without_test).collect::<Vec<_>>();
+                if values.contains(&Some(false)) {
+                    Some(false)
+                } else {
+                    values.iter().all(|&value| value == Some(true)).then_some(true)
+                }
+            }
+            CfgExpr::Any(exprs) => {
+                let values = exprs.iter().map(CfgExpr::eval_without_test).collect::<Vec<_>>();
+                if values.contains(&Some(true)) {
+                    Some(true)
+                } else {
+                    values.iter().all(|&value| value == Some(false)).then_some(false)
+                }
+            }
+            CfgExpr::Not(expr) => expr.eval_without_test().map(|value| !value),
+        }
+    }
+}
+
+fn parse_expr<'a>(tokens: &Tokens, significant: &'a [usize]) -> Option<(CfgExpr, &'a [usize])> {
+    let (&name, rest) = significant.split_first()?;
+    if tokens.tokens[name].kind != TokenKind::Ident {
+        return None;
+    }
+    let name = tokens.text(name).to_owned();
+    match rest {
+        [eq, value, rest @ ..]
+            if tokens.tokens[*eq].kind == TokenKind::Punct('=')
+                && tokens.tokens[*value].kind == TokenKind::Literal =>
+        {
+            let value = tokens.text(*value);
+            let value = value.strip_prefix('"')?.strip_suffix('"')?.to_owned();
+            Some((CfgExpr::KeyValue(name, value), rest))
+        }
+        [open, rest @ ..] if tokens.tokens[*open].kind == TokenKind::Open(Delim::Paren) => {
+            let mut rest = rest;
+            let mut exprs = Vec::new();
+            loop {
+                match rest {
+                    [close, after @ ..] if tokens.tokens[*close].kind == TokenKind::Close(Delim::Paren) => {
+                        rest = after;
+                        break;
+                    }
+                    _ => {
+                        let (expr, after) = parse_expr(tokens, rest)?;
+                        exprs.push(expr);
+                        rest = match after {
+                            [comma, after @ ..] if tokens.tokens[*comma].kind == TokenKind::Punct(',') => after,
+                            _ => after,
+                        };
+                    }
+                }
+            }
+            let expr = match name.as_str() {
+                "all" => CfgExpr::All(exprs),
+                "any" => CfgExpr::Any(exprs),
+                "not" if exprs.len() == 1 => CfgExpr::Not(Box::new(exprs.pop().unwrap())),
+                _ => return None,
+            };
+            Some((expr, rest))
+        }
+        _ => Some((CfgExpr::Name(name), rest)),
+    }
+}
+
+impl Display for CfgExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut Formatter<'_>, name: &str, exprs: &[CfgExpr]) -> fmt::Result {
+            write!(f, "{}(", name)?;
+            for (i, expr) in exprs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", expr)?;
+            }
+            write!(f, ")")
+        }
+        match self {
+            CfgExpr::Name(name) => write!(f, "{}", name),
+            CfgExpr::KeyValue(key, value) => write!(f, "{} = {:?}", key, value),
+            CfgExpr::All(exprs) => list(f, "all", exprs),
+            CfgExpr::Any(exprs) => list(f, "any", exprs),
+            CfgExpr::Not(expr) => write!(f, "not({})", expr),
+        }
+    }
+}
+
+// 属性を評価した結果です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum AttrEval {
+    // そのまま残します。
+    Keep,
+    // 属性を消します。
+    Remove,
+    // 属性を書き換えます。
+    Replace(String),
+    // 属性のついたアイテムごと消します。
+    DropItem,
+}
+
+// `#[cfg(...)]` と `#[cfg_attr(...)]` を、わかっている設定で評価します。
+pub fn eval_attr(tokens: &Tokens, attr: Range<usize>, cfg: &CfgSet) -> AttrEval {
+    let Some((name, args)) = attr_call(tokens, attr) else {
+        return AttrEval::Keep;
+    };
+    match name {
+        "cfg" => {
+            let Some(expr) = CfgExpr::parse(tokens, args) else {
+                return AttrEval::Keep;
+            };
+            match expr.simplify(cfg) {
+                Simplified::True => AttrEval::Remove,
+                Simplified::False => AttrEval::DropItem,
+                Simplified::Residual(residual) if residual == expr => AttrEval::Keep,
+                Simplified::Residual(residual) => AttrEval::Replace(format!("#[cfg({})]", residual)),
+            }
+        }
+        "cfg_attr" => {
+            let parts = split_commas(tokens, args);
+            let Some((pred, attrs)) = parts.split_first() else {
+                return AttrEval::Keep;
+            };
+            let Some(expr) = CfgExpr::parse(tokens, pred.clone()) else {
+                return AttrEval::Keep;
+            };
+            let attrs = attrs
+                .iter()
+                .map(|part| &tokens.src[tokens.span(part.clone())])
+                .collect::<Vec<_>>();
+            match expr.simplify(cfg) {
+                Simplified::True if attrs.is_empty() => AttrEval::Remove,
+                Simplified::True => AttrEval::Replace(
+                    attrs
+                        .iter()
+                        .map(|attr| format!("#[{}]", attr))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                Simplified::False => AttrEval::Remove,
+                Simplified::Residual(residual) if residual == expr => AttrEval::Keep,
+                Simplified::Residual(residual) => {
+                    AttrEval::Replace(format!("#[cfg_attr({}, {})]", residual, attrs.join(", ")))
+                }
+            }
+        }
+        _ => AttrEval::Keep,
+    }
+}
+
+// 属性が `#[cfg(...)]` で、その条件がテストのときにしか成り立たないかを判定します。
+// `#[cfg(test)]` のほか、`#[cfg(all(test, feature = "x"))]` なども含みます。
+pub fn is_cfg_test(tokens: &Tokens, attr: Range<usize>) -> bool {
+    match attr_call(tokens, attr) {
+        Some(("cfg", args)) => CfgExpr::parse(tokens, args).is_some_and(|expr| expr.requires_test()),
+        _ => false,
+    }
+}
+
+// クレートのルートにしかつけられない属性の名前です。
+const CRATE_ONLY_ATTRS: &[&str] = &[
+    "no_std",
+    "no_core",
+    "no_main",
+    "no_builtins",
+    "feature",
+    "crate_name",
+    "crate_type",
+    "recursion_limit",
+    "type_length_limit",
+    "windows_subsystem",
+    "compiler_builtins",
+    "needs_allocator",
+    "needs_panic_runtime",
+    "panic_runtime",
+    "profiler_runtime",
+    "register_tool",
+];
+
+// `#![doc(...)]` のうち、クレートのルートにしかつけられないものの名前です。
+const CRATE_ONLY_DOC_ATTRS: &[&str] = &[
+    "html_root_url",
+    "html_logo_url",
+    "html_favicon_url",
+    "html_playground_url",
+    "html_no_source",
+    "issue_tracker_base_url",
+    "test",
+];
+
+// 内部属性を評価した結果です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum InnerAttrEval {
+    // そのまま残します。
+    Keep,
+    // 属性を消します。
+    Remove,
+    // 属性を書き換えます。
+    Replace(String),
+    // クレートのルートにしかつけられない属性を含むので、それを除いて書き換えます（何も残らなければ `None`）。
+    CrateOnly(Option<String>),
+    // モジュールをテスト専用にします。
+    TestOnly,
+    // モジュールごと消します。
+    DropModule,
+}
+
+// クレートをモジュールにしたときの内部属性 `#![...]` を評価します。`cfg` が `None` ならば `cfg` は評価しません。
+//
+// * `no_std`, `feature` などクレートのルートにしかつけられない属性は、`cfg_attr` の中のものも含めて消します。
+// * `#![cfg(...)]` は、モジュールにつけた `#[cfg(...)]` と同じように評価します。
+// * lint の属性や `#![doc = "..."]` などは、モジュールにもつけられるのでそのまま残します。
+pub fn eval_inner_attr(tokens: &Tokens, attr: Range<usize>, cfg: Option<&CfgSet>) -> InnerAttrEval {
+    if attr_content(tokens, attr.clone()).is_some_and(|content| is_crate_only(tokens, content)) {
+        return InnerAttrEval::CrateOnly(None);
+    }
+    let Some((name, args)) = attr_call(tokens, attr) else {
+        return InnerAttrEval::Keep;
+    };
+    match name {
+        "cfg" => {
+            let Some(expr) = CfgExpr::parse(tokens, args) else {
+                return InnerAttrEval::Keep;
+            };
+            if expr.requires_test() {
+                return InnerAttrEval::TestOnly;
+            }
+            match cfg.map(|cfg| expr.simplify(cfg)) {
+                Some(Simplified::True) => InnerAttrEval::Remove,
+                Some(Simplified::False) => InnerAttrEval::DropModule,
+                Some(Simplified::Residual(residual)) if residual != expr => {
+                    InnerAttrEval::Replace(format!("#![cfg({})]", residual))
+                }
+                _ => InnerAttrEval::Keep,
+            }
+        }
+        "cfg_attr" => {
+            let parts = split_commas(tokens, args);
+            let Some((pred, attrs)) = parts.split_first() else {
+                return InnerAttrEval::Keep;
+            };
+            let Some(expr) = CfgExpr::parse(tokens, pred.clone()) else {
+                return InnerAttrEval::Keep;
+            };
+            let (crate_only, attrs) = attrs
+                .iter()
+                .partition::<Vec<_>, _>(|part| is_crate_only(tokens, (*part).clone()));
+            let attrs = attrs
+                .iter()
+                .map(|part| &tokens.src[tokens.span((*part).clone())])
+                .collect::<Vec<_>>();
+            let simplified = match cfg {
+                Some(cfg) => expr.simplify(cfg),
+                None => Simplified::Residual(expr.clone()),
+            };
+            let rewritten = match &simplified {
+                _ if attrs.is_empty() => None,
+                Simplified::True => Some(
+                    attrs
+                        .iter()
+                        .map(|attr| format!("#![{}]", attr))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                Simplified::False => None,
+                Simplified::Residual(residual) => Some(format!("#![cfg_attr({}, {})]", residual, attrs.join(", "))),
+            };
+            match (crate_only.is_empty(), simplified, rewritten) {
+                (false, _, rewritten) => InnerAttrEval::CrateOnly(rewritten),
+                (true, Simplified::Residual(residual), _) if residual == expr => InnerAttrEval::Keep,
+                (true, _, Some(text)) => InnerAttrEval::Replace(text),
+                (true, _, None) => InnerAttrEval::Remove,
+            }
+        }
+        _ => InnerAttrEval::Keep,
+    }
+}
+
+// 属性の中身（`no_std` や `feature(...)` など）が、クレートのルートにしかつけられないものかを判定します。
+fn is_crate_only(tokens: &Tokens, content: Range<usize>) -> bool {
+    let significant = content
+        .filter(|&i| !tokens.tokens[i].is_comment())
+        .collect::<Vec<_>>();
+    match significant[..] {
+        [name, ..] if CRATE_ONLY_ATTRS.contains(&tokens.text(name)) => true,
+        [name, open, arg, ..]
+            if tokens.text(name) == "doc" && tokens.tokens[open].kind == TokenKind::Open(Delim::Paren) =>
+        {
+            CRATE_ONLY_DOC_ATTRS.contains(&tokens.text(arg))
+        }
+        _ => false,
+    }
+}
+
+// 属性が `#[cfg(...)]` で、わかっている設定で評価したあとに残る条件が、テストのときにしか成り立たないかを判定します。
+// たとえば `#[cfg(any(feature = "x", test))]` は、feature `x` が無効ならばテスト専用です。
+pub fn is_cfg_test_with(tokens: &Tokens, attr: Range<usize>, cfg: &CfgSet) -> bool {
+    match attr_call(tokens, attr) {
+        Some(("cfg", args)) => CfgExpr::parse(tokens, args).is_some_and(|expr| match expr.simplify(cfg) {
+            Simplified::Residual(residual) => residual.requires_test(),
+            Simplified::True | Simplified::False => false,
+        }),
+        _ => false,
+    }
+}
+
+// `cfg_if!` の分岐です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct CfgIfBranch {
+    // 条件（`else` ならば `None`）
+    pub cond: Option<CfgExpr>,
+    // 中括弧の内側のトークン範囲
+    pub body: Range<usize>,
+}
+
+// `cfg_if!` の中身（中括弧の内側のトークン範囲）を、分岐に分けます。
+//
+// `if #[cfg(...)] { ... } else if #[cfg(...)] { ... } else { ... }` の形でなければ `None` を返します。
+pub fn parse_cfg_if(tokens: &Tokens, range: Range<usize>) -> Option<Vec<CfgIfBranch>> {
+    let significant = range
+        .filter(|&i| !tokens.tokens[i].is_comment())
+        .collect::<Vec<_>>();
+    let is_ident = |i: usize, ident: &str| tokens.tokens[i].kind == TokenKind::Ident && tokens.text(i) == ident;
+    let is_open = |i: usize, delim: Delim| tokens.tokens[i].kind == TokenKind::Open(delim);
+    // 閉じ括弧の次の、意味のあるトークンの位置です。
+    let after = |k: usize| significant.partition_point(|&i| i <= tokens.matching(significant[k]));
+
+    let mut branches = Vec::<CfgIfBranch>::new();
+    let mut k = 0;
+    loop {
+        let cond = match significant[k..] {
+            [if_, pound, open, ..]
+                if is_ident(if_, "if")
+                    && tokens.tokens[pound].kind == TokenKind::Punct('#')
+                    && is_open(open, Delim::Bracket) =>
+            {
+                let (name, args) = attr_call(tokens, pound..tokens.matching(open) + 1)?;
+                if name != "cfg" {
+                    return None;
+                }
+                k = after(k + 2);
+                Some(CfgExpr::parse(tokens, args)?)
+            }
+            [_, ..] if !branches.is_empty() => None,
+            _ => return None,
+        };
+        let &open = significant.get(k).filter(|&&open| is_open(open, Delim::Brace))?;
+        let is_else = cond.is_none();
+        branches.push(CfgIfBranch {
+            cond,
+            body: open + 1..tokens.matching(open),
+        });
+        k = after(k);
+        match significant.get(k) {
+            None => return Some(branches),
+            Some(&else_) if !is_else && is_ident(else_, "else") => k += 1,
+            Some(_) => return None,
+        }
+    }
+}
+
+// `cfg_if!` の分岐のうち、わかっている設定で選ばれるものを返します。
+// どの分岐も選ばれなければ `Some(None)` を、選ばれる分岐が定まらなければ `None` を返します。
+// テストでしか有効にならない条件は、偽として評価します。
+pub fn select_cfg_if_branch<'a>(branches: &'a [CfgIfBranch], cfg: &CfgSet) -> Option<Option<&'a CfgIfBranch>> {
+    for branch in branches {
+        let Some(cond) = &branch.cond else {
+            return Some(Some(branch));
+        };
+        if cond.requires_test() {
+            continue;
+        }
+        match cond.simplify(cfg) {
+            Simplified::True => return Some(Some(branch)),
+            Simplified::False => {}
+            Simplified::Residual(_) => return None,
+        }
+    }
+    Some(None)
+}
+
+// `#[name(...)]`（または `#![name(...)]`）の形の属性ならば、名前と括弧の内側のトークン範囲を返します。
+fn attr_call<'a>(tokens: &Tokens<'a>, attr: Range<usize>) -> Option<(&'a str, Range<usize>)> {
+    let mut significant = attr
+        .filter(|&i| !tokens.tokens[i].is_comment())
+        .collect::<Vec<_>>();
+    if significant.len() > 1 && tokens.tokens[significant[1]].kind == TokenKind::Punct('!') {
+        significant.remove(1);
+    }
+    match significant[..] {
+        [_, _, name, open, .., _]
+            if tokens.tokens[name].kind == TokenKind::Ident
+                && tokens.tokens[open].kind == TokenKind::Open(Delim::Paren)
+                && tokens.matching(open) + 2 == attr_end(&significant) =>
+        {
+            Some((tokens.text(name), open + 1..tokens.matching(open)))
+        }
+        _ => None,
+    }
+}
+
+// 属性の角括弧の内側のトークン範囲です。
+fn attr_content(tokens: &Tokens, attr: Range<usize>) -> Option<Range<usize>> {
+    let open = attr.clone().find(|&i| tokens.tokens[i].kind == TokenKind::Open(Delim::Bracket))?;
+    let close = tokens.matching(open);
+    (close < attr.end).then_some(open + 1..close)
+}
+
+fn attr_end(significant: &[usize]) -> usize {
+    significant.last().map_or(0, |&i| i + 1)
+}
+
+// トークン範囲を、深さ 0 のカンマで区切ります。空の部分は捨てます。
+fn split_commas(tokens: &Tokens, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut parts = Vec::new();
+    let mut start = range.start;
+    let mut i = range.start;
+    while i < range.end {
+        match tokens.tokens[i].kind {
+            TokenKind::Punct(',') => {
+                parts.push(start..i);
+                start = i + 1;
+                i += 1;
+            }
+            TokenKind::Open(_) => i = tokens.matching(i) + 1,
+            _ => i += 1,
+        }
+    }
+    parts.push(start..range.end);
+    parts
+        .into_iter()
+        .map(|part| tokens.skip_comments(part.start, part.end)..part.end)
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            eval_attr, eval_inner_attr, is_cfg_test_with, parse_cfg_if, select_cfg_if_branch, AttrEval, CfgExpr, CfgSet, InnerAttrEval,
+            Simplified,
+        },
+        crate::parse_item::Tokens,
+        test_case::test_case,
+    };
+
+    fn cfg_set() -> CfgSet {
+        CfgSet {
+            features: ["a", "b"].iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn simplify(src: &str) -> Simplified {
+        let tokens = Tokens::new(src).unwrap();
+        CfgExpr::parse(&tokens, 0..tokens.len()).unwrap().simplify(&cfg_set())
+    }
+
+    #[test_case(r#"feature = "a""# => Simplified::True; "enabled feature")]
+    #[test_case(r#"feature = "c""# => Simplified::False; "disabled feature")]
+    #[test_case(r#"not(feature = "c")"# => Simplified::True; "not")]
+    #[test_case(r#"all(feature = "a", feature = "c")"# => Simplified::False; "all")]
+    #[test_case(r#"any(feature = "c", feature = "b",)"# => Simplified::True; "any with trailing comma")]
+    #[test_case("all()" => Simplified::True; "empty all")]
+    #[test_case("any()" => Simplified::False; "empty any")]
+    fn test_simplify(src: &str) -> Simplified {
+        simplify(src)
+    }
+
+    #[test_case("test" => "test".to_owned(); "unknown name")]
+    #[test_case(r#"all(feature = "a", target_os = "linux")"# => r#"target_os = "linux""#.to_owned(); "all with unknown")]
+    #[test_case(r#"any(feature = "c", unix, windows)"# => "any(unix, windows)".to_owned(); "any with unknowns")]
+    #[test_case(r#"not(all(test, feature = "a"))"# => "not(test)".to_owned(); "nested")]
+    fn test_residual(src: &str) -> String {
+        match simplify(src) {
+            Simplified::Residual(expr) => expr.to_string(),
+            other => panic!("expected a residual, got {:?}", other),
+        }
+    }
+
+    #[test_case(r#"#[cfg(feature = "a")]"# => AttrEval::Remove; "true cfg")]
+    #[test_case(r#"#[cfg(feature = "c")]"# => AttrEval::DropItem; "false cfg")]
+    #[test_case("#[cfg(test)]" => AttrEval::Keep; "unknown cfg")]
+    #[test_case(r#"#[cfg(all(unix, feature = "a"))]"# => AttrEval::Replace("#[cfg(unix)]".to_owned()); "partially known cfg")]
+    #[test_case(r#"#[cfg_attr(feature = "a", derive(Debug), allow(dead_code))]"# => AttrEval::Replace("#[derive(Debug)] #[allow(dead_code)]".to_owned()); "true cfg_attr")]
+    #[test_case(r#"#[cfg_attr(feature = "c", derive(Debug))]"# => AttrEval::Remove; "false cfg_attr")]
+    #[test_case(r#"#[cfg_attr(any(unix, feature = "c"), derive(Debug))]"# => AttrEval::Replace("#[cfg_attr(unix, derive(Debug))]".to_owned()); "partially known cfg_attr")]
+    #[test_case("#[derive(Debug)]" => AttrEval::Keep; "other attribute")]
+    #[test_case("#[path = \"a.rs\"]" => AttrEval::Keep; "not a call")]
+    fn test_eval_attr(src: &str) -> AttrEval {
+        let tokens = Tokens::new(src).unwrap();
+        eval_attr(&tokens, 0..tokens.len(), &cfg_set())
+    }
+
+    #[test_case("#![no_std]", true => InnerAttrEval::CrateOnly(None); "no_std")]
+    #[test_case("#![feature(test)]", true => InnerAttrEval::CrateOnly(None); "feature")]
+    #[test_case(r#"#![doc(html_root_url = "https://example.com")]"#, true => InnerAttrEval::CrateOnly(None); "crate-level doc")]
+    #[test_case("#![cfg_attr(not(test), no_std)]", true => InnerAttrEval::CrateOnly(None); "cfg_attr")]
+    #[test_case("#![cfg_attr(docsrs, feature(doc_cfg), allow(unused))]", true => InnerAttrEval::CrateOnly(Some("#![cfg_attr(docsrs, allow(unused))]".to_owned())); "cfg_attr with another attribute")]
+    #[test_case(r#"#![cfg_attr(feature = "a", no_std, allow(unused))]"#, true => InnerAttrEval::CrateOnly(Some("#![allow(unused)]".to_owned())); "true cfg_attr")]
+    #[test_case("#![warn(missing_docs)]", true => InnerAttrEval::Keep; "lint")]
+    #[test_case(r#"#![doc = "A module."]"#, true => InnerAttrEval::Keep; "doc")]
+    #[test_case(r#"#![cfg(feature = "a")]"#, true => InnerAttrEval::Remove; "true cfg")]
+    #[test_case(r#"#![cfg(feature = "c")]"#, true => InnerAttrEval::DropModule; "false cfg")]
+    #[test_case(r#"#![cfg(feature = "c")]"#, false => InnerAttrEval::Keep; "cfg without features")]
+    #[test_case(r#"#![cfg(all(unix, feature = "a"))]"#, true => InnerAttrEval::Replace("#![cfg(unix)]".to_owned()); "partially known cfg")]
+    #[test_case("#![cfg(all(test, unix))]", false => InnerAttrEval::TestOnly; "test cfg")]
+    #[test_case(r#"#![cfg_attr(feature = "c", allow(unused))]"#, true => InnerAttrEval::Remove; "false cfg_attr")]
+    fn test_eval_inner_attr(src: &str, with_cfg: bool) -> InnerAttrEval {
+        let tokens = Tokens::new(src).unwrap();
+        let cfg = cfg_set();
+        eval_inner_attr(&tokens, 0..tokens.len(), with_cfg.then_some(&cfg))
+    }
+
+    #[test_case(r#"#[cfg(any(feature = "c", test))]"# => true; "disabled feature or test")]
+    #[test_case(r#"#[cfg(any(feature = "a", test))]"# => false; "enabled feature or test")]
+    #[test_case(r#"#[cfg(all(feature = "a", test))]"# => true; "enabled feature and test")]
+    #[test_case(r#"#[cfg(any(unix, test))]"# => false; "unknown or test")]
+    #[test_case("#[cfg_attr(test, derive(Debug))]" => false; "cfg_attr")]
+    fn test_is_cfg_test_with(src: &str) -> bool {
+        let tokens = Tokens::new(src).unwrap();
+        is_cfg_test_with(&tokens, 0..tokens.len(), &cfg_set())
+    }
+
+    // 選ばれた分岐の中身を返します。どれも選ばれなければ空文字列、定まらなければ `None` です。
+    #[test_case(r#"if #[cfg(feature = "a")] { a } else { b }"# => Some("a".to_owned()); "first branch")]
+    #[test_case(r#"if #[cfg(feature = "c")] { a } else if #[cfg(feature = "b")] { b } else { c }"# => Some("b".to_owned()); "else if")]
+    #[test_case(r#"if #[cfg(feature = "c")] { a } else { b }"# => Some("b".to_owned()); "else")]
+    #[test_case(r#"if #[cfg(feature = "c")] { a }"# => Some(String::new()); "no branch")]
+    #[test_case(r#"if #[cfg(test)] { a } else { /* b */ b }"# => Some("b".to_owned()); "test is false")]
+    #[test_case(r#"if #[cfg(unix)] { a } else { b }"# => None; "unknown cfg")]
+    #[test_case(r#"if #[cfg(feature = "a")] { a } else { b } c"# => None; "trailing tokens")]
+    #[test_case(r#"if #[derive(Debug)] { a }"# => None; "not a cfg")]
+    fn test_select_cfg_if_branch(src: &str) -> Option<String> {
+        let tokens = Tokens::new(src).unwrap();
+        let branches = parse_cfg_if(&tokens, 0..tokens.len())?;
+        let branch = select_cfg_if_branch(&branches, &cfg_set())?;
+        Some(branch.map_or_else(String::new, |branch| tokens.compact(branch.body.clone())))
+    }
+}
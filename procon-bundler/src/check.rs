@@ -0,0 +1,170 @@
+use {
+    crate::{source_map::SourceMap, types::Origin, BundlerError, Result},
+    serde_json::Value,
+    std::{
+        env,
+        fmt::{self, Display, Formatter},
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+    },
+    tempfile::Builder,
+};
+
+// 既定のエディションです。Cargo.toml で指定されていなければこれを使います。
+pub const DEFAULT_EDITION: &str = "2021";
+
+// rustc のコンパイルエラーと、その元の位置です。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    // `error[E0425]: cannot find value `x` in this scope` のような見出し
+    pub message: String,
+    // 出力での行番号と列番号（1 始まり）
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub origin: Option<Origin>,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (&self.origin, self.line) {
+            (Some(origin), Some(line)) => write!(
+                f,
+                "\n --> {}:{} (bundled line {}:{})",
+                origin.path.display(),
+                origin.line,
+                line,
+                self.column.unwrap_or(1),
+            ),
+            (None, Some(line)) => write!(f, "\n --> bundled line {}:{}", line, self.column.unwrap_or(1)),
+            (_, None) => Ok(()),
+        }
+    }
+}
+
+// バンドルした `bundle` を rustc でコンパイルし、エラーを `source_map` で元の位置に戻して返します。
+// `needs_main` ならば、空の `main` を後ろに足してからコンパイルします。
+// コード生成はせず、型検査までにとどめます。
+pub fn check_bundle(bundle: &str, source_map: &SourceMap, edition: &str, needs_main: bool) -> Result<Vec<CompileError>> {
+    // 共有の一時ディレクトリの中なので、推測できない名前で、すでにあれば失敗するように作ります。
+    // 作業ディレクトリは抜けるときに消えます。
+    let dir = Builder::new()
+        .prefix("procon-bundler-check-")
+        .tempdir()
+        .map_err(|e| BundlerError::CheckDirError {
+            path: env::temp_dir(),
+            source: e,
+        })?;
+    compile(dir.path(), bundle, source_map, edition, needs_main)
+}
+
+fn compile(dir: &Path, bundle: &str, source_map: &SourceMap, edition: &str, needs_main: bool) -> Result<Vec<CompileError>> {
+    let dir_error = |e| BundlerError::CheckDirError {
+        path: dir.to_path_buf(),
+        source: e,
+    };
+    let main_path = dir.join("main.rs");
+    // 後ろに足すので、出力の行番号は変わりません。
+    let source = if needs_main {
+        format!("{}\n\nfn main() {{}}\n", bundle)
+    } else {
+        format!("{}\n", bundle)
+    };
+    fs::write(&main_path, source).map_err(dir_error)?;
+
+    // Cargo と同じく、環境変数 RUSTC があればそれを使います。
+    let rustc = env::var_os("RUSTC").map_or_else(|| PathBuf::from("rustc"), PathBuf::from);
+    let output = Command::new(&rustc)
+        .arg("--edition")
+        .arg(edition)
+        .args(["--crate-type", "bin", "--crate-name", "bundle", "--emit=metadata", "--error-format=json"])
+        .arg("--out-dir")
+        .arg(dir)
+        .arg(&main_path)
+        .output()
+        .map_err(|e| BundlerError::RustcSpawnError { program: rustc, source: e })?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let errors = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|diagnostic| compile_error(&diagnostic, source_map))
+        .collect::<Vec<_>>();
+    if !output.status.success() && errors.is_empty() {
+        // JSON として読めないもの（rustc 自体のエラーなど）は、そのまま見せます。
+        return Err(BundlerError::RustcFailed {
+            stderr: stderr.trim_end().to_owned(),
+        });
+    }
+    Ok(errors)
+}
+
+// rustc の JSON 形式の診断のうち、エラーだけを読みます。
+fn compile_error(diagnostic: &Value, source_map: &SourceMap) -> Option<CompileError> {
+    let message = diagnostic.get("message")?.as_str()?;
+    if diagnostic.get("level")?.as_str()? != "error" || message.starts_with("aborting due to") {
+        return None;
+    }
+    let message = match diagnostic.get("code").and_then(|code| code.get("code")).and_then(Value::as_str) {
+        Some(code) => format!("error[{}]: {}", code, message),
+        None => format!("error: {}", message),
+    };
+    let span = diagnostic
+        .get("spans")
+        .and_then(Value::as_array)
+        .and_then(|spans| spans.iter().find(|span| span.get("is_primary").and_then(Value::as_bool) == Some(true)));
+    let position = |key| span.and_then(|span| span.get(key)).and_then(Value::as_u64).map(|x| x as usize);
+    let line = position("line_start");
+    Some(CompileError {
+        message,
+        line,
+        column: position("column_start"),
+        origin: line.and_then(|line| source_map.locate(line).ok().flatten().cloned()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{check_bundle, DEFAULT_EDITION},
+        crate::{source_map::SourceMap, types::Origin},
+        std::path::PathBuf,
+    };
+
+    #[test]
+    fn test_check_ok() {
+        let bundle = "mod a {\n    pub fn f() -> u32 { 42 }\n}";
+        let errors = check_bundle(bundle, &SourceMap::default(), DEFAULT_EDITION, true).unwrap();
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_check_error_is_located() {
+        let bundle = "mod a {\n    pub fn f() -> u32 {\n        x\n    }\n}";
+        let origin = |line| {
+            Some(Origin {
+                path: PathBuf::from("a/src/lib.rs"),
+                line,
+            })
+        };
+        let source_map = SourceMap {
+            lines: vec![None, origin(1), origin(2), origin(3), None],
+        };
+        let errors = check_bundle(bundle, &source_map, DEFAULT_EDITION, true).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "error[E0425]: cannot find value `x` in this scope");
+        assert_eq!((errors[0].line, errors[0].column), (Some(3), Some(9)));
+        assert_eq!(errors[0].origin, origin(2));
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0425]: cannot find value `x` in this scope\n --> a/src/lib.rs:2 (bundled line 3:9)"
+        );
+    }
+
+    #[test]
+    fn test_check_solution_without_main() {
+        let errors = check_bundle("fn solve() {}", &SourceMap::default(), DEFAULT_EDITION, false).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("error[E0601]"));
+    }
+}
@@ -1,27 +1,132 @@
 use {
-    crate::{BundlerError, Result},
-    std::{collections::HashMap, path::PathBuf},
-    toml::{from_str, Value},
+    crate::{
+        diagnostic::Location,
+        resolver::normalize_path,
+        template::{check_template, OutputConfig},
+        BundlerError, Result,
+    },
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        fs,
+        path::{Path, PathBuf},
+    },
+    toml::{from_str, Table, Value},
 };
 
+// クレートの Cargo.toml から読んだ設定です。`from_crate_root` で読み、`bundle_crate` に渡します。
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct ConfigToml {
-    pub deps: HashMap<String, PathBuf>,
+    // [package] セクションの name
+    pub name: Option<String>,
+    // [package] セクションの version
+    pub version: Option<String>,
+    // [package] セクションの edition
+    pub edition: Option<String>,
+    // [dependencies] と [target.*.dependencies] セクション：アンダースコアつなぎの別名 → パス依存
+    pub deps: HashMap<String, Dependency>,
+    // パス依存を宣言した位置：アンダースコアつなぎの別名 → 位置（エラーの表示用）
+    pub dep_locations: HashMap<String, Location>,
+    // [features] セクション：feature 名 → 有効にするもののリスト
+    pub features: HashMap<String, Vec<String>>,
+    // [package.metadata.procon-bundler] セクション：出力の体裁
+    pub output: OutputConfig,
+}
+
+const METADATA_TABLE: &str = "package.metadata.procon-bundler";
+
+// パス依存の一つです。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    // 本来のパッケージ名（`package = "..."` があればそれ、なければキー）
+    pub package: String,
+    pub path: PathBuf,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+}
+
+impl Dependency {
+    pub fn new(package: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            package: package.into(),
+            path: path.into(),
+            optional: false,
+            default_features: true,
+            features: Vec::new(),
+        }
+    }
 }
 
 impl ConfigToml {
+    // クレートルート直下の Cargo.toml を読んでパースします。
+    pub fn from_crate_root(crate_root: &Path) -> Result<Self> {
+        let config_path = crate_root.join("Cargo.toml");
+        if !config_path.is_file() {
+            return Err(BundlerError::CargoTomlNotFound {
+                path: config_path,
+                location: None,
+            });
+        }
+        let buf = fs::read_to_string(&config_path).map_err(|e| BundlerError::CargoTomlReadError {
+            path: config_path.clone(),
+            location: None,
+            source: e,
+        })?;
+        Self::parse(&buf, &config_path, Some(crate_root))
+    }
+
+    // ワークスペースのルートがわからないので、`workspace = true` はエラーになります。
     pub fn new(file_content: &str) -> Result<Self> {
+        Self::parse(file_content, Path::new("Cargo.toml"), None)
+    }
+
+    // `path` はエラーの表示に、`crate_root` は `workspace = true` で継承する値を探すのに使います。
+    fn parse(file_content: &str, path: &Path, crate_root: Option<&Path>) -> Result<Self> {
+        let manifest = Manifest {
+            path,
+            content: file_content,
+        };
+        let mut inheritance = Inheritance {
+            crate_root,
+            workspace: None,
+        };
+
         // dependency の行の一つの、`=` よりも右側をパースします。
-        fn from_resource(resource: &Value) -> Result<Option<PathBuf>> {
+        fn from_resource(
+            manifest: &Manifest,
+            inheritance: &mut Inheritance,
+            table_name: &str,
+            name: &str,
+            resource: &Value,
+        ) -> Result<Option<Dependency>> {
             match resource {
+                Value::Table(resource) if resource.get("workspace").and_then(Value::as_bool) == Some(true) => {
+                    from_workspace(manifest, inheritance, table_name, name, resource)
+                }
                 Value::Table(resource) => {
                     if let Some(path) = resource.get("path") {
-                        let path_str = path.as_str().ok_or_else(|| {
-                            BundlerError::PathNotString {
-                                value: format!("{:?}", path),
-                            }
+                        let path_str = path.as_str().ok_or_else(|| BundlerError::PathNotString {
+                            value: format!("{:?}", path),
+                            location: manifest.locate(table_name, Some(name)),
                         })?;
-                        Ok(Some(PathBuf::from(path_str)))
+                        let package = match resource.get("package") {
+                            Some(package) => package.as_str().ok_or_else(|| BundlerError::PackageNotString {
+                                value: format!("{:?}", package),
+                                location: manifest.locate(table_name, Some(name)),
+                            })?,
+                            None => name,
+                        };
+                        let mut dep = Dependency::new(package, path_str);
+                        dep.optional = resource.get("optional").and_then(Value::as_bool).unwrap_or(false);
+                        dep.default_features = resource
+                            .get("default-features")
+                            .or_else(|| resource.get("default_features"))
+                            .and_then(Value::as_bool)
+                            .unwrap_or(true);
+                        if let Some(features) = resource.get("features") {
+                            dep.features = manifest.string_array(table_name, name, features)?;
+                        }
+                        Ok(Some(dep))
                     } else {
                         Ok(None)
                     }
@@ -29,48 +134,363 @@ impl ConfigToml {
                 _ => Ok(None),
             }
         }
-        
-        // [dependencies] セクションをパースします。
-        fn from_deps(deps: &Value) -> Result<HashMap<String, PathBuf>> {
+
+        // `name = { workspace = true, ... }` を、ワークスペースの [workspace.dependencies] から読みます。
+        // パスはワークスペースのルートからの相対パスなので、ルートと合わせたパスにします。
+        // `features` は合併し、`optional` はメンバーのものを使います。
+        fn from_workspace(
+            manifest: &Manifest,
+            inheritance: &mut Inheritance,
+            table_name: &str,
+            name: &str,
+            resource: &Table,
+        ) -> Result<Option<Dependency>> {
+            let location = || manifest.locate(table_name, Some(name));
+            let workspace = inheritance.workspace(location)?;
+            let workspace_manifest = workspace.manifest();
+            let Some(inherited) = workspace.get(&["dependencies", name]) else {
+                return Err(BundlerError::NotInheritable {
+                    key: name.to_owned(),
+                    table: "workspace.dependencies".to_owned(),
+                    location: location(),
+                });
+            };
+            let mut no_inheritance = Inheritance::default();
+            let table_name_in_workspace = "workspace.dependencies";
+            let Some(mut dep) =
+                from_resource(&workspace_manifest, &mut no_inheritance, table_name_in_workspace, name, inherited)?
+            else {
+                return Ok(None);
+            };
+            dep.path = normalize_path(&workspace.dir.join(&dep.path));
+            dep.optional = resource.get("optional").and_then(Value::as_bool).unwrap_or(false);
+            if let Some(features) = resource.get("features") {
+                dep.features.extend(manifest.string_array(table_name, name, features)?);
+            }
+            Ok(Some(dep))
+        }
+
+        // [dependencies] セクションをパースして `result` に、宣言の位置を `locations` に加えます。
+        // キーは `package` で改名された後の別名です。
+        fn from_deps(
+            manifest: &Manifest,
+            inheritance: &mut Inheritance,
+            table_name: &str,
+            deps: &Value,
+            result: &mut HashMap<String, Dependency>,
+            locations: &mut HashMap<String, Location>,
+        ) -> Result<()> {
             match deps {
                 Value::Table(deps) => {
-                    let mut result = HashMap::new();
                     for (name, dep) in deps {
-                        if let Some(pathbuf) = from_resource(dep)? {
+                        if let Some(dep) = from_resource(manifest, inheritance, table_name, name, dep)? {
                             // ハイフンつなぎをアンダースコアつなぎに変換
-                            result.insert(name.replace('-', "_"), pathbuf);
+                            if let Entry::Vacant(entry) = result.entry(name.replace('-', "_")) {
+                                if let Some(location) = manifest.locate(table_name, Some(name)) {
+                                    locations.insert(entry.key().clone(), location);
+                                }
+                                entry.insert(dep);
+                            }
                         }
                     }
-                    Ok(result)
+                    Ok(())
                 }
-                _ => Err(BundlerError::DependenciesNotTable),
+                _ => Err(BundlerError::DependenciesNotTable {
+                    location: manifest.locate(table_name, None),
+                }),
             }
         }
-        
-        // ファイル全体をパースします。
-        let value = from_str::<Value>(file_content)
-            .map_err(|e| BundlerError::CargoTomlParseError { source: e })?;
-            
-        let deps = match value {
-            Value::Table(ref table) => {
-                if let Some(dependencies) = table.get("dependencies") {
-                    from_deps(dependencies)?
-                } else {
-                    HashMap::new()
+
+        // [target.'cfg(...)'.dependencies] セクションをすべて `result` に加えます。
+        // どのターゲットでも使えるよう、条件は評価しません。
+        fn from_targets(
+            manifest: &Manifest,
+            inheritance: &mut Inheritance,
+            targets: &Value,
+            result: &mut HashMap<String, Dependency>,
+            locations: &mut HashMap<String, Location>,
+        ) -> Result<()> {
+            let Value::Table(targets) = targets else {
+                return Ok(());
+            };
+            for (target, table) in targets {
+                if let Some(deps) = table.get("dependencies") {
+                    let bare = target.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                    let table_name = match bare {
+                        true => format!("target.{}.dependencies", target),
+                        false => format!("target.'{}'.dependencies", target),
+                    };
+                    from_deps(manifest, inheritance, &table_name, deps, result, locations)?;
                 }
             }
-            _ => return Err(BundlerError::TomlNotTable),
+            Ok(())
+        }
+
+        // [features] セクションをパースします。
+        // 依存クレートを指す部分（`dep:name`, `name/feat`）のクレート名もアンダースコアつなぎにします。
+        fn from_features(manifest: &Manifest, features: &Value) -> Result<HashMap<String, Vec<String>>> {
+            match features {
+                Value::Table(features) => features
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = manifest.string_array("features", name, value)?
+                            .into_iter()
+                            .map(|value| match value.split_once('/') {
+                                Some((dep, feature)) => format!("{}/{}", dep.replace('-', "_"), feature),
+                                None => match value.strip_prefix("dep:") {
+                                    Some(dep) => format!("dep:{}", dep.replace('-', "_")),
+                                    None => value,
+                                },
+                            })
+                            .collect();
+                        Ok((name.clone(), value))
+                    })
+                    .collect(),
+                _ => Err(BundlerError::FeaturesNotTable {
+                    location: manifest.locate("features", None),
+                }),
+            }
+        }
+
+        // [package.metadata.procon-bundler] セクションをパースします。
+        fn from_metadata(manifest: &Manifest, metadata: &Value) -> Result<OutputConfig> {
+            let Value::Table(metadata) = metadata else {
+                return Err(BundlerError::MetadataNotTable {
+                    location: manifest.locate(METADATA_TABLE, None),
+                });
+            };
+            manifest.output_config(METADATA_TABLE, metadata)
+        }
+
+        // ファイル全体をパースします。
+        let value = parse_toml(file_content, path)?;
+        let Value::Table(ref table) = value else {
+            return Err(BundlerError::TomlNotTable {
+                path: path.to_path_buf(),
+            });
+        };
+        let package = table.get("package");
+        let name = package
+            .and_then(|package| package.get("name"))
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        // `key.workspace = true` ならば、ワークスペースの [workspace.package] から継承します。
+        let mut package_field = |key: &str| -> Result<Option<String>> {
+            let Some(value) = package.and_then(|package| package.get(key)) else {
+                return Ok(None);
+            };
+            if value.get("workspace").and_then(Value::as_bool) != Some(true) {
+                return Ok(value.as_str().map(str::to_owned));
+            }
+            let location = || manifest.locate("package", Some(key));
+            match inheritance.workspace(location)?.get(&["package", key]) {
+                Some(value) => Ok(value.as_str().map(str::to_owned)),
+                None => Err(BundlerError::NotInheritable {
+                    key: key.to_owned(),
+                    table: "workspace.package".to_owned(),
+                    location: location(),
+                }),
+            }
+        };
+        let version = package_field("version")?;
+        let edition = package_field("edition")?;
+        let mut deps = HashMap::new();
+        let mut dep_locations = HashMap::new();
+        if let Some(dependencies) = table.get("dependencies") {
+            from_deps(&manifest, &mut inheritance, "dependencies", dependencies, &mut deps, &mut dep_locations)?;
+        }
+        if let Some(targets) = table.get("target") {
+            from_targets(&manifest, &mut inheritance, targets, &mut deps, &mut dep_locations)?;
+        }
+        let features = match table.get("features") {
+            Some(features) => from_features(&manifest, features)?,
+            None => HashMap::new(),
+        };
+
+        let output = match package
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("procon-bundler"))
+        {
+            Some(metadata) => from_metadata(&manifest, metadata)?,
+            None => OutputConfig::default(),
+        };
+
+        Ok(Self {
+            name,
+            version,
+            edition,
+            deps,
+            dep_locations,
+            features,
+            output,
+        })
+    }
+}
+
+// `workspace = true` で継承する値を探すための情報です。ワークスペースのルートは必要になってから探します。
+#[derive(Default)]
+struct Inheritance<'a> {
+    crate_root: Option<&'a Path>,
+    workspace: Option<WorkspaceRoot>,
+}
+
+impl Inheritance<'_> {
+    // 探している間のエラーには、`workspace = true` の位置を添えます。
+    fn workspace(&mut self, location: impl Fn() -> Option<Location>) -> Result<&WorkspaceRoot> {
+        if self.workspace.is_none() {
+            let workspace = match self.crate_root {
+                Some(crate_root) => WorkspaceRoot::find(crate_root).map_err(|e| e.or_at(&location))?,
+                None => None,
+            };
+            let workspace = workspace.ok_or_else(|| BundlerError::WorkspaceRootNotFound { location: location() })?;
+            self.workspace = Some(workspace);
+        }
+        Ok(self.workspace.as_ref().unwrap())
+    }
+}
+
+// `[workspace]` セクションのある Cargo.toml です。
+struct WorkspaceRoot {
+    dir: PathBuf,
+    path: PathBuf,
+    content: String,
+    // [workspace] セクション
+    workspace: Value,
+}
+
+impl WorkspaceRoot {
+    // `crate_root` とその祖先のディレクトリから、Cargo と同様に最も近いものを探します。
+    fn find(crate_root: &Path) -> Result<Option<Self>> {
+        let crate_root = crate_root.canonicalize().unwrap_or_else(|_| crate_root.to_path_buf());
+        for dir in crate_root.ancestors() {
+            let path = dir.join("Cargo.toml");
+            if !path.is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(&path).map_err(|e| BundlerError::CargoTomlReadError {
+                path: path.clone(),
+                location: None,
+                source: e,
+            })?;
+            if let Some(workspace) = parse_toml(&content, &path)?.get("workspace") {
+                return Ok(Some(Self {
+                    dir: dir.to_path_buf(),
+                    workspace: workspace.clone(),
+                    path,
+                    content,
+                }));
+            }
+        }
+        Ok(None)
+    }
+    fn manifest(&self) -> Manifest<'_> {
+        Manifest {
+            path: &self.path,
+            content: &self.content,
+        }
+    }
+    // [workspace] セクションの中の値を、キーをたどって探します。
+    fn get(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().try_fold(&self.workspace, |value, key| value.get(key))
+    }
+}
+
+// TOML をパースします。エラーには、`path` と問題の位置を添えます。
+pub fn parse_toml(content: &str, path: &Path) -> Result<Value> {
+    from_str::<Value>(content).map_err(|e| BundlerError::CargoTomlParseError {
+        path: path.to_path_buf(),
+        location: e.span().map(|span| Location::new(path, content, span)),
+        source: Box::new(e),
+    })
+}
+
+// エラーの位置を求めるための、TOML のソースです。
+pub struct Manifest<'a> {
+    pub path: &'a Path,
+    pub content: &'a str,
+}
+
+impl Manifest<'_> {
+    // 文字列の配列を読みます。
+    pub fn string_array(&self, table: &str, key: &str, value: &Value) -> Result<Vec<String>> {
+        let invalid = || BundlerError::InvalidFeatureList {
+            key: key.to_owned(),
+            value: format!("{:?}", value),
+            location: self.locate(table, Some(key)),
         };
-        
-        Ok(Self { deps })
+        value
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|value| value.as_str().map(str::to_owned).ok_or_else(invalid))
+            .collect()
+    }
+    // ヘッダーやフッターのテンプレートを読みます。
+    pub fn template(&self, table: &str, key: &str, value: &Value) -> Result<String> {
+        let location = || self.locate(table, Some(key));
+        let template = value.as_str().ok_or_else(|| BundlerError::TemplateNotString {
+            key: key.to_owned(),
+            value: format!("{:?}", value),
+            location: location(),
+        })?;
+        check_template(template, location)?;
+        Ok(template.to_owned())
+    }
+    // テーブルの `header`, `footer`, `allow` を読みます。ほかのキーは見ません。
+    pub fn output_config(&self, table_name: &str, table: &Table) -> Result<OutputConfig> {
+        let template = |key| table.get(key).map(|value| self.template(table_name, key, value)).transpose();
+        Ok(OutputConfig {
+            header: template("header")?,
+            footer: template("footer")?,
+            allow: table
+                .get("allow")
+                .map(|allow| self.string_array(table_name, "allow", allow))
+                .transpose()?,
+        })
+    }
+
+    // テーブル `[table]` またはそのキー `key` の書かれている位置を、行単位で探します。
+    // `[table.key]` の形も探します。`table` が空ならば、最初のテーブルより前のキーを探します。
+    // TOML の値は位置をもたないので、見つからなければ `None` です。
+    pub fn locate(&self, table: &str, key: Option<&str>) -> Option<Location> {
+        let mut offset = 0;
+        let mut in_table = table.is_empty();
+        for line in self.content.split('\n') {
+            let start = offset + (line.len() - line.trim_start().len());
+            offset += line.len() + 1;
+            let trimmed = line.trim();
+            if let Some(header) = trimmed.strip_prefix('[') {
+                let header = header.trim_start_matches('[').split(']').next().unwrap_or("").trim();
+                in_table = header == table;
+                let found = match key {
+                    None => in_table,
+                    Some(key) => header == format!("{}.{}", table, key),
+                };
+                if found {
+                    return Some(Location::new(self.path, self.content, start..start + trimmed.len()));
+                }
+            } else if let Some(key) = key.filter(|_| in_table) {
+                let rest = trimmed.strip_prefix(key).or_else(|| {
+                    trimmed
+                        .strip_prefix('"')
+                        .and_then(|rest| rest.strip_prefix(key))
+                        .and_then(|rest| rest.strip_prefix('"'))
+                });
+                if rest.is_some_and(|rest| rest.trim_start().starts_with(['=', '.'])) {
+                    return Some(Location::new(self.path, self.content, start..start + trimmed.len()));
+                }
+            }
+        }
+        None
     }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::ConfigToml,
-        std::{collections::HashMap, path::PathBuf},
+        super::{ConfigToml, Dependency},
+        crate::{template::OutputConfig, BundlerError},
+        std::{collections::HashMap, path::Path},
     };
 
     #[test]
@@ -84,9 +504,9 @@ mod tests {
         "#,
         ).unwrap();
         let mut expected = HashMap::new();
-        expected.insert("a".to_string(), PathBuf::from("../path/to/a"));
-        expected.insert("b".to_string(), PathBuf::from("../path/to/b"));
-        expected.insert("c".to_string(), PathBuf::from("../path/to/c"));
+        expected.insert("a".to_string(), Dependency::new("a", "../path/to/a"));
+        expected.insert("b".to_string(), Dependency::new("b", "../path/to/b"));
+        expected.insert("c".to_string(), Dependency::new("c", "../path/to/c"));
         assert_eq!(config.deps, expected);
     }
 
@@ -99,7 +519,7 @@ mod tests {
         "#,
         ).unwrap();
         let mut expected = HashMap::new();
-        expected.insert("a_b".to_string(), PathBuf::from("../path/to/a-b"));
+        expected.insert("a_b".to_string(), Dependency::new("a-b", "../path/to/a-b"));
         assert_eq!(config.deps, expected);
     }
 
@@ -116,9 +536,9 @@ mod tests {
         "#,
         ).unwrap();
         let mut expected = HashMap::new();
-        expected.insert("a".to_string(), PathBuf::from("../path/to/a"));
-        expected.insert("b".to_string(), PathBuf::from("../path/to/b"));
-        expected.insert("c".to_string(), PathBuf::from("../path/to/c"));
+        expected.insert("a".to_string(), Dependency::new("a", "../path/to/a"));
+        expected.insert("b".to_string(), Dependency::new("b", "../path/to/b"));
+        expected.insert("c".to_string(), Dependency::new("c", "../path/to/c"));
         assert_eq!(config.deps, expected);
     }
 
@@ -135,7 +555,7 @@ mod tests {
         "#,
         ).unwrap();
         let mut expected = HashMap::new();
-        expected.insert("a".to_string(), PathBuf::from("../path/to/a"));
+        expected.insert("a".to_string(), Dependency::new("a", "../path/to/a"));
         assert_eq!(config.deps, expected);
     }
 
@@ -167,9 +587,193 @@ mod tests {
             test-case = { version = "1", features = ["hamcrest_assertions"] }
         "#,
         ).unwrap();
+        assert_eq!(config.name.as_deref(), Some("hi!"));
+        let mut expected = HashMap::new();
+        expected.insert("here".to_string(), Dependency::new("here", "../here"));
+        expected.insert("there".to_string(), Dependency::new("there", "../there"));
+        assert_eq!(config.deps, expected);
+    }
+
+    #[test]
+    fn test_dependency_features() {
+        let config = ConfigToml::new(
+            r#"
+            [dependencies]
+            a = { path = "../path/to/a", features = ["x", "y"] }
+            b-c = { path = "../path/to/b-c", optional = true, default-features = false }
+        "#,
+        ).unwrap();
+        let mut a = Dependency::new("a", "../path/to/a");
+        a.features = vec!["x".to_owned(), "y".to_owned()];
+        let mut b_c = Dependency::new("b-c", "../path/to/b-c");
+        b_c.optional = true;
+        b_c.default_features = false;
+        let mut expected = HashMap::new();
+        expected.insert("a".to_string(), a);
+        expected.insert("b_c".to_string(), b_c);
+        assert_eq!(config.deps, expected);
+    }
+
+    #[test]
+    fn test_renamed_and_target_dependencies() {
+        let config = ConfigToml::new(
+            r#"
+            [dependencies]
+            fenwick = { package = "fenwick-tree", path = "../fenwick-tree" }
+
+            [target.'cfg(unix)'.dependencies]
+            seg-tree = { path = "../seg-tree" }
+
+            [target.x86_64-pc-windows-msvc.dependencies]
+            win = { package = "winapi", version = "0.3" }
+        "#,
+        ).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert("fenwick".to_string(), Dependency::new("fenwick-tree", "../fenwick-tree"));
+        expected.insert("seg_tree".to_string(), Dependency::new("seg-tree", "../seg-tree"));
+        assert_eq!(config.deps, expected);
+    }
+
+    #[test]
+    fn test_workspace_inheritance() {
+        let workspace_root = Path::new("../testcase/inherit").canonicalize().unwrap();
+        let config = ConfigToml::from_crate_root(&workspace_root.join("crates/app")).unwrap();
+        assert_eq!(config.version.as_deref(), Some("0.3.0"));
+        assert_eq!(config.edition.as_deref(), Some("2021"));
+        // パスはワークスペースのルートからの相対パスで、feature は合併します。
+        let mut fenwick = Dependency::new("fenwick-tree", workspace_root.join("crates/fenwick-tree"));
+        fenwick.features = vec!["a".to_owned(), "b".to_owned()];
+        let mut seg = Dependency::new("seg-tree", workspace_root.join("crates/seg-tree"));
+        seg.optional = true;
         let mut expected = HashMap::new();
-        expected.insert("here".to_string(), PathBuf::from("../here"));
-        expected.insert("there".to_string(), PathBuf::from("../there"));
+        expected.insert("fenwick_tree".to_string(), fenwick);
+        expected.insert("seg".to_string(), seg);
         assert_eq!(config.deps, expected);
     }
+
+    #[test]
+    fn test_dep_locations() {
+        let config = ConfigToml::new(
+            r#"
+[dependencies]
+a-b = { path = "../a-b" }
+
+[target.'cfg(unix)'.dependencies]
+c = { path = "../c" }
+a-b = { path = "../other" }
+        "#,
+        )
+        .unwrap();
+        let lines = |name: &str| config.dep_locations.get(name).map(|location| location.line);
+        assert_eq!(lines("a_b"), Some(3));
+        assert_eq!(lines("c"), Some(6));
+    }
+
+    #[test]
+    fn test_workspace_root_not_found() {
+        let err = ConfigToml::new("[dependencies]\na = { workspace = true }\n").unwrap_err();
+        assert!(matches!(err, BundlerError::WorkspaceRootNotFound { .. }));
+        assert_eq!(err.location().map(|location| location.line), Some(2));
+    }
+
+    #[test]
+    fn test_package_not_string() {
+        let err = ConfigToml::new(
+            r#"
+[target.'cfg(unix)'.dependencies]
+a = { package = 1, path = "../a" }
+        "#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, BundlerError::PackageNotString { .. }));
+        assert_eq!(err.location().map(|location| location.line), Some(3));
+    }
+
+    #[test]
+    fn test_features() {
+        let config = ConfigToml::new(
+            r#"
+            [dependencies]
+            b-c = { path = "../path/to/b-c", optional = true }
+
+            [features]
+            default = ["x"]
+            x = []
+            y = ["x", "dep:b-c", "b-c/z"]
+        "#,
+        ).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert("default".to_string(), vec!["x".to_owned()]);
+        expected.insert("x".to_string(), vec![]);
+        expected.insert(
+            "y".to_string(),
+            vec!["x".to_owned(), "dep:b_c".to_owned(), "b_c/z".to_owned()],
+        );
+        assert_eq!(config.features, expected);
+    }
+
+    #[test]
+    fn test_features_not_string_array() {
+        let result = ConfigToml::new(
+            r#"
+            [features]
+            x = "y"
+        "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_error_location() {
+        let err = ConfigToml::new(
+            r#"
+[dependencies]
+a = { path = "../a" }
+b = { path = 1 }
+        "#,
+        )
+        .unwrap_err();
+        let location = err.location().unwrap();
+        assert_eq!((location.line, location.column), (4, 1));
+        assert_eq!(location.source_line, "b = { path = 1 }");
+
+        let err = ConfigToml::new("[dependencies]\na = {").unwrap_err();
+        assert_eq!(err.location().map(|location| location.line), Some(2));
+    }
+
+    #[test]
+    fn test_output_metadata() {
+        let config = ConfigToml::new(
+            r#"
+            [package]
+            name = "my-crate"
+            version = "0.1.0"
+
+            [package.metadata.procon-bundler]
+            header = "// {name} v{version}"
+            allow = ["dead_code"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.version.as_deref(), Some("0.1.0"));
+        let expected = OutputConfig {
+            header: Some("// {name} v{version}".to_owned()),
+            footer: None,
+            allow: Some(vec!["dead_code".to_owned()]),
+        };
+        assert_eq!(config.output, expected);
+    }
+
+    #[test]
+    fn test_output_metadata_unknown_placeholder() {
+        let err = ConfigToml::new(
+            r#"
+[package.metadata.procon-bundler]
+footer = "// end of {crate}"
+        "#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, BundlerError::UnknownPlaceholder { ref placeholder, .. } if placeholder == "crate"));
+        assert_eq!(err.location().map(|location| location.line), Some(3));
+    }
 }
@@ -0,0 +1,142 @@
+use {
+    crate::{lexer::line_column, BundlerError},
+    std::{
+        fmt::{self, Display, Formatter, Write},
+        ops::Range,
+        path::{Path, PathBuf},
+    },
+};
+
+// エラーの起きたソース上の位置です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    // 1 始まりの行番号と列番号（列は文字単位）
+    pub line: usize,
+    pub column: usize,
+    // その行のテキスト（スニペットの表示用）
+    pub source_line: String,
+    // 下線を引く文字数（行末で打ち切ります。）
+    pub width: usize,
+}
+
+impl Location {
+    // ソース `src` のバイト範囲 `range` の位置です。
+    pub fn new(path: &Path, src: &str, range: Range<usize>) -> Self {
+        let start = floor_char_boundary(src, range.start);
+        let (line, column) = line_column(src, start);
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let end = floor_char_boundary(src, range.end.clamp(start, line_end));
+        Self {
+            path: path.to_path_buf(),
+            line,
+            column,
+            source_line: src[line_start..line_end].trim_end_matches('\r').to_owned(),
+            width: src[start..end].chars().count().max(1),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+fn floor_char_boundary(src: &str, mut i: usize) -> usize {
+    i = i.min(src.len());
+    while !src.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+// エラーを rustc 風に、位置がわかればスニペットつきで表示します。
+//
+// ```text
+// error: Module file not found for path "./a" (tried ["a.rs", "a/mod.rs"])
+//  --> src/lib.rs:1:1
+//   |
+// 1 | mod a;
+//   | ^^^^^^
+// ```
+pub fn render(err: &BundlerError) -> String {
+    let mut result = format!("error: {}", err);
+    if let Some(location) = err.location() {
+        let pad = " ".repeat(location.line.to_string().len());
+        // タブはそのまま残して、下線の位置をそろえます。
+        let indent = location
+            .source_line
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        write!(
+            result,
+            "\n{pad}--> {location}\n{pad} |\n{line} | {source_line}\n{pad} | {indent}{carets}",
+            line = location.line,
+            source_line = location.source_line,
+            carets = "^".repeat(location.width),
+        )
+        .unwrap();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{render, Location},
+        crate::BundlerError,
+        std::path::Path,
+    };
+
+    #[test]
+    fn test_location() {
+        let src = "fn f() {}\r\n\tmod あ;\nfn g() {}\n";
+        let start = src.find("mod").unwrap();
+        let location = Location::new(Path::new("src/lib.rs"), src, start..src.len());
+        assert_eq!(location.line, 2);
+        assert_eq!(location.column, 2);
+        assert_eq!(location.source_line, "\tmod あ;");
+        assert_eq!(location.width, 6);
+        assert_eq!(location.to_string(), "src/lib.rs:2:2");
+    }
+
+    #[test]
+    fn test_location_at_end_of_file() {
+        let location = Location::new(Path::new("lib.rs"), "mod a {\n", 8..9);
+        assert_eq!((location.line, location.column), (2, 1));
+        assert_eq!(location.source_line, "");
+        assert_eq!(location.width, 1);
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "fn f() {}\n\tmod a {\n";
+        let err = BundlerError::Syntax {
+            location: Location::new(Path::new("src/lib.rs"), src, 16..17),
+            message: "unclosed delimiter",
+        };
+        assert_eq!(
+            render(&err),
+            concat!(
+                "error: Syntax error: unclosed delimiter\n",
+                " --> src/lib.rs:2:7\n",
+                "  |\n",
+                "2 | \tmod a {\n",
+                "  | \t     ^",
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_without_location() {
+        let err = BundlerError::DependencyCycle {
+            path: Path::new("a").to_path_buf(),
+            location: None,
+        };
+        assert_eq!(render(&err), "error: Cyclic path dependency detected at \"a\"");
+    }
+}
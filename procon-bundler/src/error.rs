@@ -1,54 +1,299 @@
+use crate::diagnostic::Location;
 use std::path::PathBuf;
 use thiserror::Error;
 
 /// Custom error types for the procon-bundler
 #[derive(Error, Debug)]
 pub enum BundlerError {
+    // 依存クレートのものならば、`location` は依存の宣言の位置です。
     #[error("Cargo.toml not found at {path:?}")]
-    CargoTomlNotFound { path: PathBuf },
+    CargoTomlNotFound { path: PathBuf, location: Option<Location> },
 
     #[error("Failed to read Cargo.toml at {path:?}: {source}")]
     CargoTomlReadError {
         path: PathBuf,
+        location: Option<Location>,
         #[source]
         source: std::io::Error,
     },
 
-    #[error("Failed to parse Cargo.toml: {source}")]
+    #[error("Failed to parse {path:?}: {}", .source.message())]
     CargoTomlParseError {
+        path: PathBuf,
+        location: Option<Location>,
         #[source]
-        source: toml::de::Error,
+        source: Box<toml::de::Error>,
     },
 
-    #[error("Module file not found for path {module_path:?} at {file_path:?}: {source}")]
+    // `location` はモジュール宣言の位置です。
+    #[error("Module file not found for path {module_path:?} (tried {candidates:?})")]
     ModuleFileNotFound {
         module_path: PathBuf,
-        file_path: PathBuf,
-        #[source]
-        source: std::io::Error,
+        candidates: Vec<PathBuf>,
+        location: Option<Location>,
+    },
+
+    #[error("Module file for path {module_path:?} is ambiguous: found all of {candidates:?}")]
+    AmbiguousModuleFile {
+        module_path: PathBuf,
+        candidates: Vec<PathBuf>,
+        location: Option<Location>,
     },
 
     #[error("Failed to read module file {path:?}: {source}")]
     ModuleFileReadError {
         path: PathBuf,
+        location: Option<Location>,
         #[source]
         source: std::io::Error,
     },
 
+    #[error("Syntax error: {message}")]
+    Syntax { location: Location, message: &'static str },
+
     #[error("Invalid path conversion: {path:?}")]
     InvalidPathConversion { path: PathBuf },
 
     #[error("Invalid file stem: {path:?}")]
     InvalidFileStem { path: PathBuf },
 
-    #[error("TOML file is not a table")]
-    TomlNotTable,
+    #[error("TOML file {path:?} is not a table")]
+    TomlNotTable { path: PathBuf },
 
     #[error("Dependencies section is not a table")]
-    DependenciesNotTable,
+    DependenciesNotTable { location: Option<Location> },
+
+    #[error("Features section is not a table")]
+    FeaturesNotTable { location: Option<Location> },
+
+    #[error("Metadata section is not a table")]
+    MetadataNotTable { location: Option<Location> },
+
+    #[error("Value of {key:?} is not a string: {value}")]
+    TemplateNotString {
+        key: String,
+        value: String,
+        location: Option<Location>,
+    },
+
+    #[error("Unknown placeholder {{{placeholder}}} in template {template:?} (available: {})", crate::template::PLACEHOLDERS.join(", "))]
+    UnknownPlaceholder {
+        template: String,
+        placeholder: String,
+        location: Option<Location>,
+    },
+
+    #[error("Failed to read options file {path:?}: {source}")]
+    OptionsReadError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Unknown option {key:?} (available: {})", crate::options::OPTION_KEYS.join(", "))]
+    UnknownOption { key: String, location: Option<Location> },
+
+    #[error("Invalid value of option {key:?}: {message}")]
+    InvalidOptionValue {
+        key: String,
+        message: String,
+        location: Option<Location>,
+    },
+
+    #[error("Value of {key:?} is not an array of strings: {value}")]
+    InvalidFeatureList {
+        key: String,
+        value: String,
+        location: Option<Location>,
+    },
+
+    #[error("Crate {crate_name:?} has no feature {feature:?}")]
+    UnknownFeature {
+        crate_name: String,
+        feature: String,
+        location: Option<Location>,
+    },
 
     #[error("Path value is not a string: {value:?}")]
-    PathNotString { value: String },
+    PathNotString { value: String, location: Option<Location> },
+
+    #[error("Package name is not a string: {value:?}")]
+    PackageNotString { value: String, location: Option<Location> },
+
+    #[error("`workspace = true` is used, but no Cargo.toml with a [workspace] section was found")]
+    WorkspaceRootNotFound { location: Option<Location> },
+
+    #[error("{key:?} is inherited with `workspace = true`, but is not in [{table}] of the workspace root")]
+    NotInheritable {
+        key: String,
+        table: String,
+        location: Option<Location>,
+    },
+
+    #[error("Crate root not found at {path:?}: {source}")]
+    CrateRootNotFound {
+        path: PathBuf,
+        location: Option<Location>,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to read stdin: {source}")]
+    StdinReadError {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to read solution file {path:?}: {source}")]
+    SolutionReadError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("No Cargo.toml found in any ancestor directory of {path:?}")]
+    ManifestNotFound { path: PathBuf },
+
+    #[error("No [workspace] section in {path:?}")]
+    NotAWorkspace { path: PathBuf },
+
+    #[error("Invalid workspace member {pattern:?}: {message}")]
+    InvalidWorkspaceMember {
+        pattern: String,
+        message: String,
+        location: Option<Location>,
+    },
+
+    // 3 つのフィールドとあわせると大きすぎるので、`location` は箱に入れます。
+    #[error(
+        "Crate {name:?} is not a member of the workspace at {workspace_root:?}{}",
+        did_you_mean(close_matches)
+    )]
+    CrateNotInWorkspace {
+        name: String,
+        workspace_root: PathBuf,
+        close_matches: Vec<String>,
+        location: Option<Box<Location>>,
+    },
+
+    // `location` は循環を閉じる依存の宣言の位置です。
+    #[error("Cyclic path dependency detected at {path:?}")]
+    DependencyCycle { path: PathBuf, location: Option<Location> },
+
+    // 同じく、`location` は箱に入れます。
+    #[error("Two distinct crates are bundled under the same name {name:?}: {first:?} and {second:?}")]
+    DuplicateCrateName {
+        name: String,
+        first: PathBuf,
+        second: PathBuf,
+        location: Option<Box<Location>>,
+    },
+
+    #[error("Failed to read source map {path:?}: {source}")]
+    SourceMapReadError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write source map {path:?}: {source}")]
+    SourceMapWriteError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid source map {path:?}: {message}")]
+    InvalidSourceMap { path: PathBuf, message: String },
+
+    #[error("Line {line} is out of range: the output has {len} lines")]
+    LineOutOfRange { line: usize, len: usize },
+
+    #[error("Failed to prepare a directory {path:?} for rustc: {source}")]
+    CheckDirError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to run {program:?}: {source}")]
+    RustcSpawnError {
+        program: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    // `report` は rustc のエラーを元の位置に戻したものです。
+    #[error("The bundle failed to compile with {errors} error(s):\n\n{report}")]
+    CheckFailed { report: String, errors: usize },
+
+    #[error("rustc failed without reporting errors:\n{stderr}")]
+    RustcFailed { stderr: String },
+}
+
+impl BundlerError {
+    // エラーの起きたソース上の位置です。
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            BundlerError::Syntax { location, .. } => Some(location),
+            BundlerError::CargoTomlNotFound { location, .. }
+            | BundlerError::CargoTomlReadError { location, .. }
+            | BundlerError::CargoTomlParseError { location, .. }
+            | BundlerError::ModuleFileNotFound { location, .. }
+            | BundlerError::AmbiguousModuleFile { location, .. }
+            | BundlerError::ModuleFileReadError { location, .. }
+            | BundlerError::DependenciesNotTable { location }
+            | BundlerError::FeaturesNotTable { location }
+            | BundlerError::MetadataNotTable { location }
+            | BundlerError::TemplateNotString { location, .. }
+            | BundlerError::UnknownPlaceholder { location, .. }
+            | BundlerError::UnknownOption { location, .. }
+            | BundlerError::InvalidOptionValue { location, .. }
+            | BundlerError::InvalidFeatureList { location, .. }
+            | BundlerError::UnknownFeature { location, .. }
+            | BundlerError::PathNotString { location, .. }
+            | BundlerError::PackageNotString { location, .. }
+            | BundlerError::WorkspaceRootNotFound { location }
+            | BundlerError::NotInheritable { location, .. }
+            | BundlerError::CrateRootNotFound { location, .. }
+            | BundlerError::InvalidWorkspaceMember { location, .. }
+            | BundlerError::DependencyCycle { location, .. } => location.as_ref(),
+            BundlerError::CrateNotInWorkspace { location, .. } | BundlerError::DuplicateCrateName { location, .. } => {
+                location.as_deref()
+            }
+            _ => None,
+        }
+    }
+    // 位置がまだわかっていなければ、`location` を設定します。
+    pub fn or_at(mut self, new_location: impl FnOnce() -> Option<Location>) -> Self {
+        match &mut self {
+            BundlerError::CargoTomlNotFound { location, .. }
+            | BundlerError::CargoTomlReadError { location, .. }
+            | BundlerError::ModuleFileNotFound { location, .. }
+            | BundlerError::AmbiguousModuleFile { location, .. }
+            | BundlerError::ModuleFileReadError { location, .. }
+            | BundlerError::UnknownFeature { location, .. }
+            | BundlerError::CrateRootNotFound { location, .. }
+            | BundlerError::DependencyCycle { location, .. }
+                if location.is_none() =>
+            {
+                *location = new_location();
+            }
+            BundlerError::DuplicateCrateName { location, .. } if location.is_none() => {
+                *location = new_location().map(Box::new);
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+fn did_you_mean(close_matches: &[String]) -> String {
+    if close_matches.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", close_matches.join(", "))
+    }
 }
 
 /// Result type alias for convenience
@@ -0,0 +1,229 @@
+use {
+    crate::{
+        bundle_crate::bundle_crates_with_deps,
+        features::{resolve_features, FeatureRequest},
+        diagnostic::Location,
+        lexer::tokenize,
+        paths::{apply_edits, PathContext},
+        prettify::format_crates_with_source_map,
+        resolver::find_buffer,
+        shake::shake_crates,
+        source_map::SourceMap,
+        types::Origin,
+        Bundle, BundleOptions, BundlerError, ConfigToml, Result,
+    },
+    std::{
+        collections::BTreeMap,
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+// 解答ファイルを読み、参照しているライブラリクレートを展開します。
+// 解答やライブラリのファイルの内容は、ディスク上のパスをキーとする `buffers` にあればそれで置き換えます。
+pub(crate) fn expand_solution_with_buffers(
+    solution_path: &Path,
+    manifest_dir: Option<&Path>,
+    request: &FeatureRequest,
+    shake: bool,
+    options: &BundleOptions,
+    buffers: &BTreeMap<PathBuf, String>,
+) -> Result<Bundle> {
+    let solution = match find_buffer(buffers, solution_path) {
+        Some(solution) => solution.clone(),
+        None => fs::read_to_string(solution_path).map_err(|e| BundlerError::SolutionReadError {
+            path: solution_path.to_path_buf(),
+            source: e,
+        })?,
+    };
+    let manifest_dir = match manifest_dir {
+        Some(manifest_dir) => manifest_dir.to_path_buf(),
+        None => find_manifest_dir(solution_path)?,
+    };
+    expand(&solution, solution_path, &manifest_dir, request, shake, options, buffers)
+}
+
+// 解答から参照されているパス依存クレートを推移的にバンドルし、
+// パスを `crate::dep::` に書き換えた解答の後ろに貼り付けます。
+// feature の指定は解答のクレートに対するもので、依存クレートの feature はそこから定まります。
+// `shake` ならば、解答から到達可能なアイテムだけを残します。
+// `solution_path` はエラーの表示に使います。
+fn expand(
+    solution: &str,
+    solution_path: &Path,
+    manifest_dir: &Path,
+    request: &FeatureRequest,
+    shake: bool,
+    options: &BundleOptions,
+    buffers: &BTreeMap<PathBuf, String>,
+) -> Result<Bundle> {
+    let config = ConfigToml::from_crate_root(manifest_dir)?;
+    let tokens = tokenize(solution).map_err(|e| BundlerError::Syntax {
+        location: Location::new(solution_path, solution, e.offset..e.offset + 1),
+        message: e.message,
+    })?;
+    let edits = PathContext {
+        crate_name: None,
+        config: &config,
+    }
+    .edits(&tokens, solution);
+    let solution_name = manifest_dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("solution");
+    let mut resolved = resolve_features(solution_name, &config, request)?;
+
+    // 出力が安定するよう、参照されているクレートを名前順に並べます。
+    let mut names = edits.iter().filter_map(|edit| edit.dep).collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    let roots = names
+        .iter()
+        .map(|&name| {
+            let request = resolved.deps.remove(name).unwrap_or_default();
+            let dep = &config.deps[name];
+            let site = config.dep_locations.get(name).cloned();
+            (dep.package.as_str(), manifest_dir.join(&dep.path), request, site)
+        })
+        .collect::<Vec<_>>();
+    let crates = bundle_crates_with_deps(
+        roots
+            .iter()
+            .map(|(name, path, request, site)| (*name, path.as_path(), request.clone(), site.clone())),
+        options,
+        buffers,
+    )?;
+    let crates = if shake { shake_crates(crates, &[solution]) } else { crates };
+
+    // パスの書き換えでは行の数は変わらないので、解答の i 行目はそのまま出力の i 行目です。
+    let lines = apply_edits(solution, &edits).lines().map(str::to_owned).collect::<Vec<_>>();
+    let mut source_map = SourceMap {
+        lines: (1..=lines.len())
+            .map(|line| {
+                Some(Origin {
+                    path: solution_path.to_path_buf(),
+                    line,
+                })
+            })
+            .collect(),
+    };
+    let mut result = lines.join("\n");
+    let dropped_attrs = crates.iter().flat_map(|krate| krate.info.dropped_attrs.clone()).collect();
+    if !crates.is_empty() {
+        let (bundled, bundled_map) = format_crates_with_source_map(crates, options);
+        result.push_str("\n\n");
+        result.push_str(&bundled);
+        // 空の解答も一行と数えます。
+        let blank_lines = if lines.is_empty() { 2 } else { 1 };
+        source_map.lines.extend((0..blank_lines).map(|_| None));
+        source_map.append(bundled_map);
+    }
+    Ok(Bundle {
+        code: result,
+        source_map,
+        dropped_attrs,
+    })
+}
+
+// 解答ファイルのあるディレクトリから親をたどり、最初に Cargo.toml が見つかったディレクトリを返します。
+pub fn find_manifest_dir(solution_path: &Path) -> Result<PathBuf> {
+    let solution_path = solution_path
+        .canonicalize()
+        .map_err(|e| BundlerError::SolutionReadError {
+            path: solution_path.to_path_buf(),
+            source: e,
+        })?;
+    solution_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map(Path::to_path_buf)
+        .ok_or(BundlerError::ManifestNotFound {
+            path: solution_path,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{expand, expand_solution_with_buffers},
+        crate::{
+            check::{check_bundle, DEFAULT_EDITION},
+            features::FeatureRequest,
+            Bundle, BundleOptions,
+        },
+        std::{collections::BTreeMap, path::Path},
+    };
+
+    #[test]
+    fn test_expand_solution() {
+        let solution = concat!(
+            "use mid::Mid;\n",
+            "fn main() {\n",
+            "    let _ = mid::Mid(leaf::Leaf);\n",
+            "}",
+        );
+        let result = expand(solution, Path::new("<solution>"), Path::new("../testcase/expand"), &FeatureRequest::default(), false, &BundleOptions::default(), &BTreeMap::new()).unwrap().code;
+        let expected = concat!(
+            "use crate::mid::Mid;\n",
+            "fn main() {\n",
+            "    let _ = crate::mid::Mid(crate::leaf::Leaf);\n",
+            "}\n",
+            "\n",
+            concat!("// leaf {", "{{\n"),
+            "// https://ngtkana.github.io/ac-adapter-rs/leaf/index.html\n",
+            "#[allow(unused_imports)]\n",
+            "#[allow(dead_code)]\n",
+            "mod leaf {\n",
+            "    pub struct Leaf;\n",
+            "}\n",
+            concat!("// }", "}}\n"),
+            concat!("// mid {", "{{\n"),
+            "// https://ngtkana.github.io/ac-adapter-rs/mid/index.html\n",
+            "#[allow(unused_imports)]\n",
+            "#[allow(dead_code)]\n",
+            "mod mid {\n",
+            "    pub use crate::leaf::Leaf;\n",
+            "    pub struct Mid(pub Leaf);\n",
+            "}\n",
+            concat!("// }", "}}"),
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_expand_unreferenced_crates_are_skipped() {
+        let solution = "fn main() {}";
+        let result = expand(solution, Path::new("<solution>"), Path::new("../testcase/expand"), &FeatureRequest::default(), false, &BundleOptions::default(), &BTreeMap::new()).unwrap().code;
+        assert_eq!(result, solution);
+    }
+
+    #[test]
+    fn test_expand_finds_manifest() {
+        let result = expand_solution_with_buffers(Path::new("../testcase/expand/src/main.rs"), None, &FeatureRequest::default(), false, &BundleOptions::default(), &BTreeMap::new()).unwrap().code;
+        assert!(result.starts_with("use crate::root::build;\n"));
+        assert!(result.contains("mod root {\n"));
+    }
+
+    // 解答から使う `pub mod` は、可視性を残さなければコンパイルできません。
+    #[test]
+    fn test_expand_public_module_compiles() {
+        let path = Path::new("../testcase/public_module/contest/src/main.rs");
+        let Bundle { code: result, source_map, .. } = expand_solution_with_buffers(path, None, &FeatureRequest::default(), false, &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        assert!(result.contains("    pub mod point {\n"));
+        let errors = check_bundle(&result, &source_map, DEFAULT_EDITION, false).unwrap();
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_expand_source_map() {
+        let path = Path::new("../testcase/expand/src/main.rs");
+        let Bundle { code: result, source_map, .. } = expand_solution_with_buffers(path, None, &FeatureRequest::default(), false, &BundleOptions::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(source_map.lines.len(), result.lines().count());
+        let first = source_map.locate(1).unwrap().unwrap();
+        assert_eq!((first.path.as_path(), first.line), (path, 1));
+        let line = result.lines().position(|line| line == "mod root {").unwrap() + 1;
+        assert_eq!(source_map.locate(line).unwrap(), None);
+        assert!(source_map.locate(line + 1).unwrap().is_some());
+    }
+}
@@ -0,0 +1,248 @@
+use {
+    crate::{BundlerError, ConfigToml, Result},
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+// クレートに対して有効にする feature の指定です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FeatureRequest {
+    pub features: BTreeSet<String>,
+    pub all_features: bool,
+    pub default_features: bool,
+}
+
+impl Default for FeatureRequest {
+    fn default() -> Self {
+        Self {
+            features: BTreeSet::new(),
+            all_features: false,
+            default_features: true,
+        }
+    }
+}
+
+impl FeatureRequest {
+    // Cargo と同様に、同じクレートへの指定を合併します。変化があれば true を返します。
+    pub(crate) fn merge(&mut self, other: &Self) -> bool {
+        let before = self.clone();
+        self.features.extend(other.features.iter().cloned());
+        self.all_features |= other.all_features;
+        self.default_features |= other.default_features;
+        *self != before
+    }
+}
+
+// feature を解決した結果です。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResolvedFeatures {
+    // 有効な feature（optional な依存の暗黙の feature も含みます。）
+    pub enabled: BTreeSet<String>,
+    // 有効なパス依存 → その依存クレートに対する指定
+    pub deps: BTreeMap<String, FeatureRequest>,
+}
+
+// [features] セクションをたどって、有効な feature と有効な依存クレートを求めます。
+//
+// * `dep:name` は optional な依存を有効にします。
+// * `name/feat` は依存を有効にし、その feature も有効にします。`name?/feat` は依存を有効にしません。
+// * それ以外は同じクレートの feature です。
+//
+// パス依存でない依存クレートは `ConfigToml` に現れないので、それを指すものは無視します。
+pub fn resolve_features(crate_name: &str, config: &ConfigToml, request: &FeatureRequest) -> Result<ResolvedFeatures> {
+    let mut resolver = FeatureResolver::new(config);
+    let mut requested = request.features.iter().cloned().collect::<Vec<_>>();
+    if request.all_features {
+        requested.extend(config.features.keys().cloned());
+        requested.extend(resolver.implicit.iter().cloned());
+    }
+    if request.default_features && config.features.contains_key("default") {
+        requested.push("default".to_owned());
+    }
+    for feature in requested {
+        if !feature.contains('/') && !feature.starts_with("dep:") && !resolver.is_feature(&feature) {
+            return Err(BundlerError::UnknownFeature {
+                crate_name: crate_name.to_owned(),
+                feature,
+                location: None,
+            });
+        }
+        resolver.enable(&feature);
+    }
+
+    let deps = config
+        .deps
+        .iter()
+        .filter(|(name, dep)| !dep.optional || resolver.optional_deps.contains(*name))
+        .map(|(name, dep)| {
+            let mut features = dep.features.iter().cloned().collect::<BTreeSet<_>>();
+            features.extend(resolver.dep_features.remove(name).unwrap_or_default());
+            let request = FeatureRequest {
+                features,
+                all_features: false,
+                default_features: dep.default_features,
+            };
+            (name.clone(), request)
+        })
+        .collect();
+    Ok(ResolvedFeatures {
+        enabled: resolver.enabled,
+        deps,
+    })
+}
+
+struct FeatureResolver<'a> {
+    config: &'a ConfigToml,
+    // `dep:name` で参照されていない optional な依存の名前（同名の feature が暗黙にあります。）
+    implicit: BTreeSet<String>,
+    enabled: BTreeSet<String>,
+    optional_deps: BTreeSet<String>,
+    dep_features: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl<'a> FeatureResolver<'a> {
+    fn new(config: &'a ConfigToml) -> Self {
+        let explicit = config
+            .features
+            .values()
+            .flatten()
+            .filter_map(|value| value.strip_prefix("dep:"))
+            .collect::<BTreeSet<_>>();
+        let implicit = config
+            .deps
+            .iter()
+            .filter(|(name, dep)| {
+                dep.optional && !explicit.contains(name.as_str()) && !config.features.contains_key(*name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        Self {
+            config,
+            implicit,
+            enabled: BTreeSet::new(),
+            optional_deps: BTreeSet::new(),
+            dep_features: BTreeMap::new(),
+        }
+    }
+    fn is_feature(&self, name: &str) -> bool {
+        self.config.features.contains_key(name) || self.implicit.contains(name)
+    }
+    // [features] の値ひとつを有効にします。
+    fn enable(&mut self, value: &str) {
+        if let Some(dep) = value.strip_prefix("dep:") {
+            self.optional_deps.insert(dep.to_owned());
+        } else if let Some((dep, feature)) = value.split_once('/') {
+            let dep = match dep.strip_suffix('?') {
+                Some(dep) => dep,
+                None => {
+                    self.optional_deps.insert(dep.to_owned());
+                    if self.implicit.contains(dep) {
+                        self.enable(dep);
+                    }
+                    dep
+                }
+            };
+            self.dep_features
+                .entry(dep.to_owned())
+                .or_default()
+                .insert(feature.to_owned());
+        } else if self.is_feature(value) && self.enabled.insert(value.to_owned()) {
+            match self.config.features.get(value) {
+                Some(values) => {
+                    for value in values {
+                        self.enable(value);
+                    }
+                }
+                None => {
+                    self.optional_deps.insert(value.to_owned());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{resolve_features, FeatureRequest},
+        crate::{BundlerError, ConfigToml},
+        std::collections::BTreeSet,
+    };
+
+    fn build_config_toml() -> ConfigToml {
+        ConfigToml::new(
+            r#"
+            [dependencies]
+            a = { path = "../a" }
+            b = { path = "../b", optional = true, features = ["p"] }
+            c = { path = "../c", optional = true, default-features = false }
+            d = { path = "../d", optional = true }
+
+            [features]
+            default = ["x"]
+            x = ["a/q"]
+            y = ["dep:c", "c/r", "d?/s"]
+            z = ["b"]
+        "#,
+        )
+        .unwrap()
+    }
+
+    fn request(features: &[&str], all_features: bool, default_features: bool) -> FeatureRequest {
+        FeatureRequest {
+            features: features.iter().map(|s| s.to_string()).collect(),
+            all_features,
+            default_features,
+        }
+    }
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_features() {
+        let resolved = resolve_features("my_crate", &build_config_toml(), &FeatureRequest::default()).unwrap();
+        assert_eq!(resolved.enabled, set(&["default", "x"]));
+        assert_eq!(resolved.deps.keys().collect::<Vec<_>>(), ["a"]);
+        assert_eq!(resolved.deps["a"], request(&["q"], false, true));
+    }
+
+    #[test]
+    fn test_no_default_features() {
+        let resolved = resolve_features("my_crate", &build_config_toml(), &request(&["y"], false, false)).unwrap();
+        assert_eq!(resolved.enabled, set(&["y"]));
+        assert_eq!(resolved.deps.keys().collect::<Vec<_>>(), ["a", "c"]);
+        assert_eq!(resolved.deps["a"], request(&[], false, true));
+        assert_eq!(resolved.deps["c"], request(&["r"], false, false));
+    }
+
+    #[test]
+    fn test_implicit_feature_of_optional_dependency() {
+        let resolved = resolve_features("my_crate", &build_config_toml(), &request(&["z"], false, false)).unwrap();
+        assert_eq!(resolved.enabled, set(&["b", "z"]));
+        assert_eq!(resolved.deps.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(resolved.deps["b"], request(&["p"], false, true));
+    }
+
+    #[test]
+    fn test_weak_dependency_feature() {
+        let config = build_config_toml();
+        let resolved = resolve_features("my_crate", &config, &request(&["y", "d"], false, false)).unwrap();
+        assert_eq!(resolved.deps["d"], request(&["s"], false, true));
+        let resolved = resolve_features("my_crate", &config, &request(&["y"], false, false)).unwrap();
+        assert!(!resolved.deps.contains_key("d"));
+    }
+
+    #[test]
+    fn test_all_features() {
+        let resolved = resolve_features("my_crate", &build_config_toml(), &request(&[], true, false)).unwrap();
+        assert_eq!(resolved.enabled, set(&["b", "d", "default", "x", "y", "z"]));
+        assert_eq!(resolved.deps.keys().collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_unknown_feature() {
+        let err = resolve_features("my_crate", &build_config_toml(), &request(&["w"], false, true)).unwrap_err();
+        assert!(matches!(err, BundlerError::UnknownFeature { feature, .. } if feature == "w"));
+    }
+}
@@ -0,0 +1,429 @@
+use std::{ops::Range, str::Chars};
+
+// トークンの種類です。空白は捨てますが、コメントはトークンとして残します。
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TokenKind {
+    // 識別子とキーワード（`r#ident` も含みます。）
+    Ident,
+    // `'a` のようなライフタイムとラベル
+    Lifetime,
+    // 文字列、生文字列、バイト文字列、文字、数値などのリテラル
+    Literal,
+    // `//` から行末まで（`///`, `//!` ならば doc）
+    LineComment { doc: bool },
+    // `/* */`（入れ子可、`/**`, `/*!` ならば doc）
+    BlockComment { doc: bool },
+    Open(Delim),
+    Close(Delim),
+    // その他の一文字の記号
+    Punct(char),
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Delim {
+    Paren,
+    Bracket,
+    Brace,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    // ソース中のバイト位置
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+    pub fn is_comment(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::LineComment { .. } | TokenKind::BlockComment { .. }
+        )
+    }
+    pub fn is_doc_comment(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::LineComment { doc: true } | TokenKind::BlockComment { doc: true }
+        )
+    }
+}
+
+// 字句解析や括弧の対応づけのエラーです。位置はソース中のバイト位置です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut lexer = Lexer {
+        src,
+        chars: src.chars(),
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    chars: Chars<'a>,
+}
+
+impl Lexer<'_> {
+    fn offset(&self) -> usize {
+        self.src.len() - self.chars.as_str().len()
+    }
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.clone().nth(n)
+    }
+    fn bump(&mut self) -> Option<char> {
+        self.chars.next()
+    }
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek(0).is_some_and(&f) {
+            self.bump();
+        }
+    }
+    fn rest(&self) -> &str {
+        self.chars.as_str()
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, SyntaxError> {
+        self.eat_while(char::is_whitespace);
+        let start = self.offset();
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let kind = match c {
+            '/' if self.peek(1) == Some('/') => self.line_comment(),
+            '/' if self.peek(1) == Some('*') => self.block_comment(start)?,
+            '"' => {
+                self.bump();
+                self.quoted('"', start)?
+            }
+            '\'' => self.char_or_lifetime(start)?,
+            'r' | 'b' | 'c' if self.raw_or_prefixed_literal(start)? => TokenKind::Literal,
+            'r' if self.peek(1) == Some('#') && self.peek(2).is_some_and(is_ident_start) => {
+                self.bump();
+                self.bump();
+                self.eat_while(is_ident_continue);
+                TokenKind::Ident
+            }
+            c if is_ident_start(c) => {
+                self.eat_while(is_ident_continue);
+                TokenKind::Ident
+            }
+            c if c.is_ascii_digit() => self.number(),
+            c => {
+                self.bump();
+                match c {
+                    '(' => TokenKind::Open(Delim::Paren),
+                    '[' => TokenKind::Open(Delim::Bracket),
+                    '{' => TokenKind::Open(Delim::Brace),
+                    ')' => TokenKind::Close(Delim::Paren),
+                    ']' => TokenKind::Close(Delim::Bracket),
+                    '}' => TokenKind::Close(Delim::Brace),
+                    c => TokenKind::Punct(c),
+                }
+            }
+        };
+        Ok(Some(Token {
+            kind,
+            start,
+            end: self.offset(),
+        }))
+    }
+
+    fn line_comment(&mut self) -> TokenKind {
+        let rest = self.rest();
+        let doc = (rest.starts_with("///") && !rest.starts_with("////")) || rest.starts_with("//!");
+        self.eat_while(|c| c != '\n');
+        TokenKind::LineComment { doc }
+    }
+
+    fn block_comment(&mut self, start: usize) -> Result<TokenKind, SyntaxError> {
+        let rest = self.rest();
+        let doc = (rest.starts_with("/**") && !rest.starts_with("/***") && !rest.starts_with("/**/"))
+            || rest.starts_with("/*!");
+        self.bump();
+        self.bump();
+        let mut depth = 1;
+        while depth > 0 {
+            match self.bump() {
+                Some('/') if self.peek(0) == Some('*') => {
+                    self.bump();
+                    depth += 1;
+                }
+                Some('*') if self.peek(0) == Some('/') => {
+                    self.bump();
+                    depth -= 1;
+                }
+                Some(_) => {}
+                None => {
+                    return Err(SyntaxError {
+                        offset: start,
+                        message: "unterminated block comment",
+                    })
+                }
+            }
+        }
+        Ok(TokenKind::BlockComment { doc })
+    }
+
+    // 開きクォートを読んだあとから、対応する閉じクォートまでを読みます。
+    fn quoted(&mut self, quote: char, start: usize) -> Result<TokenKind, SyntaxError> {
+        loop {
+            match self.bump() {
+                Some('\\') => {
+                    self.bump();
+                }
+                Some(c) if c == quote => break,
+                Some(_) => {}
+                None => {
+                    return Err(SyntaxError {
+                        offset: start,
+                        message: if quote == '"' {
+                            "unterminated double quote string"
+                        } else {
+                            "unterminated character literal"
+                        },
+                    })
+                }
+            }
+        }
+        self.literal_suffix();
+        Ok(TokenKind::Literal)
+    }
+
+    // `r"..."`, `r#"..."#`, `b"..."`, `br"..."`, `b'x'`, `c"..."`, `cr"..."` を読みます。
+    // これらのリテラルでなければ何も読まずに `false` を返します。
+    fn raw_or_prefixed_literal(&mut self, start: usize) -> Result<bool, SyntaxError> {
+        let rest = self.rest();
+        let prefix_len = if rest.starts_with("br") || rest.starts_with("cr") {
+            2
+        } else {
+            1
+        };
+        let is_raw = rest[..prefix_len].ends_with('r');
+        let after_prefix = &rest[prefix_len..];
+        if is_raw {
+            let hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+            if !after_prefix[hashes..].starts_with('"') {
+                return Ok(false);
+            }
+            for _ in 0..prefix_len + hashes + 1 {
+                self.bump();
+            }
+            let terminator = format!("\"{}", "#".repeat(hashes));
+            match self.rest().find(&terminator) {
+                Some(len) => {
+                    let char_count = self.rest()[..len + terminator.len()].chars().count();
+                    for _ in 0..char_count {
+                        self.bump();
+                    }
+                    self.literal_suffix();
+                    Ok(true)
+                }
+                None => Err(SyntaxError {
+                    offset: start,
+                    message: "unterminated raw string",
+                }),
+            }
+        } else if after_prefix.starts_with('"') {
+            self.bump();
+            self.bump();
+            self.quoted('"', start).map(|_| true)
+        } else if rest.starts_with("b'") {
+            self.bump();
+            self.bump();
+            self.quoted('\'', start).map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // `'` から始まる文字リテラルまたはライフタイムを読みます。
+    fn char_or_lifetime(&mut self, start: usize) -> Result<TokenKind, SyntaxError> {
+        self.bump();
+        match (self.peek(0), self.peek(1)) {
+            (Some('\\'), _) => self.quoted('\'', start),
+            (Some(_), Some('\'')) => {
+                self.bump();
+                self.bump();
+                self.literal_suffix();
+                Ok(TokenKind::Literal)
+            }
+            (Some(c), _) if is_ident_start(c) => {
+                self.eat_while(is_ident_continue);
+                Ok(TokenKind::Lifetime)
+            }
+            _ => Err(SyntaxError {
+                offset: start,
+                message: "unterminated character literal",
+            }),
+        }
+    }
+
+    fn number(&mut self) -> TokenKind {
+        let is_hex = self.rest().starts_with("0x") || self.rest().starts_with("0X");
+        loop {
+            self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            let last = self.src[..self.offset()].chars().next_back();
+            match (self.peek(0), self.peek(1)) {
+                // `1.5`（`1..2` や `1.max(2)` は別のトークンです。）
+                (Some('.'), Some(c)) if c.is_ascii_digit() => {
+                    self.bump();
+                }
+                // `1e-5`
+                (Some('+' | '-'), Some(c))
+                    if !is_hex && matches!(last, Some('e' | 'E')) && c.is_ascii_digit() =>
+                {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+        TokenKind::Literal
+    }
+
+    fn literal_suffix(&mut self) {
+        if self.peek(0).is_some_and(is_ident_start) {
+            self.eat_while(is_ident_continue);
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+// バイト位置から、1 始まりの行番号と列番号を求めます。
+pub fn line_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{line_column, tokenize, Delim, TokenKind},
+        test_case::test_case,
+    };
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.kind, &src[token.range()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_simple_tokens() {
+        assert_eq!(
+            kinds("pub mod a { fn f() {} }"),
+            vec![
+                (TokenKind::Ident, "pub"),
+                (TokenKind::Ident, "mod"),
+                (TokenKind::Ident, "a"),
+                (TokenKind::Open(Delim::Brace), "{"),
+                (TokenKind::Ident, "fn"),
+                (TokenKind::Ident, "f"),
+                (TokenKind::Open(Delim::Paren), "("),
+                (TokenKind::Close(Delim::Paren), ")"),
+                (TokenKind::Open(Delim::Brace), "{"),
+                (TokenKind::Close(Delim::Brace), "}"),
+                (TokenKind::Close(Delim::Brace), "}"),
+            ]
+        );
+    }
+
+    #[test_case("/// hi" => TokenKind::LineComment { doc: true }; "outer doc comments")]
+    #[test_case("//! hi" => TokenKind::LineComment { doc: true }; "inner doc comments")]
+    #[test_case("//// hi" => TokenKind::LineComment { doc: false }; "four slashes")]
+    #[test_case("// hi" => TokenKind::LineComment { doc: false }; "line comments")]
+    #[test_case("/** hi */" => TokenKind::BlockComment { doc: true }; "outer block doc comments")]
+    #[test_case("/*! hi */" => TokenKind::BlockComment { doc: true }; "inner block doc comments")]
+    #[test_case("/*** hi */" => TokenKind::BlockComment { doc: false }; "three stars")]
+    #[test_case("/**/" => TokenKind::BlockComment { doc: false }; "empty block comments")]
+    #[test_case("/* a /* nested */ b */" => TokenKind::BlockComment { doc: false }; "nested block comments")]
+    fn test_comments(src: &str) -> TokenKind {
+        let tokens = kinds(src);
+        assert_eq!(tokens.len(), 1);
+        tokens[0].0
+    }
+
+    #[test_case(r#""}""#; "string")]
+    #[test_case(r#""\"}""#; "string with escaped quote")]
+    #[test_case(r###"r#"}"# "###; "raw string")]
+    #[test_case(r###"r##"a "# b"##"###; "raw string with a fake terminator")]
+    #[test_case(r#"b"}""#; "byte string")]
+    #[test_case(r#"br"}""#; "raw byte string")]
+    #[test_case(r#"c"}""#; "c string")]
+    #[test_case("'}'"; "char")]
+    #[test_case(r"'\''"; "escaped quote char")]
+    #[test_case("b'}'"; "byte char")]
+    #[test_case("'あ'"; "multibyte char")]
+    #[test_case("1_000u64"; "integer with suffix")]
+    #[test_case("1.5e-3f64"; "float")]
+    #[test_case("0xffe"; "hex")]
+    fn test_literal(src: &str) {
+        assert_eq!(kinds(src.trim()), vec![(TokenKind::Literal, src.trim())]);
+    }
+
+    #[test]
+    fn test_lifetimes() {
+        assert_eq!(
+            kinds("&'a str 'label: loop {}")
+                .into_iter()
+                .filter(|(kind, _)| *kind == TokenKind::Lifetime)
+                .collect::<Vec<_>>(),
+            vec![(TokenKind::Lifetime, "'a"), (TokenKind::Lifetime, "'label")]
+        );
+    }
+
+    #[test]
+    fn test_range_is_not_float() {
+        assert_eq!(
+            kinds("1..2"),
+            vec![
+                (TokenKind::Literal, "1"),
+                (TokenKind::Punct('.'), "."),
+                (TokenKind::Punct('.'), "."),
+                (TokenKind::Literal, "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_raw_identifier() {
+        assert_eq!(kinds("r#match"), vec![(TokenKind::Ident, "r#match")]);
+    }
+
+    #[test_case("/* never closed"; "block comment")]
+    #[test_case("\"never closed"; "string")]
+    #[test_case("r#\"never closed\""; "raw string")]
+    fn test_unterminated(src: &str) {
+        assert!(tokenize(src).is_err());
+    }
+
+    #[test]
+    fn test_line_column() {
+        assert_eq!(line_column("ab\ncd", 0), (1, 1));
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+    }
+}
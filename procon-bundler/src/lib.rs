@@ -0,0 +1,115 @@
+// クレートとそのパス依存を、競技プログラミングの解答に貼り付けられるひとつのモジュールにまとめます。
+//
+// 入口は `Bundler` で、`procon-bundler` コマンドはその薄いラッパーです。
+//
+// ```
+// use procon_bundler::Bundler;
+// use std::path::Path;
+//
+// let bundle = Bundler::new().bundle("seg-tree", Path::new("libs/seg-tree"))?;
+// print!("{}", bundle.code);
+// ```
+mod bundle_crate;
+mod bundler;
+mod cfg;
+mod check;
+mod config_toml;
+mod diagnostic;
+mod error;
+mod expand;
+mod features;
+mod lexer;
+mod options;
+mod parse_item;
+mod parse_line;
+mod paths;
+mod prettify;
+mod resolver;
+mod shake;
+mod source_map;
+mod template;
+mod types;
+mod workspace;
+
+// `bundle_crate` と `format_crate_to_string` は、`Bundler` を通さずにクレートひとつをバンドルして整形します。
+pub use {
+    bundle_crate::bundle_crate,
+    bundler::{Bundle, Bundler},
+    config_toml::ConfigToml,
+    error::{BundlerError, Result},
+    features::FeatureRequest,
+    options::BundleOptions,
+    prettify::format_crate_to_string,
+    resolver::{CrateResolver, InMemoryResolver, OverlayResolver, Resolve},
+    source_map::SourceMap,
+    template::OutputConfig,
+    types::{Crate, DroppedAttr, Origin},
+};
+
+// コマンドと `manual_resolver!` のためのもので、安定した API ではありません。
+#[doc(hidden)]
+pub use {
+    check::{check_bundle, CompileError, DEFAULT_EDITION},
+    diagnostic::{render, Location},
+    expand::find_manifest_dir,
+    options::{discover_options, PartialOptions},
+    resolver::normalize_path,
+    template::check_template,
+    workspace::find_crate,
+};
+
+pub(crate) use types::{CrateInfo, ItemSpan, ItemSpanKind, Module, Span};
+
+// テスト用に、モジュールパスと内容の組からリゾルバを作ります。
+#[doc(hidden)]
+#[macro_export]
+macro_rules! manual_resolver {
+    (struct $resolver_name:ident {
+        $($module_path:expr => $content:expr),* $(,)?
+    }) => {
+        struct $resolver_name {}
+        impl $crate::Resolve for $resolver_name {
+            type B = ::std::io::BufReader<&'static [u8]>;
+            fn file_exists(&self, file_path: &::std::path::Path) -> bool {
+                file_path
+                    .to_str()
+                    .is_some_and(|path| [$($module_path),*].contains(&path))
+            }
+            fn resolve_file(&self, file_path: &::std::path::Path) -> $crate::Result<Self::B> {
+                // ファイルパスでも、それに対応するモジュールパスでも引けるようにします。
+                if self.file_exists(file_path) {
+                    return self.resolve(file_path);
+                }
+                let module_path = match file_path.to_str() {
+                    Some("lib.rs") => ::std::path::PathBuf::from("."),
+                    _ => ::std::path::Path::new(".").join(file_path.with_extension("")),
+                };
+                self.resolve(&module_path)
+            }
+            fn file_path(&self, module_path: &::std::path::Path) -> $crate::Result<::std::path::PathBuf> {
+                Ok(match module_path.to_str() {
+                    Some(".") => ::std::path::PathBuf::from("lib.rs"),
+                    _ => $crate::normalize_path(module_path).with_extension("rs"),
+                })
+            }
+            fn resolve(&self, module_path: &::std::path::Path) -> $crate::Result<Self::B> {
+                let path_str = module_path.to_str().ok_or_else(|| {
+                    $crate::BundlerError::InvalidPathConversion {
+                        path: module_path.to_path_buf(),
+                    }
+                })?;
+                let content = match path_str {
+                    $(
+                        $module_path => $content,
+                    )*
+                    _ => return Err($crate::BundlerError::ModuleFileNotFound {
+                        module_path: module_path.to_path_buf(),
+                        candidates: vec![module_path.to_path_buf()],
+                        location: None,
+                    }),
+                };
+                Ok(::std::io::BufReader::new(content.as_bytes()))
+            }
+        }
+    };
+}
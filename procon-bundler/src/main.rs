@@ -1,60 +1,19 @@
-mod bundle_crate;
-mod config_toml;
-mod error;
-mod parse_line;
-mod prettify;
-mod resolver;
-mod types;
-
-pub use {
-    bundle_crate::bundle_crate,
-    config_toml::ConfigToml,
-    error::{BundlerError, Result},
-    prettify::format_crate_to_string,
-    resolver::{CrateResolver, Resolve},
-    std::path::{Path, PathBuf},
-    types::{Crate, Module, Span},
+use {
+    clap::{Args, Parser, Subcommand},
+    procon_bundler::{
+        check_bundle, check_template, discover_options, find_crate, find_manifest_dir, render, Bundle, BundleOptions,
+        Bundler, BundlerError, CompileError, ConfigToml, FeatureRequest, OutputConfig, PartialOptions, SourceMap,
+        DEFAULT_EDITION,
+    },
+    std::{
+        fs,
+        io::{self, Write},
+        path::{Path, PathBuf},
+    },
 };
 
-use clap::{Parser, Subcommand};
-use std::fs;
-
-const TAB: &str = "    ";
-const TAB_LENGTH: usize = TAB.len();
-
-#[allow(unused_macros)]
-#[macro_export]
-macro_rules! manual_resolver {
-    (struct $resolver_name:ident {
-        $($module_path:expr => $content:expr),* $(,)?
-    }) => {
-        struct $resolver_name {}
-        impl $crate::resolver::Resolve for $resolver_name {
-            type B = ::std::io::BufReader<&'static [u8]>;
-            fn resolve(&self, module_path: &::std::path::Path) -> $crate::Result<Self::B> {
-                let path_str = module_path.to_str().ok_or_else(|| {
-                    $crate::BundlerError::InvalidPathConversion {
-                        path: module_path.to_path_buf(),
-                    }
-                })?;
-                let content = match path_str {
-                    $(
-                        $module_path => $content,
-                    )*
-                    _ => return Err($crate::BundlerError::ModuleFileNotFound {
-                        module_path: module_path.to_path_buf(),
-                        file_path: module_path.to_path_buf(),
-                        source: ::std::io::Error::new(
-                            ::std::io::ErrorKind::NotFound,
-                            format!("Mock resolver: path not found: {:?}", module_path)
-                        ),
-                    }),
-                };
-                Ok(::std::io::BufReader::new(content.as_bytes()))
-            }
-        }
-    };
-}
+// BundlerError は大きいので、関数の間では Box に包んで受け渡します。
+type Result<T> = std::result::Result<T, Box<BundlerError>>;
 
 #[derive(Parser)]
 #[command(name = "procon-bundler")]
@@ -72,6 +31,19 @@ enum Commands {
     Bundle {
         /// The path to the root of a crate to bundle
         crate_root: PathBuf,
+        /// Do not bundle path dependencies of the crate
+        #[arg(long)]
+        no_deps: bool,
+        #[command(flatten)]
+        features: FeatureArgs,
+        #[command(flatten)]
+        shake: ShakeArgs,
+        #[command(flatten)]
+        options: OptionsArgs,
+        #[command(flatten)]
+        source_map: SourceMapArgs,
+        #[command(flatten)]
+        buffer: BufferArgs,
     },
     /// Find and bundle a desired crate in a workspace
     Find {
@@ -79,7 +51,198 @@ enum Commands {
         workspace_root: PathBuf,
         /// The name of a crate to bundle (either chain-case or snake_case is okay)
         crate_name: String,
+        /// Do not bundle path dependencies of the crate
+        #[arg(long)]
+        no_deps: bool,
+        #[command(flatten)]
+        features: FeatureArgs,
+        #[command(flatten)]
+        shake: ShakeArgs,
+        #[command(flatten)]
+        options: OptionsArgs,
+        #[command(flatten)]
+        source_map: SourceMapArgs,
+        #[command(flatten)]
+        buffer: BufferArgs,
+    },
+    /// Expand a solution file by appending the library crates it uses
+    Expand {
+        /// The path to a solution file (e.g. src/main.rs of a contest crate)
+        solution: PathBuf,
+        /// The directory of Cargo.toml declaring the library crates as path dependencies
+        /// (defaults to the nearest ancestor of the solution containing Cargo.toml)
+        #[arg(long)]
+        manifest_dir: Option<PathBuf>,
+        /// Keep only the items reachable from the solution
+        #[arg(long)]
+        shake: bool,
+        #[command(flatten)]
+        features: FeatureArgs,
+        #[command(flatten)]
+        options: OptionsArgs,
+        #[command(flatten)]
+        source_map: SourceMapArgs,
+        #[command(flatten)]
+        buffer: BufferArgs,
+    },
+    /// Bundle a crate (or expand a solution file) and compile it with the local rustc to verify it
+    Check {
+        /// The path to the root of a crate, or to a solution file to expand
+        path: PathBuf,
+        /// Do not bundle path dependencies of the crate
+        #[arg(long)]
+        no_deps: bool,
+        /// The edition to compile with (defaults to the edition in Cargo.toml, or 2021)
+        #[arg(long)]
+        edition: Option<String>,
+        #[command(flatten)]
+        features: FeatureArgs,
+        #[command(flatten)]
+        options: OptionsArgs,
+        #[command(flatten)]
+        buffer: BufferArgs,
     },
+    /// Print the original file and line of lines in a bundled output
+    Locate {
+        /// The source map written with --source-map
+        source_map: PathBuf,
+        /// Line numbers (1-based) in the bundled output
+        #[arg(required = true)]
+        lines: Vec<usize>,
+    },
+}
+
+#[derive(Args)]
+struct FeatureArgs {
+    /// Space or comma separated list of features to activate
+    #[arg(short = 'F', long)]
+    features: Vec<String>,
+    /// Activate all available features
+    #[arg(long)]
+    all_features: bool,
+    /// Do not activate the `default` feature
+    #[arg(long)]
+    no_default_features: bool,
+}
+
+#[derive(Args)]
+struct ShakeArgs {
+    /// Keep only the items reachable from this item path (e.g. `seg_tree::SegTree`); can be repeated
+    #[arg(long, value_name = "ITEM_PATH")]
+    keep: Vec<String>,
+    /// Keep only the items reachable from this solution file
+    #[arg(long, value_name = "SOLUTION")]
+    keep_used_by: Option<PathBuf>,
+}
+
+// procon-bundler.toml などの設定を上書きする引数です。
+#[derive(Args)]
+struct OptionsArgs {
+    /// Keep doc comments instead of removing them
+    #[arg(long)]
+    keep_doc_comments: bool,
+    /// Width of one level of indentation (tabs in the source count as this many spaces)
+    #[arg(long, value_name = "WIDTH", value_parser = clap::value_parser!(u16).range(1..))]
+    tab_width: Option<u16>,
+    /// Header written above each crate, overriding `[package.metadata.procon-bundler]`;
+    /// placeholders: {name}, {package}, {version}, {commit}, {path}, {date}
+    #[arg(long, value_name = "TEMPLATE")]
+    header: Option<String>,
+    /// Footer written below each crate; the same placeholders as --header are available
+    #[arg(long, value_name = "TEMPLATE")]
+    footer: Option<String>,
+    /// Comma separated lints to allow at the top of each crate; pass no value to emit none
+    #[arg(long, value_name = "LINTS", value_delimiter = ',', num_args = 0..)]
+    allow: Option<Vec<String>>,
+}
+
+#[derive(Args)]
+struct SourceMapArgs {
+    /// Write a JSON source map from each output line to its original file and line
+    #[arg(long, value_name = "PATH")]
+    source_map: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BufferArgs {
+    /// Read the content of this source file from stdin instead of the disk (e.g. an unsaved editor buffer)
+    #[arg(long, value_name = "PATH")]
+    stdin_file: Option<PathBuf>,
+}
+
+impl BufferArgs {
+    fn apply(&self, bundler: Bundler) -> Result<Bundler> {
+        let Some(path) = &self.stdin_file else {
+            return Ok(bundler);
+        };
+        let content = io::read_to_string(io::stdin()).map_err(|e| BundlerError::StdinReadError { source: e })?;
+        Ok(bundler.buffer(path, content))
+    }
+}
+
+impl SourceMapArgs {
+    fn write(&self, source_map: &SourceMap) -> Result<()> {
+        match &self.source_map {
+            Some(path) => Ok(source_map.write(path)?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl OptionsArgs {
+    // `start_dir` から見つけた設定を、引数で上書きします。
+    fn to_options(&self, start_dir: &Path) -> Result<BundleOptions> {
+        for template in self.header.iter().chain(&self.footer) {
+            check_template(template, || None)?;
+        }
+        let overrides = PartialOptions {
+            remove_doc_comments: self.keep_doc_comments.then_some(false),
+            tab_width: self.tab_width.map(usize::from),
+            output: OutputConfig {
+                header: self.header.clone(),
+                footer: self.footer.clone(),
+                allow: self.allow.as_ref().map(|allow| {
+                    allow
+                        .iter()
+                        .map(|lint| lint.trim())
+                        .filter(|lint| !lint.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                }),
+            },
+        };
+        Ok(overrides.or(&discover_options(start_dir)?).into_options())
+    }
+}
+
+impl ShakeArgs {
+    // 刈り込みの起点です。空ならば刈り込みません。
+    fn roots(&self) -> Result<Vec<String>> {
+        let mut roots = self.keep.clone();
+        if let Some(path) = &self.keep_used_by {
+            roots.push(fs::read_to_string(path).map_err(|e| BundlerError::SolutionReadError {
+                path: path.clone(),
+                source: e,
+            })?);
+        }
+        Ok(roots)
+    }
+}
+
+impl FeatureArgs {
+    fn to_request(&self) -> FeatureRequest {
+        FeatureRequest {
+            features: self
+                .features
+                .iter()
+                .flat_map(|features| features.split([' ', ',']))
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            all_features: self.all_features,
+            default_features: !self.no_default_features,
+        }
+    }
 }
 
 fn main() {
@@ -88,63 +251,151 @@ fn main() {
     let result = match run(cli) {
         Ok(output) => output,
         Err(e) => {
-            eprintln!("Error: {}", e);
+            eprintln!("{}", render(&e));
             std::process::exit(1);
         }
     };
 
-    println!("{}", result);
+    // パイプの先が閉じていても panic しないよう、書き込みのエラーは無視します。
+    let _ = writeln!(io::stdout(), "{}", result);
 }
 
 fn run(cli: Cli) -> Result<String> {
-    let crate_root = match cli.command {
+    let (crate_root, crate_name, no_deps, features, shake, options, source_map, buffer) = match cli.command {
         Commands::Find {
             workspace_root,
             crate_name,
-        } => workspace_root.join("libs").join(crate_name),
-        Commands::Bundle { crate_root } => crate_root,
+            no_deps,
+            features,
+            shake,
+            options,
+            source_map,
+            buffer,
+        } => {
+            let member = find_crate(&workspace_root, &crate_name)?;
+            (member.root, member.name, no_deps, features, shake, options, source_map, buffer)
+        }
+        Commands::Bundle {
+            crate_root,
+            no_deps,
+            features,
+            shake,
+            options,
+            source_map,
+            buffer,
+        } => {
+            let crate_name = crate_name_of(&crate_root)?.to_owned();
+            (crate_root, crate_name, no_deps, features, shake, options, source_map, buffer)
+        }
+        Commands::Expand {
+            solution,
+            manifest_dir,
+            shake,
+            features,
+            options,
+            source_map,
+            buffer,
+        } => {
+            let manifest_dir = match manifest_dir {
+                Some(manifest_dir) => manifest_dir,
+                None => find_manifest_dir(&solution)?,
+            };
+            let bundler = Bundler::new()
+                .options(options.to_options(&manifest_dir)?)
+                .features(features.to_request())
+                .shake(shake);
+            let bundle = buffer.apply(bundler)?.expand(&solution, Some(&manifest_dir))?;
+            source_map.write(&bundle.source_map)?;
+            warn_dropped_attrs(&bundle);
+            return Ok(bundle.code);
+        }
+        Commands::Check {
+            path,
+            no_deps,
+            edition,
+            features,
+            options,
+            buffer,
+        } => {
+            let bundler = Bundler::new().features(features.to_request()).deps(!no_deps);
+            return check(&path, edition, buffer.apply(bundler)?, &options);
+        }
+        Commands::Locate { source_map, lines } => return locate(&SourceMap::read(&source_map)?, &lines),
     };
 
-    bundle_to_string(&crate_root)
+    let bundler = Bundler::new()
+        .options(options.to_options(&crate_root)?)
+        .features(features.to_request())
+        .deps(!no_deps)
+        .keep(shake.roots()?);
+    let bundle = buffer.apply(bundler)?.bundle(&crate_name, &crate_root)?;
+    source_map.write(&bundle.source_map)?;
+    warn_dropped_attrs(&bundle);
+    Ok(bundle.code)
 }
 
-fn bundle_to_string(path: &Path) -> Result<String> {
+// モジュールにつけられないために消した、クレートのルートの内部属性を標準エラー出力に報告します。
+fn warn_dropped_attrs(bundle: &Bundle) {
+    for dropped in &bundle.dropped_attrs {
+        eprintln!(
+            "warning: removed crate-level attribute `{}` ({}:{})",
+            dropped.attr,
+            dropped.origin.path.display(),
+            dropped.origin.line
+        );
+    }
+}
+
+// `path` がファイルならば解答として展開し、ディレクトリならばクレートとしてバンドルして、rustc でコンパイルします。
+// エラーは元のファイルの位置に戻して報告します。
+fn check(path: &Path, edition: Option<String>, bundler: Bundler, options: &OptionsArgs) -> Result<String> {
+    let is_solution = path.is_file();
+    let manifest_dir = if is_solution { find_manifest_dir(path)? } else { path.to_path_buf() };
+    let bundler = bundler.options(options.to_options(&manifest_dir)?);
+    let bundle = if is_solution {
+        bundler.expand(path, Some(&manifest_dir))?
+    } else {
+        bundler.bundle(crate_name_of(path)?, path)?
+    };
+    warn_dropped_attrs(&bundle);
+    let edition = match edition {
+        Some(edition) => edition,
+        None => ConfigToml::from_crate_root(&manifest_dir)?
+            .edition
+            .unwrap_or_else(|| DEFAULT_EDITION.to_owned()),
+    };
+    let errors = check_bundle(&bundle.code, &bundle.source_map, &edition, !is_solution)?;
+    if errors.is_empty() {
+        Ok(format!("The bundle compiles with rustc (edition {})", edition))
+    } else {
+        Err(Box::new(BundlerError::CheckFailed {
+            report: errors.iter().map(CompileError::to_string).collect::<Vec<_>>().join("\n\n"),
+            errors: errors.len(),
+        }))
+    }
+}
+
+// 出力の行番号ごとに、`12 -> src/lib.rs:3` のように元の位置を答えます。
+fn locate(source_map: &SourceMap, lines: &[usize]) -> Result<String> {
+    let answers = lines
+        .iter()
+        .map(|&line| {
+            Ok(match source_map.locate(line)? {
+                Some(origin) => format!("{} -> {}:{}", line, origin.path.display(), origin.line),
+                None => format!("{} -> (generated by procon-bundler)", line),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(answers.join("\n"))
+}
+
+fn crate_name_of(path: &Path) -> Result<&str> {
     let name = path
         .file_stem()
         .ok_or_else(|| BundlerError::InvalidFileStem {
             path: path.to_path_buf(),
         })?;
-    let name = name
-        .to_str()
-        .ok_or_else(|| BundlerError::InvalidPathConversion {
-            path: path.to_path_buf(),
-        })?;
-    
-    let resolver = CrateResolver::new(path.to_path_buf());
-    let config_path = path.join("Cargo.toml");
-    
-    let buf = fs::read_to_string(&config_path).map_err(|e| {
-        BundlerError::CargoTomlReadError {
-            path: config_path,
-            source: e,
-        }
-    })?;
-    
-    let config = ConfigToml::new(&buf)?;
-    let my_crate = bundle_crate(name, resolver, config)?;
-    Ok(format_crate_to_string(my_crate))
-}
-
-#[cfg(test)]
-mod tests {
-    use {super::bundle_to_string, difference::assert_diff, std::path::Path};
-
-    #[test]
-    fn test_bundle_by_crate_path() {
-        let result = bundle_to_string(Path::new("../procon-bundler-sample")).unwrap();
-        let expected = include_str!("../../procon-bundler-sample-result/src/lib.rs");
-        let result = result.as_ref();
-        let expected = expected[..expected.len() - 1].as_ref();
-        assert_diff!(result, expected, "\n", 0);
-    }
+    Ok(name.to_str().ok_or_else(|| BundlerError::InvalidPathConversion {
+        path: path.to_path_buf(),
+    })?)
 }
@@ -0,0 +1,232 @@
+use {
+    crate::{
+        config_toml::{parse_toml, Manifest},
+        template::OutputConfig,
+        BundlerError, Result,
+    },
+    std::{fs, path::Path},
+    toml::{Table, Value},
+};
+
+// 設定ファイルの名前です。
+pub const OPTIONS_FILE: &str = "procon-bundler.toml";
+
+const WORKSPACE_METADATA_TABLE: &str = "workspace.metadata.procon-bundler";
+
+// 設定ファイルと `[workspace.metadata.procon-bundler]` に書けるキーです。
+pub const OPTION_KEYS: &[&str] = &["remove-doc-comments", "tab-width", "header", "footer", "allow"];
+
+// バンドルと出力の設定です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BundleOptions {
+    // doc comments を消すかどうか
+    pub remove_doc_comments: bool,
+    // インデントの幅（ソースのタブもこの幅とみなします。）
+    pub tab_width: usize,
+    // ヘッダー、フッター、allow 属性（各クレートの Cargo.toml の設定よりも優先します。）
+    pub output: OutputConfig,
+}
+
+impl Default for BundleOptions {
+    fn default() -> Self {
+        Self {
+            remove_doc_comments: true,
+            tab_width: 4,
+            output: OutputConfig::default(),
+        }
+    }
+}
+
+// 設定ファイルやコマンドラインで指定された、一部だけの設定です。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialOptions {
+    pub remove_doc_comments: Option<bool>,
+    pub tab_width: Option<usize>,
+    pub output: OutputConfig,
+}
+
+impl PartialOptions {
+    // `self` で指定されていないものを `other` で補います。
+    pub fn or(&self, other: &Self) -> Self {
+        Self {
+            remove_doc_comments: self.remove_doc_comments.or(other.remove_doc_comments),
+            tab_width: self.tab_width.or(other.tab_width),
+            output: self.output.or(&other.output),
+        }
+    }
+    // 指定されていないものを既定値にします。
+    pub fn into_options(self) -> BundleOptions {
+        let default = BundleOptions::default();
+        BundleOptions {
+            remove_doc_comments: self.remove_doc_comments.unwrap_or(default.remove_doc_comments),
+            tab_width: self.tab_width.unwrap_or(default.tab_width),
+            output: self.output,
+        }
+    }
+}
+
+// `start_dir` とその祖先のディレクトリから設定を探し、近いものを優先して合わせます。
+//
+// * 各ディレクトリの procon-bundler.toml
+// * 各ディレクトリの Cargo.toml の `[workspace.metadata.procon-bundler]`
+//
+// 同じディレクトリに両方あれば、procon-bundler.toml を優先します。
+pub fn discover_options(start_dir: &Path) -> Result<PartialOptions> {
+    let start_dir = start_dir.canonicalize().unwrap_or_else(|_| start_dir.to_path_buf());
+    let mut result = PartialOptions::default();
+    for dir in start_dir.ancestors() {
+        let options_path = dir.join(OPTIONS_FILE);
+        if options_path.is_file() {
+            let content = read_to_string(&options_path)?;
+            result = result.or(&parse_options(&content, &options_path)?);
+        }
+        let config_path = dir.join("Cargo.toml");
+        if config_path.is_file() {
+            let content = read_to_string(&config_path)?;
+            result = result.or(&parse_workspace_metadata(&content, &config_path)?);
+        }
+    }
+    Ok(result)
+}
+
+// procon-bundler.toml をパースします。`path` はエラーの表示に使います。
+pub fn parse_options(content: &str, path: &Path) -> Result<PartialOptions> {
+    let value = parse_toml(content, path)?;
+    let Value::Table(table) = value else {
+        return Err(BundlerError::TomlNotTable {
+            path: path.to_path_buf(),
+        });
+    };
+    from_table(&Manifest { path, content }, "", &table)
+}
+
+// Cargo.toml の `[workspace.metadata.procon-bundler]` をパースします。なければ何も指定しません。
+pub fn parse_workspace_metadata(content: &str, path: &Path) -> Result<PartialOptions> {
+    let value = parse_toml(content, path)?;
+    let manifest = Manifest { path, content };
+    match value
+        .get("workspace")
+        .and_then(|workspace| workspace.get("metadata"))
+        .and_then(|metadata| metadata.get("procon-bundler"))
+    {
+        None => Ok(PartialOptions::default()),
+        Some(Value::Table(table)) => from_table(&manifest, WORKSPACE_METADATA_TABLE, table),
+        Some(_) => Err(BundlerError::MetadataNotTable {
+            location: manifest.locate(WORKSPACE_METADATA_TABLE, None),
+        }),
+    }
+}
+
+fn read_to_string(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| BundlerError::OptionsReadError {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn from_table(manifest: &Manifest, table_name: &str, table: &Table) -> Result<PartialOptions> {
+    if let Some(key) = table.keys().find(|key| !OPTION_KEYS.contains(&key.as_str())) {
+        return Err(BundlerError::UnknownOption {
+            key: key.clone(),
+            location: manifest.locate(table_name, Some(key)),
+        });
+    }
+    let invalid = |key: &str, expected: &str, value: &Value| BundlerError::InvalidOptionValue {
+        key: key.to_owned(),
+        message: format!("expected {}, found {}", expected, value),
+        location: manifest.locate(table_name, Some(key)),
+    };
+    let remove_doc_comments = table
+        .get("remove-doc-comments")
+        .map(|value| value.as_bool().ok_or_else(|| invalid("remove-doc-comments", "a boolean", value)))
+        .transpose()?;
+    let tab_width = table
+        .get("tab-width")
+        .map(|value| {
+            value
+                .as_integer()
+                .and_then(|width| usize::try_from(width).ok())
+                .filter(|&width| width > 0)
+                .ok_or_else(|| invalid("tab-width", "a positive integer", value))
+        })
+        .transpose()?;
+    Ok(PartialOptions {
+        remove_doc_comments,
+        tab_width,
+        output: manifest.output_config(table_name, table)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{discover_options, parse_options, parse_workspace_metadata, PartialOptions, OPTIONS_FILE},
+        crate::{template::OutputConfig, BundlerError},
+        std::path::Path,
+    };
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse_options(
+            concat!(
+                "remove-doc-comments = false\n",
+                "tab-width = 2\n",
+                "header = \"// {name}\"\n",
+                "allow = []\n",
+            ),
+            Path::new(OPTIONS_FILE),
+        )
+        .unwrap();
+        let expected = PartialOptions {
+            remove_doc_comments: Some(false),
+            tab_width: Some(2),
+            output: OutputConfig {
+                header: Some("// {name}".to_owned()),
+                footer: None,
+                allow: Some(Vec::new()),
+            },
+        };
+        assert_eq!(options, expected);
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let err = parse_options("tab-width = 2\ntab_width = 2\n", Path::new(OPTIONS_FILE)).unwrap_err();
+        assert!(matches!(err, BundlerError::UnknownOption { ref key, .. } if key == "tab_width"));
+        assert_eq!(err.location().map(|location| location.line), Some(2));
+    }
+
+    #[test]
+    fn test_invalid_tab_width() {
+        let err = parse_options("tab-width = 0\n", Path::new(OPTIONS_FILE)).unwrap_err();
+        assert!(matches!(err, BundlerError::InvalidOptionValue { ref key, .. } if key == "tab-width"));
+    }
+
+    #[test]
+    fn test_parse_workspace_metadata() {
+        let options = parse_workspace_metadata(
+            concat!(
+                "[workspace]\n",
+                "members = [\"a\"]\n",
+                "\n",
+                "[workspace.metadata.procon-bundler]\n",
+                "footer = \"\"\n",
+            ),
+            Path::new("Cargo.toml"),
+        )
+        .unwrap();
+        assert_eq!(options.output.footer.as_deref(), Some(""));
+        let options = parse_workspace_metadata("[package]\nname = \"a\"\n", Path::new("Cargo.toml")).unwrap();
+        assert_eq!(options, PartialOptions::default());
+    }
+
+    // 近いディレクトリの設定を優先し、同じディレクトリでは procon-bundler.toml を優先します。
+    #[test]
+    fn test_discover_options() {
+        let options = discover_options(Path::new("../testcase/options/workspace/crates/inner")).unwrap();
+        assert_eq!(options.tab_width, Some(2));
+        assert_eq!(options.remove_doc_comments, Some(false));
+        assert_eq!(options.output.header.as_deref(), Some("// {name} from procon-bundler.toml"));
+        assert_eq!(options.output.allow, Some(vec!["dead_code".to_owned()]));
+    }
+}
@@ -0,0 +1,612 @@
+use {
+    crate::{
+        cfg,
+        lexer::{tokenize, Delim, SyntaxError, Token, TokenKind},
+        types::ItemSpanKind,
+    },
+    std::ops::Range,
+};
+
+// ソースとそのトークン列、および括弧の対応です。
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tokens<'a> {
+    pub src: &'a str,
+    pub tokens: Vec<Token>,
+    // 開き括弧のトークン番号 → 対応する閉じ括弧のトークン番号
+    matching: Vec<usize>,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(src: &'a str) -> Result<Self, SyntaxError> {
+        let tokens = tokenize(src)?;
+        let mut matching = vec![usize::MAX; tokens.len()];
+        let mut stack = Vec::<(usize, Delim)>::new();
+        for (i, token) in tokens.iter().enumerate() {
+            match token.kind {
+                TokenKind::Open(delim) => stack.push((i, delim)),
+                TokenKind::Close(delim) => match stack.pop() {
+                    Some((j, open)) if open == delim => {
+                        matching[j] = i;
+                        matching[i] = j;
+                    }
+                    _ => {
+                        return Err(SyntaxError {
+                            offset: token.start,
+                            message: "unexpected closing delimiter",
+                        })
+                    }
+                },
+                _ => {}
+            }
+        }
+        if let Some(&(i, _)) = stack.last() {
+            return Err(SyntaxError {
+                offset: tokens[i].start,
+                message: "unclosed delimiter",
+            });
+        }
+        Ok(Self {
+            src,
+            tokens,
+            matching,
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+    pub fn text(&self, i: usize) -> &'a str {
+        &self.src[self.tokens[i].range()]
+    }
+    // 括弧のトークン番号から、対応する括弧のトークン番号を返します。
+    pub fn matching(&self, i: usize) -> usize {
+        self.matching[i]
+    }
+    // トークン範囲の、ソース上でのバイト範囲です。
+    pub fn span(&self, range: Range<usize>) -> Range<usize> {
+        self.tokens[range.start].start..self.tokens[range.end - 1].end
+    }
+    // コメントを飛ばして、`i` 以降で最初のトークン番号を返します。
+    pub fn skip_comments(&self, mut i: usize, end: usize) -> usize {
+        while i < end && self.tokens[i].is_comment() {
+            i += 1;
+        }
+        i
+    }
+    fn is_ident(&self, i: usize, ident: &str) -> bool {
+        self.tokens[i].kind == TokenKind::Ident && self.text(i) == ident
+    }
+    fn is_punct(&self, i: usize, c: char) -> bool {
+        self.tokens[i].kind == TokenKind::Punct(c)
+    }
+    // トークン範囲を、コメントと空白を除いて連結します。
+    pub fn compact(&self, range: Range<usize>) -> String {
+        range
+            .filter(|&i| !self.tokens[i].is_comment())
+            .map(|i| self.text(i))
+            .collect()
+    }
+    // 属性が `#[cfg(test)]` や `#[cfg(all(test, ...))]` のように、テストでしか有効にならない cfg であるかを判定します。
+    pub fn is_cfg_test(&self, attr: Range<usize>) -> bool {
+        cfg::is_cfg_test(self, attr)
+    }
+    // アイテムがテスト専用であるかを判定します。
+    // cfg の属性が複数あるときはすべてを満たす必要があるので、どれか一つがテスト専用ならばテスト専用です。
+    pub fn is_test_only(&self, item: &Item) -> bool {
+        item.attrs
+            .iter()
+            .any(|attr| self.is_cfg_test(attr.clone()) || self.compact(attr.clone()) == "#[test]")
+    }
+    // アイテムが `name! { ... }` の形のマクロ呼出し（`macro_rules!` を除く）であれば、
+    // マクロの名前（パスの最後の部分）と、中括弧の内側のトークン範囲を返します。
+    pub fn macro_block(&self, item: &Item) -> Option<(&'a str, Range<usize>)> {
+        let significant = (item.head..item.end)
+            .filter(|&i| !self.tokens[i].is_comment())
+            .collect::<Vec<_>>();
+        let bang = significant.iter().position(|&i| self.is_punct(i, '!'))?;
+        let (path, rest) = significant.split_at(bang);
+        let is_path = path.iter().enumerate().all(|(k, &i)| match self.tokens[i].kind {
+            TokenKind::Ident => k == 0 || self.is_punct(path[k - 1], ':'),
+            TokenKind::Punct(':') => true,
+            _ => false,
+        });
+        let &name = path.last()?;
+        let &open = rest.get(1)?;
+        if !is_path
+            || self.tokens[name].kind != TokenKind::Ident
+            || self.is_ident(name, "macro_rules")
+            || self.tokens[open].kind != TokenKind::Open(Delim::Brace)
+        {
+            return None;
+        }
+        // 閉じ括弧の後ろには `;` だけを許します。
+        let close = self.matching(open);
+        match rest[rest.partition_point(|&i| i <= close)..] {
+            [] => Some((self.text(name), open + 1..close)),
+            [semi] if self.is_punct(semi, ';') => Some((self.text(name), open + 1..close)),
+            _ => None,
+        }
+    }
+    // アイテムの種類と名前、可視性を返します。
+    pub fn item_signature(&self, item: &Item) -> (ItemSpanKind, Option<String>, Option<String>) {
+        if item.kind == ItemKind::InnerAttr || item.head >= item.end {
+            return (ItemSpanKind::Attrs, None, None);
+        }
+        let end = item.end;
+        let next = |i: usize| self.skip_comments(i + 1, end);
+        let mut i = item.head;
+        let mut vis = None;
+        if self.is_ident(i, "pub") {
+            let mut vis_end = i;
+            let open = next(i);
+            if open < end && self.tokens[open].kind == TokenKind::Open(Delim::Paren) {
+                vis_end = self.matching(open);
+            }
+            // `pub(in crate::a)` の空白を消さないよう、書かれたとおりに（空白はひとつにまとめて）残します。
+            vis = Some(self.src[self.span(i..vis_end + 1)].split_whitespace().collect::<Vec<_>>().join(" "));
+            i = next(vis_end);
+        }
+        // 修飾子を読み飛ばします。
+        while i < end {
+            match self.text(i) {
+                "default" | "unsafe" | "async" | "auto" => i = next(i),
+                "const" if next(i) < end && ["fn", "unsafe", "async", "extern"].contains(&self.text(next(i))) => {
+                    i = next(i)
+                }
+                "extern" if next(i) < end && self.tokens[next(i)].kind == TokenKind::Literal => i = next(next(i)),
+                "extern" if next(i) < end && self.is_ident(next(i), "fn") => i = next(i),
+                _ => break,
+            }
+        }
+        if i >= end {
+            return (ItemSpanKind::Other, None, vis);
+        }
+        let name_at = |j: usize| (j < end && self.tokens[j].kind == TokenKind::Ident).then(|| self.text(j).to_owned());
+        let (kind, name) = match self.text(i) {
+            "fn" => (ItemSpanKind::Fn, name_at(next(i))),
+            "struct" => (ItemSpanKind::Struct, name_at(next(i))),
+            "enum" => (ItemSpanKind::Enum, name_at(next(i))),
+            "trait" => (ItemSpanKind::Trait, name_at(next(i))),
+            "type" => (ItemSpanKind::Type, name_at(next(i))),
+            "impl" => (ItemSpanKind::Impl, None),
+            "use" => (ItemSpanKind::Use, None),
+            "const" => (ItemSpanKind::Const, name_at(next(i))),
+            "static" => {
+                let j = next(i);
+                let j = if j < end && self.is_ident(j, "mut") { next(j) } else { j };
+                (ItemSpanKind::Static, name_at(j))
+            }
+            "macro_rules" if next(i) < end && self.is_punct(next(i), '!') => {
+                (ItemSpanKind::MacroRules, name_at(next(next(i))))
+            }
+            _ => (ItemSpanKind::Other, None),
+        };
+        (kind, name, vis)
+    }
+    // 属性が `#[path = "..."]` であれば、その値を返します。
+    pub fn path_attr(&self, attr: Range<usize>) -> Option<String> {
+        let significant = attr
+            .filter(|&i| !self.tokens[i].is_comment())
+            .collect::<Vec<_>>();
+        match significant[..] {
+            [_, _, path, eq, value, _]
+                if self.is_ident(path, "path")
+                    && self.is_punct(eq, '=')
+                    && self.tokens[value].kind == TokenKind::Literal =>
+            {
+                string_literal_value(self.text(value))
+            }
+            _ => None,
+        }
+    }
+}
+
+// 文字列リテラルの値を返します。（エスケープは `\\` と `\"` だけ解釈します。）
+fn string_literal_value(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let raw = raw.trim_matches('#');
+        return Some(raw.strip_prefix('"')?.strip_suffix('"')?.to_owned());
+    }
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.replace("\\\\", "\\").replace("\\\"", "\""))
+}
+
+// モジュール直下のアイテムです。
+#[derive(Clone, Debug, Hash, PartialEq)]
+pub struct Item {
+    // 外部属性 `#[...]` のトークン範囲
+    pub attrs: Vec<Range<usize>>,
+    // 属性を除いた本体の先頭のトークン番号
+    pub head: usize,
+    // 末尾の次のトークン番号
+    pub end: usize,
+    pub kind: ItemKind,
+}
+
+impl Item {
+    // 属性を含めた、先頭のトークン番号です。
+    pub fn start(&self) -> usize {
+        self.attrs.first().map_or(self.head, |attr| attr.start)
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq)]
+pub enum ItemKind {
+    // `mod name;` または `mod name { ... }`（`body` は中括弧の内側のトークン範囲）
+    Module {
+        name: String,
+        body: Option<Range<usize>>,
+    },
+    // `impl ... { ... }` または `trait ... { ... }`（`body` は中括弧の内側のトークン範囲）
+    ImplOrTrait { body: Range<usize> },
+    // `#![...]`
+    InnerAttr,
+    Other,
+}
+
+// トークン範囲を、モジュール直下のアイテムに区切ります。
+//
+// アイテムの終わりは、`const`, `static`, `use`, `type`, `extern crate`, `let` ならば深さ 0 の `;`、
+// それ以外は深さ 0 の `;` または最初の `{ ... }`（直後に `;` があればそれも含めます。）です。
+// アイテムとして解釈できない部分は、範囲の終わりまでをひとつの `Other` とします。
+pub fn parse_items(tokens: &Tokens, range: Range<usize>) -> Vec<Item> {
+    let end = range.end;
+    let mut items = Vec::new();
+    let mut i = tokens.skip_comments(range.start, end);
+    while i < end {
+        // 内部属性
+        if tokens.is_punct(i, '#') {
+            let bang = tokens.skip_comments(i + 1, end);
+            let open = tokens.skip_comments(bang + 1, end);
+            if bang < end
+                && tokens.is_punct(bang, '!')
+                && open < end
+                && tokens.tokens[open].kind == TokenKind::Open(Delim::Bracket)
+            {
+                let item_end = tokens.matching(open) + 1;
+                items.push(Item {
+                    attrs: Vec::new(),
+                    head: i,
+                    end: item_end,
+                    kind: ItemKind::InnerAttr,
+                });
+                i = tokens.skip_comments(item_end, end);
+                continue;
+            }
+        }
+
+        // 外部属性
+        let mut attrs = Vec::new();
+        loop {
+            let open = tokens.skip_comments(i + 1, end);
+            if i < end
+                && tokens.is_punct(i, '#')
+                && open < end
+                && tokens.tokens[open].kind == TokenKind::Open(Delim::Bracket)
+            {
+                let attr_end = tokens.matching(open) + 1;
+                attrs.push(i..attr_end);
+                i = tokens.skip_comments(attr_end, end);
+            } else {
+                break;
+            }
+        }
+        let head = i;
+        let (item_end, kind) = parse_item_body(tokens, head, end);
+        items.push(Item {
+            attrs,
+            head,
+            end: item_end,
+            kind,
+        });
+        i = tokens.skip_comments(item_end, end);
+    }
+    items
+}
+
+// 属性を除いたアイテムの本体を読み、末尾の次のトークン番号と種類を返します。
+fn parse_item_body(tokens: &Tokens, head: usize, end: usize) -> (usize, ItemKind) {
+    let next = |i: usize| tokens.skip_comments(i + 1, end);
+
+    // 可視性
+    let mut i = head;
+    if i < end && tokens.is_ident(i, "pub") {
+        i = next(i);
+        if i < end && tokens.tokens[i].kind == TokenKind::Open(Delim::Paren) {
+            i = next(tokens.matching(i));
+        }
+    }
+
+    // モジュール
+    if i < end && tokens.is_ident(i, "mod") {
+        let name = next(i);
+        let after = next(name);
+        if after < end && tokens.tokens[name].kind == TokenKind::Ident {
+            let name_text = tokens.text(name).to_owned();
+            if tokens.is_punct(after, ';') {
+                return (
+                    after + 1,
+                    ItemKind::Module {
+                        name: name_text,
+                        body: None,
+                    },
+                );
+            }
+            if tokens.tokens[after].kind == TokenKind::Open(Delim::Brace) {
+                let close = tokens.matching(after);
+                return (
+                    close + 1,
+                    ItemKind::Module {
+                        name: name_text,
+                        body: Some(after + 1..close),
+                    },
+                );
+            }
+        }
+    }
+
+    let is_impl_or_trait = {
+        let mut j = i;
+        while j < end && ["unsafe", "auto", "default"].contains(&tokens.text(j)) {
+            j = next(j);
+        }
+        j < end && (tokens.is_ident(j, "impl") || tokens.is_ident(j, "trait"))
+    };
+    let ends_with_semi = i < end
+        && match tokens.text(i) {
+            "const" => {
+                let j = next(i);
+                !(j < end && ["fn", "unsafe", "async", "extern"].contains(&tokens.text(j)))
+            }
+            "extern" => {
+                let j = next(i);
+                j < end && tokens.is_ident(j, "crate")
+            }
+            "static" | "use" | "type" | "let" => true,
+            _ => false,
+        };
+    while i < end {
+        match tokens.tokens[i].kind {
+            // 終端のないアイテムの後ろにモジュールが続いていれば、その手前で区切ります。
+            _ if i > head && is_module_ahead(tokens, i, end) => return (i, ItemKind::Other),
+            TokenKind::Punct(';') => return (i + 1, ItemKind::Other),
+            TokenKind::Open(Delim::Brace) if !ends_with_semi => {
+                let after = next(tokens.matching(i));
+                let item_end = if after < end && tokens.is_punct(after, ';') {
+                    after + 1
+                } else {
+                    tokens.matching(i) + 1
+                };
+                let kind = if is_impl_or_trait {
+                    ItemKind::ImplOrTrait {
+                        body: i + 1..tokens.matching(i),
+                    }
+                } else {
+                    ItemKind::Other
+                };
+                return (item_end, kind);
+            }
+            TokenKind::Open(_) => i = tokens.matching(i) + 1,
+            _ => i += 1,
+        }
+    }
+    (end, ItemKind::Other)
+}
+
+// `i` から、属性と可視性を除いて `mod name;` または `mod name {` が始まるかを判定します。
+fn is_module_ahead(tokens: &Tokens, mut i: usize, end: usize) -> bool {
+    let next = |i: usize| tokens.skip_comments(i + 1, end);
+    while i < end && tokens.is_punct(i, '#') {
+        let open = next(i);
+        if open < end && tokens.tokens[open].kind == TokenKind::Open(Delim::Bracket) {
+            i = next(tokens.matching(open));
+        } else {
+            return false;
+        }
+    }
+    if i < end && tokens.is_ident(i, "pub") {
+        i = next(i);
+        if i < end && tokens.tokens[i].kind == TokenKind::Open(Delim::Paren) {
+            i = next(tokens.matching(i));
+        }
+    }
+    let name = next(i);
+    let after = next(name);
+    after < end
+        && tokens.is_ident(i, "mod")
+        && tokens.tokens[name].kind == TokenKind::Ident
+        && (tokens.is_punct(after, ';') || tokens.tokens[after].kind == TokenKind::Open(Delim::Brace))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_items, ItemKind, Tokens},
+        crate::types::ItemSpanKind,
+        test_case::test_case,
+    };
+
+    fn item_texts(src: &str) -> Vec<&str> {
+        let tokens = Tokens::new(src).unwrap();
+        parse_items(&tokens, 0..tokens.len())
+            .into_iter()
+            .map(|item| &src[tokens.span(item.start()..item.end)])
+            .collect()
+    }
+
+    fn module_name(src: &str) -> Option<String> {
+        let tokens = Tokens::new(src).unwrap();
+        match &parse_items(&tokens, 0..tokens.len())[..] {
+            [item] => match &item.kind {
+                ItemKind::Module { name, .. } => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test_case("mod a;" => Some("a".to_owned()); "simple mod decl")]
+    #[test_case("pub mod a;" => Some("a".to_owned()); "pub mod decl")]
+    #[test_case("pub(crate) mod a;" => Some("a".to_owned()); "pub(crate) mod decl")]
+    #[test_case("pub(super::super) mod a;" => Some("a".to_owned()); "pub(path) mod decl")]
+    #[test_case("    mod a;" => Some("a".to_owned()); "with leading spaces")]
+    #[test_case("use a;" => None; "fake(use decl)")]
+    #[test_case("mod ab;" => Some("ab".to_owned()); "two-char name")]
+    #[test_case("mod a1;" => Some("a1".to_owned()); "contains digit")]
+    #[test_case("mod a_;" => Some("a_".to_owned()); "contains under")]
+    #[test_case("mod _a;" => Some("_a".to_owned()); "starts with under")]
+    #[test_case("pub ( path  ::hey )  mod  __my_42_fn  ; " => Some("__my_42_fn".to_owned()); "complicated")]
+    #[test_case("mod\n    a\n;" => Some("a".to_owned()); "across lines")]
+    #[test_case("mod a {" => None; "unclosed is not a module")]
+    #[test_case("mod a {}" => Some("a".to_owned()); "simple mod block")]
+    #[test_case("    pub ( foo   ) mod  bar  { } " => Some("bar".to_owned()); "complicated mod block")]
+    fn test_parse_module(src: &str) -> Option<String> {
+        if Tokens::new(src).is_err() {
+            return None;
+        }
+        module_name(src)
+    }
+
+    #[test]
+    fn test_one_line_module_body() {
+        let src = "mod a { fn f() {} } fn g() {}";
+        let tokens = Tokens::new(src).unwrap();
+        let items = parse_items(&tokens, 0..tokens.len());
+        match &items[0].kind {
+            ItemKind::Module {
+                body: Some(body), ..
+            } => assert_eq!(&src[tokens.span(body.clone())], "fn f() {}"),
+            _ => panic!("expected an inline module"),
+        }
+        assert_eq!(item_texts(src), vec!["mod a { fn f() {} }", "fn g() {}"]);
+    }
+
+    #[test]
+    fn test_brace_in_literals_and_comments() {
+        let src = concat!(
+            "mod a {\n",
+            "    const S: &str = r#\"}\"#;\n",
+            "    const C: char = '}';\n",
+            "    /* } */\n",
+            "}\n",
+            "fn f() {}\n",
+        );
+        assert_eq!(item_texts(src).len(), 2);
+    }
+
+    #[test]
+    fn test_items() {
+        let src = concat!(
+            "//! crate doc\n",
+            "#![allow(dead_code)]\n",
+            "use std::io;\n",
+            "#[derive(Debug)]\n",
+            "/// doc\n",
+            "struct A(i32);\n",
+            "const X: [u8; 2] = { [0; 2] };\n",
+            "const fn f() -> usize { 0 }\n",
+            "impl A where A: Sized { fn g() {} }\n",
+            "macro_rules! m { () => {}; }\n",
+            "m! {}\n",
+            "m!();\n",
+            "hi,\n",
+            "#[cfg(test)]\n",
+            "mod a;\n",
+            "hello!\n",
+        );
+        assert_eq!(
+            item_texts(src),
+            vec![
+                "#![allow(dead_code)]",
+                "use std::io;",
+                "#[derive(Debug)]\n/// doc\nstruct A(i32);",
+                "const X: [u8; 2] = { [0; 2] };",
+                "const fn f() -> usize { 0 }",
+                "impl A where A: Sized { fn g() {} }",
+                "macro_rules! m { () => {}; }",
+                "m! {}",
+                "m!();",
+                "hi,",
+                "#[cfg(test)]\nmod a;",
+                "hello!",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_impl_or_trait_body() {
+        let src = "unsafe impl<T> Send for A<T> { #[cfg(test)] fn f() {} fn g(); }";
+        let tokens = Tokens::new(src).unwrap();
+        let items = parse_items(&tokens, 0..tokens.len());
+        let ItemKind::ImplOrTrait { body } = &items[0].kind else {
+            panic!("expected an impl block");
+        };
+        let assoc = parse_items(&tokens, body.clone());
+        assert_eq!(assoc.len(), 2);
+        assert!(tokens.is_test_only(&assoc[0]));
+        assert!(!tokens.is_test_only(&assoc[1]));
+    }
+
+    #[test_case("#[cfg(test)]" => true; "simple cfg(test)")]
+    #[test_case("#  [  cfg  (  test  )  ]  " => true; "cfg(test) with may spaces")]
+    #[test_case("#[cfg(not(test))]" => false; "cfg(not(test))")]
+    #[test_case(r#"#[cfg(all(test, feature = "x"))]"# => true; "all with test")]
+    #[test_case(r#"#[cfg(any(test, feature = "x"))]"# => false; "any with test")]
+    #[test_case(r#"#[cfg(any(all(test, unix), all(test, windows)))]"# => true; "nested")]
+    #[test_case("#[cfg(not(not(test)))]" => true; "double negation")]
+    #[test_case("#[cfg(/* test */ test)]" => true; "with comment")]
+    #[test_case("#[cfg_attr(test, allow(dead_code))]" => false; "cfg_attr")]
+    fn test_is_cfg_test(src: &str) -> bool {
+        let tokens = Tokens::new(src).unwrap();
+        tokens.is_cfg_test(0..tokens.len())
+    }
+
+    #[test_case("cfg_if! { mod a; }" => Some(("cfg_if".to_owned(), "moda;".to_owned())); "macro block")]
+    #[test_case("cfg_if::cfg_if! { mod a; };" => Some(("cfg_if".to_owned(), "moda;".to_owned())); "macro path with semicolon")]
+    #[test_case("macro_rules! m { () => {} }" => None; "macro_rules")]
+    #[test_case("vec![1, 2];" => None; "bracket macro")]
+    #[test_case("fn f() { m! {} }" => None; "function")]
+    fn test_macro_block(src: &str) -> Option<(String, String)> {
+        let tokens = Tokens::new(src).unwrap();
+        let items = parse_items(&tokens, 0..tokens.len());
+        let (name, body) = tokens.macro_block(&items[0])?;
+        Some((name.to_owned(), tokens.compact(body)))
+    }
+
+    #[test_case("pub fn f() {}", ItemSpanKind::Fn, Some("f"), Some("pub"); "fn")]
+    #[test_case("pub(crate) const unsafe fn f() {}", ItemSpanKind::Fn, Some("f"), Some("pub(crate)"); "const fn")]
+    #[test_case("extern \"C\" fn f() {}", ItemSpanKind::Fn, Some("f"), None; "extern fn")]
+    #[test_case("#[derive(Debug)] struct A;", ItemSpanKind::Struct, Some("A"), None; "struct")]
+    #[test_case("pub(in crate::a) enum E {}", ItemSpanKind::Enum, Some("E"), Some("pub(in crate::a)"); "enum")]
+    #[test_case("unsafe impl<T> Send for A<T> {}", ItemSpanKind::Impl, None, None; "impl")]
+    #[test_case("pub unsafe trait T {}", ItemSpanKind::Trait, Some("T"), Some("pub"); "trait")]
+    #[test_case("use std::io;", ItemSpanKind::Use, None, None; "use")]
+    #[test_case("macro_rules! m { () => {}; }", ItemSpanKind::MacroRules, Some("m"), None; "macro_rules")]
+    #[test_case("const N: usize = 1;", ItemSpanKind::Const, Some("N"), None; "const")]
+    #[test_case("static mut X: u32 = 0;", ItemSpanKind::Static, Some("X"), None; "static mut")]
+    #[test_case("type A = u32;", ItemSpanKind::Type, Some("A"), None; "type alias")]
+    #[test_case("thread_local! {}", ItemSpanKind::Other, None, None; "macro call")]
+    #[test_case("#![allow(dead_code)]", ItemSpanKind::Attrs, None, None; "inner attribute")]
+    #[test_case("#[allow(dead_code)]", ItemSpanKind::Attrs, None, None; "dangling attribute")]
+    fn test_item_signature(src: &str, kind: ItemSpanKind, name: Option<&str>, vis: Option<&str>) {
+        let tokens = Tokens::new(src).unwrap();
+        let items = parse_items(&tokens, 0..tokens.len());
+        let signature = tokens.item_signature(&items[0]);
+        assert_eq!((signature.0, signature.1.as_deref(), signature.2.as_deref()), (kind, name, vis));
+    }
+
+    #[test_case(r#"#[path = "a/b.rs"]"# => Some("a/b.rs".to_owned()); "simple path attribute")]
+    #[test_case(r#"  #  [ path =  "../x.rs" ]  "# => Some("../x.rs".to_owned()); "path attribute with many spaces")]
+    #[test_case(r##"#[path = r#"raw.rs"#]"## => Some("raw.rs".to_owned()); "raw string")]
+    #[test_case(r#"#[cfg(test)]"# => None; "other attribute")]
+    fn test_path_attr(src: &str) -> Option<String> {
+        let tokens = Tokens::new(src).unwrap();
+        tokens.path_attr(0..tokens.len())
+    }
+
+    #[test_case("}"; "unexpected close")]
+    #[test_case("mod a { fn f() { }"; "unclosed")]
+    #[test_case("fn f() { ) }"; "mismatched")]
+    fn test_unbalanced(src: &str) {
+        assert!(Tokens::new(src).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use {
+    crate::{
+        lexer::{tokenize, Delim, Token, TokenKind},
+        ConfigToml,
+    },
+    std::{borrow::Cow, ops::Range},
+};
+
+// 直後の `::` がパスの先頭になるキーワードです。
+const KEYWORDS: &[&str] = &[
+    "as", "box", "break", "const", "dyn", "else", "for", "if", "impl", "in", "let", "match", "move", "mut", "pub",
+    "ref", "return", "static", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+// ソースのバイト範囲 `range` を `text` に置き換える書き換えです。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct PathEdit<'a> {
+    pub range: Range<usize>,
+    pub text: String,
+    // 依存クレートへのパスの書き換えならば、その名前
+    pub dep: Option<&'a str>,
+}
+
+// パスの書き換えに使う情報です。
+//
+// * `crate::` と `$crate::` を `crate::name::` と `$crate::name::` にします。
+//   ただし `$crate::path!` のようなマクロ呼出しはそのままにします。
+// * 依存クレートを指すパスを `crate::dep` にします。use tree の根、先頭の `::`、`extern crate` も扱います。
+//   `package = "..."` で改名された依存は、別名を本来のパッケージ名にします。
+//
+// トークンを見て書き換えるので、リテラルやコメントの中は書き換えません。
+#[derive(Clone, Debug)]
+pub struct PathContext<'a> {
+    // 書き換え先のクレート名（アンダースコアつなぎ）。`None` ならば `crate::` を書き換えません。
+    pub crate_name: Option<String>,
+    pub config: &'a ConfigToml,
+}
+
+impl PathContext<'_> {
+    // トークン列の書き換えを、位置の順に返します。
+    pub fn edits<'s>(&self, tokens: &[Token], src: &'s str) -> Vec<PathEdit<'s>> {
+        let mut scanner = Scanner {
+            src,
+            code: tokens.iter().filter(|token| !token.is_comment()).copied().collect(),
+            context: self,
+            edits: Vec::new(),
+        };
+        scanner.scan();
+        let mut edits = scanner.edits;
+        edits.sort_by_key(|edit| edit.range.start);
+        edits
+    }
+    // ソースのパスを書き換えます。字句解析できなければそのまま返します。
+    pub fn substitute<'s>(&self, src: &'s str) -> Cow<'s, str> {
+        match tokenize(src) {
+            Ok(tokens) => apply_edits(src, &self.edits(&tokens, src)),
+            Err(_) => Cow::Borrowed(src),
+        }
+    }
+}
+
+// 書き換えを適用します。
+pub fn apply_edits<'s>(src: &'s str, edits: &[PathEdit]) -> Cow<'s, str> {
+    if edits.is_empty() {
+        return Cow::Borrowed(src);
+    }
+    let mut result = String::new();
+    let mut last = 0;
+    for edit in edits {
+        result.push_str(&src[last..edit.range.start]);
+        result.push_str(&edit.text);
+        last = edit.range.end;
+    }
+    result.push_str(&src[last..]);
+    Cow::Owned(result)
+}
+
+struct Scanner<'a, 'b> {
+    src: &'a str,
+    // コメントを除いたトークン
+    code: Vec<Token>,
+    context: &'b PathContext<'b>,
+    edits: Vec<PathEdit<'a>>,
+}
+
+impl<'a> Scanner<'a, '_> {
+    fn scan(&mut self) {
+        let mut i = 0;
+        while i < self.code.len() {
+            i = if self.is_ident(i, "use") {
+                self.use_tree(i + 1, true)
+            } else if self.is_ident(i, "extern") && self.is_ident(i + 1, "crate") && self.is_dep(i + 2) {
+                // `extern crate dep;` は `use crate::dep;` にします。
+                let dep = self.text(i + 2);
+                self.push(i..i + 3, format!("use {}", self.dep_path(dep)), Some(dep));
+                i + 3
+            } else if self.is_punct(i, '$') && self.is_ident(i + 1, "crate") && self.adjacent(i) {
+                if self.is_path_sep(i + 2) && !self.is_macro_path(i + 2) {
+                    self.push_crate(i + 1);
+                }
+                i + 2
+            } else if self.is_ident(i, "crate") && self.is_path_sep(i + 1) && !self.follows_path(i) {
+                self.push_crate(i);
+                i + 1
+            } else if self.is_path_sep(i) && self.is_leading(i) && self.is_dep(i + 2) && self.is_path_sep(i + 3) {
+                // `::dep::` の先頭の `::` は消します。
+                let dep = self.text(i + 2);
+                self.push(i..i + 3, self.dep_path(dep), Some(dep));
+                i + 3
+            } else if self.is_dep(i) && self.is_path_sep(i + 1) && !self.follows_path(i) {
+                self.push_dep(i);
+                i + 1
+            } else {
+                i + 1
+            };
+        }
+    }
+    // `use` の後の use tree を読み、依存クレートと `crate` で始まるものを書き換えます。
+    // `root` ならば、前にパスのない位置です。読み終えた位置を返します。
+    fn use_tree(&mut self, mut i: usize, root: bool) -> usize {
+        if root && self.is_path_sep(i) {
+            if self.is_open_brace(i + 2) {
+                // `::{dep::X, std::io}` は `::` を消して `{crate::dep::X, std::io}` にします。
+                let len = self.edits.len();
+                let next = self.use_group(i + 2, true);
+                if self.edits.len() > len {
+                    self.push(i..i + 2, String::new(), None);
+                }
+                return next;
+            }
+            if self.is_dep(i + 2) {
+                let dep = self.text(i + 2);
+                self.push(i..i + 3, self.dep_path(dep), Some(dep));
+                i += 3;
+            } else {
+                i += 2;
+            }
+        } else if root && self.is_dep(i) {
+            self.push_dep(i);
+        } else if root && self.is_ident(i, "crate") {
+            self.push_crate(i);
+        }
+        let start = i;
+        while i < self.code.len() {
+            if self.is_path_sep(i) {
+                i += 2;
+            } else if self.code[i].kind == TokenKind::Ident || self.is_punct(i, '*') {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        if self.is_open_brace(i) {
+            i = self.use_group(i, root && i == start);
+        }
+        i
+    }
+    // `{` から対応する `}` までの use tree を読みます。
+    fn use_group(&mut self, open: usize, root: bool) -> usize {
+        let mut i = open + 1;
+        while i < self.code.len() && self.code[i].kind != TokenKind::Close(Delim::Brace) {
+            let next = self.use_tree(i, root);
+            // カンマや、読めなかったトークンを飛ばします。
+            i = if next == i { i + 1 } else { next };
+        }
+        i + 1
+    }
+    fn push(&mut self, range: Range<usize>, text: String, dep: Option<&'a str>) {
+        let range = self.code[range.start].start..self.code[range.end - 1].end;
+        self.edits.push(PathEdit { range, text, dep });
+    }
+    fn push_dep(&mut self, i: usize) {
+        let dep = self.text(i);
+        self.push(i..i + 1, self.dep_path(dep), Some(dep));
+    }
+    // 依存クレートの別名 `dep` に対応する、バンドル後のパスです。
+    fn dep_path(&self, dep: &str) -> String {
+        format!("crate::{}", self.context.config.deps[dep].package.replace('-', "_"))
+    }
+    fn push_crate(&mut self, i: usize) {
+        if let Some(crate_name) = &self.context.crate_name {
+            let text = format!("crate::{}", crate_name);
+            self.push(i..i + 1, text, None);
+        }
+    }
+    fn text(&self, i: usize) -> &'a str {
+        self.code.get(i).map_or("", |token| &self.src[token.range()])
+    }
+    fn is_ident(&self, i: usize, ident: &str) -> bool {
+        self.code.get(i).is_some_and(|token| token.kind == TokenKind::Ident) && self.text(i) == ident
+    }
+    fn is_punct(&self, i: usize, c: char) -> bool {
+        self.code.get(i).is_some_and(|token| token.kind == TokenKind::Punct(c))
+    }
+    fn is_open_brace(&self, i: usize) -> bool {
+        self.code.get(i).is_some_and(|token| token.kind == TokenKind::Open(Delim::Brace))
+    }
+    fn is_dep(&self, i: usize) -> bool {
+        self.code.get(i).is_some_and(|token| token.kind == TokenKind::Ident)
+            && self.context.config.deps.contains_key(self.text(i))
+    }
+    // `i` 番目と次のトークンが、間をあけずに並んでいるかどうか
+    fn adjacent(&self, i: usize) -> bool {
+        i + 1 < self.code.len() && self.code[i].end == self.code[i + 1].start
+    }
+    fn is_path_sep(&self, i: usize) -> bool {
+        self.is_punct(i, ':') && self.is_punct(i + 1, ':') && self.adjacent(i)
+    }
+    // `i` 番目のトークンが、パスの途中やフィールド、メタ変数の名前であるかどうか
+    fn follows_path(&self, i: usize) -> bool {
+        i >= 1 && (self.is_punct(i - 1, '.') || self.is_punct(i - 1, '$') || i >= 2 && self.is_path_sep(i - 2))
+    }
+    // `i` 番目からの `::` が、パスの先頭であるかどうか
+    fn is_leading(&self, i: usize) -> bool {
+        if i == 0 || i >= 2 && self.is_path_sep(i - 2) {
+            return i == 0;
+        }
+        let prev = self.code[i - 1];
+        match prev.kind {
+            TokenKind::Ident => KEYWORDS.contains(&self.text(i - 1)),
+            // `Vec<T>::` は続きですが、`->` と `=>` の後は先頭です。
+            TokenKind::Punct('>') => {
+                i >= 2 && (self.is_punct(i - 2, '-') || self.is_punct(i - 2, '=')) && self.adjacent(i - 2)
+            }
+            _ => true,
+        }
+    }
+    // `i` 番目からが `::a::b!` のようなマクロ呼出しのパスであるかどうか
+    fn is_macro_path(&self, mut i: usize) -> bool {
+        while self.is_path_sep(i) && self.code.get(i + 2).is_some_and(|token| token.kind == TokenKind::Ident) {
+            i += 3;
+        }
+        self.is_punct(i, '!')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::PathContext,
+        crate::{lexer::tokenize, ConfigToml},
+        std::borrow::Cow,
+        test_case::test_case,
+    };
+
+    // 依存クレートへのパス `dep::` を `crate::dep::` に置換します。
+    fn substitute_dep_path<'a>(src: &'a str, config: &ConfigToml) -> Cow<'a, str> {
+        PathContext { crate_name: None, config }.substitute(src)
+    }
+
+    // パスとして参照されている依存クレートの名前を、現れる順に列挙します。
+    fn referenced_deps<'a>(src: &'a str, config: &ConfigToml) -> Vec<&'a str> {
+        let context = PathContext { crate_name: None, config };
+        match tokenize(src) {
+            Ok(tokens) => context.edits(&tokens, src).into_iter().filter_map(|edit| edit.dep).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // パスの置換をします。
+    fn substitute_path(src: &str, crate_name: &str, config: &ConfigToml) -> String {
+        PathContext {
+            crate_name: Some(crate_name.replace('-', "_")),
+            config,
+        }
+        .substitute(src)
+        .into_owned()
+    }
+
+    #[test_case("use crate_a::f" => "use crate::crate_a::f".to_owned(); "simple qualified use")]
+    #[test_case("use crate_a::f as _" => "use crate::crate_a::f as _".to_owned(); "qualified use as")]
+    #[test_case("use crate_never::f" => "use crate_never::f".to_owned(); "not in deps")]
+    #[test_case("let _: crate_a::Type = crate_a::Type::new()" => "let _: crate::crate_a::Type = crate::crate_a::Type::new()".to_owned(); "expand twice")]
+    #[test_case("type X = (crate_a::A, crate_b::B);" => "type X = (crate::crate_a::A, crate::crate_b::B);".to_owned(); "expand two distinct crates")]
+    #[test_case("use crate_a;" => "use crate::crate_a;".to_owned(); "use crate itself")]
+    #[test_case("use crate_a as a;" => "use crate::crate_a as a;".to_owned(); "use crate as")]
+    #[test_case("use crate_a::{self, f};" => "use crate::crate_a::{self, f};".to_owned(); "use self in group")]
+    #[test_case("use {crate_a, crate_b::{f, g}};" => "use {crate::crate_a, crate::crate_b::{f, g}};".to_owned(); "use group at root")]
+    #[test_case("use std::{crate_a, io};" => "use std::{crate_a, io};".to_owned(); "dep name in nested group")]
+    #[test_case("use ::crate_a::f;" => "use crate::crate_a::f;".to_owned(); "leading colons in use")]
+    #[test_case("use ::{crate_a::f, std::io};" => "use {crate::crate_a::f, std::io};".to_owned(); "leading colons before group")]
+    #[test_case("let x = ::crate_a::f();" => "let x = crate::crate_a::f();".to_owned(); "leading colons in expression")]
+    #[test_case("fn f() -> ::crate_a::A {}" => "fn f() -> crate::crate_a::A {}".to_owned(); "leading colons after arrow")]
+    #[test_case("extern crate crate_a;" => "use crate::crate_a;".to_owned(); "extern crate")]
+    #[test_case("pub extern crate crate_a as a;" => "pub use crate::crate_a as a;".to_owned(); "extern crate as")]
+    #[test_case(r#"let s = "crate_a::f";"# => r#"let s = "crate_a::f";"#.to_owned(); "string literal")]
+    #[test_case("// crate_a::f" => "// crate_a::f".to_owned(); "comment")]
+    #[test_case("let crate_a = x.crate_a::<u32>() + y::crate_a::Z;" => "let crate_a = x.crate_a::<u32>() + y::crate_a::Z;".to_owned(); "not a path start")]
+    #[test_case("use renamed::f; renamed::g();" => "use crate::real_name::f; crate::real_name::g();".to_owned(); "renamed dependency")]
+    #[test_case("use crate_a::{\n    f,\n    g,\n};" => "use crate::crate_a::{\n    f,\n    g,\n};".to_owned(); "multiline use")]
+    fn test_substitute_non_macro_path(line: &str) -> String {
+        substitute_path(line, "my_crate", &build_sample_config_toml())
+    }
+
+    #[test_case("$crate::a" => "$crate::my_crate::a".to_owned(); "simple macro with $crate")]
+    #[test_case("$crate::a!" => "$crate::a!".to_owned(); "simple item with $crate")]
+    #[test_case("crate::a" => "crate::my_crate::a".to_owned(); "not `$crate` but just `crate`")]
+    #[test_case("{ $crate::a!(); $crate::b::c() }" => "{ $crate::a!(); $crate::my_crate::b::c() }".to_owned(); "macro call and path")]
+    #[test_case("pub(crate) use crate::{a, b};" => "pub(crate) use crate::my_crate::{a, b};".to_owned(); "pub crate")]
+    #[test_case("pub(in crate::a) fn f() {}" => "pub(in crate::my_crate::a) fn f() {}".to_owned(); "pub in crate")]
+    fn test_substitute_macro_path(line: &str) -> String {
+        substitute_path(line, "my_crate", &build_sample_config_toml())
+    }
+
+    #[test_case("use crate_a::f" => "use crate::crate_a::f".to_owned(); "dep path")]
+    #[test_case("use crate::x" => "use crate::x".to_owned(); "crate path is untouched")]
+    #[test_case("$crate::x" => "$crate::x".to_owned(); "macro path is untouched")]
+    fn test_substitute_dep_path(line: &str) -> String {
+        substitute_dep_path(line, &build_sample_config_toml()).into_owned()
+    }
+
+    #[test_case("use crate_a::f;" => vec!["crate_a"]; "simple use")]
+    #[test_case("use {crate_b::B, std::io, crate_a::A};" => vec!["crate_b", "crate_a"]; "use tree")]
+    #[test_case("let x = crate_never::f();" => Vec::<&str>::new(); "not in deps")]
+    #[test_case("extern crate crate_c;\nuse crate_d;" => vec!["crate_c", "crate_d"]; "extern crate and use")]
+    #[test_case(r#"println!("crate_a::f");"# => Vec::<&str>::new(); "string literal")]
+    fn test_referenced_deps(line: &str) -> Vec<&str> {
+        referenced_deps(line, &build_sample_config_toml())
+    }
+
+    fn build_sample_config_toml() -> ConfigToml {
+        ConfigToml::new(
+            r#"
+            [dependencies]
+            crate_a = { path = "../crate_a" }
+            crate_b = { path = "../crate_b" }
+            crate_c = { path = "../crate_c" }
+            crate_d = { path = "../crate_d" }
+            renamed = { package = "real-name", path = "../real-name" }
+        "#,
+        ).unwrap()
+    }
+}
@@ -1,74 +1,123 @@
 use {
-    crate::{Crate, Module, Span, TAB, TAB_LENGTH},
-    std::fmt::{Display, Formatter, Result, Write},
+    crate::{
+        options::BundleOptions,
+        source_map::SourceMap,
+        template::render_template,
+        types::{LiteralLines, Origin},
+        Crate, Module, Span,
+    },
 };
 
-const OPEN: &str = "{";
-const CLOSE: &str = "}";
+// バンドルしたクレートを、ヘッダーなどをつけた `mod name { ... }` として整形します。
+pub fn format_crate_to_string(my_crate: Crate, options: &BundleOptions) -> String {
+    format_crates_with_source_map(vec![my_crate], options).0
+}
+
+// 複数のクレートを、兄弟のトップレベルモジュールとして並べます。出力と合わせて、出力の各行の元の位置を返します。
+// ヘッダーなどは `options` で指定されていなければ、それぞれのクレートの Cargo.toml の設定か、既定値を使います。
+pub fn format_crates_with_source_map(crates: Vec<Crate>, options: &BundleOptions) -> (String, SourceMap) {
+    let mut output = Output::default();
+    for my_crate in &crates {
+        format_crate(&mut output, my_crate, options);
+    }
+    (output.lines.join("\n"), SourceMap { lines: output.origins })
+}
 
-pub fn format_crate_to_string(my_crate: Crate) -> String {
-    format!("{}", CrateFormatter(&my_crate))
+// 出力の行と、その元の位置と、リテラルの途中から始まる行です。
+#[derive(Default)]
+struct Output {
+    lines: Vec<String>,
+    origins: Vec<Option<Origin>>,
+    literal_lines: LiteralLines,
 }
 
-struct CrateFormatter<'a>(&'a Crate);
+impl Output {
+    fn push(&mut self, line: String, origin: Option<&Origin>) {
+        self.lines.push(line);
+        self.origins.push(origin.cloned());
+    }
+    // リテラルの途中から始まる行は、インデントを足さずにそのまま書き出します。
+    fn push_literal(&mut self, line: String, origin: Option<&Origin>) {
+        self.literal_lines.0.push(self.lines.len());
+        self.push(line, origin);
+    }
+    // 複数行のテンプレートは行に分けます。
+    fn push_template(&mut self, text: &str) {
+        for line in text.split('\n') {
+            self.push(line.to_owned(), None);
+        }
+    }
+}
 
-impl Display for CrateFormatter<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let undered = self.0.name.replace('-', "_");
-        writeln!(f, "// {undered} {OPEN}{OPEN}{OPEN}")?;
-        writeln!(
-            f,
-            "// https://ngtkana.github.io/ac-adapter-rs/{undered}/index.html",
-        )?;
-        writeln!(f, "#[allow(unused_imports)]")?;
-        writeln!(f, "#[allow(dead_code)]")?;
-        fmt_dfs(f, &self.0.name, &self.0.root, 0)?;
-        write!(f, "// {CLOSE}{CLOSE}{CLOSE}")?;
-        Ok(())
+fn format_crate(output: &mut Output, my_crate: &Crate, options: &BundleOptions) {
+    let config = options.output.or(&my_crate.info.output);
+    let header = render_template(config.header(), my_crate);
+    let footer = render_template(config.footer(), my_crate);
+    if !header.is_empty() {
+        output.push_template(&header);
+    }
+    for lint in config.allow() {
+        output.push(format!("#[allow({lint})]"), None);
+    }
+    fmt_dfs(output, &my_crate.name, &my_crate.root, 0, options.tab_width);
+    if !footer.is_empty() {
+        output.push_template(&footer);
     }
 }
 
-pub fn fmt_dfs(
-    w: &mut impl Write,
-    crate_name: &str,
+// ひとつのモジュールを `mod name { ... }` の行に整形して、各行の元の位置とリテラルの途中から始まる行と合わせて返します。
+// マクロの中に展開するモジュールのように、モジュールの木の外に置くものに使います。
+pub(crate) fn format_module_lines(
     module: &Module,
-    indent_level: usize,
-) -> Result {
+    tab_width: usize,
+) -> (Vec<String>, Vec<Option<Origin>>, LiteralLines) {
+    let mut output = Output::default();
+    fmt_dfs(&mut output, "", module, 0, tab_width);
+    (output.lines, output.origins, output.literal_lines)
+}
+
+fn fmt_dfs(output: &mut Output, crate_name: &str, module: &Module, indent_level: usize, tab_width: usize) {
     let name = module
         .path
         .as_path()
         .file_stem()
-        .map(|s| {
-            s.to_str()
-                .unwrap_or_else(|| panic!("OsStr から str に変換できませんでした。"))
-                .to_owned()
-        })
+        .map(|s| s.to_string_lossy().into_owned())
         .unwrap_or_else(|| crate_name.replace('-', "_"));
-    let indent = " ".repeat(indent_level * TAB_LENGTH);
-    writeln!(w, "{}mod {} {{", &indent, &name)?;
+    let indent = " ".repeat(indent_level * tab_width);
+    let tab = " ".repeat(tab_width);
+    let vis = module.vis.as_ref().map_or_else(String::new, |vis| format!("{} ", vis));
+    output.push(format!("{}{}mod {} {{", &indent, &vis, &name), None);
     for span in &module.spans {
-        match span {
-            Span::Lines(lines) => {
-                for line in lines {
-                    writeln!(w, "{}{}{}", &indent, &TAB, line)?;
-                }
-            }
+        let (lines, origins, literal_lines) = match span {
+            Span::Lines(lines, origins, literal_lines) => (lines, origins, literal_lines),
+            Span::Item(item) => (&item.lines, &item.origins, &item.literal_lines),
             Span::Module(module) => {
                 if !module.is_test {
-                    fmt_dfs(w, crate_name, module, indent_level + 1)?
+                    fmt_dfs(output, crate_name, module, indent_level + 1, tab_width)
                 }
+                continue;
+            }
+        };
+        for (k, line) in lines.iter().enumerate() {
+            if literal_lines.contains(k) {
+                output.push_literal(line.clone(), origins.get(k));
+            } else {
+                output.push(format!("{}{}{}", &indent, &tab, line), origins.get(k));
             }
         }
     }
-    writeln!(w, "{}}}", &indent)?;
-    Ok(())
+    output.push(format!("{}}}", &indent), None);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::format_crate_to_string;
+    use crate::{BundleOptions, OutputConfig};
     use {
-        super::{Crate, Module, Span},
+        super::{format_crate_to_string, format_crates_with_source_map, Crate, Module, Span},
+        crate::{
+            types::{LiteralLines, Origin, Origins},
+            CrateInfo,
+        },
         std::path::PathBuf,
     };
 
@@ -78,16 +127,18 @@ mod tests {
             name: "holy_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
-                spans: vec![Span::Lines(vec![
+                spans: vec![Span::lines(vec![
                     "1".to_owned(),
                     "2".to_owned(),
                     "3".to_owned(),
                     "4".to_owned(),
                 ])],
             },
+            info: CrateInfo::default(),
         };
-        let result = format_crate_to_string(w);
+        let result = format_crate_to_string(w, &BundleOptions::default());
         let expected = concat!(
             concat!("// holy_crate {", "{{\n"),
             "// https://ngtkana.github.io/ac-adapter-rs/holy_crate/index.html\n",
@@ -110,27 +161,31 @@ mod tests {
             name: "holy_crate".to_owned(),
             root: Module {
                 is_test: false,
+                vis: None,
                 path: PathBuf::from("."),
                 spans: vec![
-                    Span::Lines(vec!["start root".to_owned()]),
+                    Span::lines(vec!["start root".to_owned()]),
                     Span::Module(Box::new(Module {
                         is_test: false,
+                        vis: None,
                         path: PathBuf::from("./a"),
                         spans: vec![
-                            Span::Lines(vec!["start a".to_owned()]),
+                            Span::lines(vec!["start a".to_owned()]),
                             Span::Module(Box::new(Module {
                                 is_test: false,
+                                vis: None,
                                 path: PathBuf::from("./a/b"),
-                                spans: vec![Span::Lines(vec!["in b".to_owned()])],
+                                spans: vec![Span::lines(vec!["in b".to_owned()])],
                             })),
-                            Span::Lines(vec!["end a".to_owned()]),
+                            Span::lines(vec!["end a".to_owned()]),
                         ],
                     })),
-                    Span::Lines(vec!["end root".to_owned()]),
+                    Span::lines(vec!["end root".to_owned()]),
                 ],
             },
+            info: CrateInfo::default(),
         };
-        let result = format_crate_to_string(w);
+        let result = format_crate_to_string(w, &BundleOptions::default());
         let expected = concat!(
             concat!("// holy_crate {", "{{\n"),
             "// https://ngtkana.github.io/ac-adapter-rs/holy_crate/index.html\n",
@@ -151,4 +206,128 @@ mod tests {
         );
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_many_crates() {
+        let w = ["a", "b"]
+            .iter()
+            .map(|name| Crate {
+                name: (*name).to_owned(),
+                root: Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("."),
+                    spans: vec![Span::lines(vec![format!("in {}", name)])],
+                },
+                info: CrateInfo::default(),
+            })
+            .collect();
+        let result = format_crates_with_source_map(w, &BundleOptions::default()).0;
+        let expected = concat!(
+            concat!("// a {", "{{\n"),
+            "// https://ngtkana.github.io/ac-adapter-rs/a/index.html\n",
+            "#[allow(unused_imports)]\n",
+            "#[allow(dead_code)]\n",
+            "mod a {\n",
+            "    in a\n",
+            "}\n",
+            concat!("// }", "}}\n"),
+            concat!("// b {", "{{\n"),
+            "// https://ngtkana.github.io/ac-adapter-rs/b/index.html\n",
+            "#[allow(unused_imports)]\n",
+            "#[allow(dead_code)]\n",
+            "mod b {\n",
+            "    in b\n",
+            "}\n",
+            concat!("// }", "}}"),
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_options() {
+        let w = ["a", "b"]
+            .iter()
+            .map(|name| Crate {
+                name: (*name).to_owned(),
+                root: Module {
+                    is_test: false,
+                    vis: None,
+                    path: PathBuf::from("."),
+                    spans: vec![Span::lines(vec![format!("in {}", name)])],
+                },
+                info: CrateInfo {
+                    // Cargo.toml の設定よりも、引数の設定を優先します。
+                    output: OutputConfig {
+                        header: Some("// crate {name}".to_owned()),
+                        footer: Some("// end {name}".to_owned()),
+                        allow: None,
+                    },
+                    ..CrateInfo::default()
+                },
+            })
+            .collect();
+        let options = BundleOptions {
+            tab_width: 2,
+            output: OutputConfig {
+                header: None,
+                footer: Some(String::new()),
+                allow: Some(vec!["clippy::all".to_owned()]),
+            },
+            ..BundleOptions::default()
+        };
+        let result = format_crates_with_source_map(w, &options).0;
+        let expected = concat!(
+            "// crate a\n",
+            "#[allow(clippy::all)]\n",
+            "mod a {\n",
+            "  in a\n",
+            "}\n",
+            "// crate b\n",
+            "#[allow(clippy::all)]\n",
+            "mod b {\n",
+            "  in b\n",
+            "}",
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let origin = |line| Origin {
+            path: PathBuf::from("src/lib.rs"),
+            line,
+        };
+        let w = Crate {
+            name: "a".to_owned(),
+            root: Module {
+                is_test: false,
+                vis: None,
+                path: PathBuf::from("."),
+                spans: vec![
+                    Span::Lines(
+                        vec!["x".to_owned(), "y".to_owned()],
+                        Origins(vec![origin(1), origin(3)]),
+                        LiteralLines::default(),
+                    ),
+                    Span::lines(vec!["unknown".to_owned()]),
+                ],
+            },
+            info: CrateInfo::default(),
+        };
+        let options = BundleOptions {
+            output: OutputConfig {
+                header: Some("// first\n// second".to_owned()),
+                footer: Some("// end".to_owned()),
+                allow: Some(Vec::new()),
+            },
+            ..BundleOptions::default()
+        };
+        let (result, source_map) = format_crates_with_source_map(vec![w], &options);
+        assert_eq!(result, "// first\n// second\nmod a {\n    x\n    y\n    unknown\n}\n// end");
+        assert_eq!(
+            source_map.lines,
+            vec![None, None, None, Some(origin(1)), Some(origin(3)), None, None, None]
+        );
+    }
 }
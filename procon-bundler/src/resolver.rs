@@ -1,17 +1,99 @@
 use {
     crate::{BundlerError, Result},
     std::{
+        collections::BTreeMap,
         fs::File,
-        io::{BufRead, BufReader},
-        path::{Path, PathBuf},
+        io::{BufRead, BufReader, Cursor},
+        path::{Component, Path, PathBuf},
     },
 };
 
+// クレートのソースファイルを読むものです。
+//
+// ファイルは `lib.rs` や `a/mod.rs` のような `src` からの相対パス（ファイルパス）で、
+// モジュールはクレートのルートを `.`、`crate::a::b` を `./a/b` とするモジュールパスで指定します。
+// 実装するのは `file_exists` と `resolve_file` で、モジュールパスからファイルパスへは既定の `file_path` が
+// rustc と同様に対応づけます。
 pub trait Resolve {
     type B: BufRead;
-    fn resolve(&self, module_path: &Path) -> Result<Self::B>;
+    // `src` からの相対パスで指定されたファイルが存在するかを判定します。`resolve_file` と食い違ってはいけません。
+    fn file_exists(&self, file_path: &Path) -> bool;
+    // `src` からの相対パスで指定されたファイルを開きます。なければ `ModuleFileNotFound` を返します。
+    fn resolve_file(&self, file_path: &Path) -> Result<Self::B>;
+    // モジュールパスから、そのモジュールのファイルの `src` からの相対パスを求めます。
+    // ルートは lib.rs、それ以外は `a/b.rs` と `a/b/mod.rs` の二通りを試し、どちらもないか両方あればエラーにします。
+    fn file_path(&self, module_path: &Path) -> Result<PathBuf> {
+        let is_root = module_path
+            .to_str()
+            .ok_or_else(|| BundlerError::InvalidPathConversion {
+                path: module_path.to_path_buf(),
+            })?
+            == ".";
+        if is_root {
+            return Ok(PathBuf::from("lib.rs"));
+        }
+        let module_path = normalize_path(module_path);
+        let candidates = vec![module_path.with_extension("rs"), module_path.join("mod.rs")];
+        pick_module_file(self, &module_path, candidates)
+    }
+    // `src` からの相対パスを、エラーやソースマップの表示に使うパスにします。
+    // 既定はそのままで、ファイルシステムの上のリゾルバは `src` のディレクトリをつけます。
+    fn display_path(&self, file_path: &Path) -> PathBuf {
+        file_path.to_path_buf()
+    }
+    // クレートのルートのディレクトリです。ファイルシステムの上にないクレートでは `None` です。
+    // Cargo.toml を読むのと、ヘッダーの `{path}` と `{commit}` に使います。
+    fn root_dir(&self) -> Option<&Path> {
+        None
+    }
+    // モジュールパスで指定されたモジュールのファイルを開きます。実装で上書きする必要はありません。
+    fn resolve(&self, module_path: &Path) -> Result<Self::B> {
+        self.resolve_file(&self.file_path(module_path)?)
+    }
+}
+
+// 候補のうち、ちょうどひとつだけ存在するファイルを選びます。
+pub fn pick_module_file<R: Resolve + ?Sized>(
+    resolver: &R,
+    module_path: &Path,
+    candidates: Vec<PathBuf>,
+) -> Result<PathBuf> {
+    let mut found = candidates.iter().filter(|path| resolver.file_exists(path));
+    match (found.next(), found.next()) {
+        (Some(file_path), None) => Ok(file_path.clone()),
+        (Some(_), Some(_)) => Err(BundlerError::AmbiguousModuleFile {
+            module_path: module_path.to_path_buf(),
+            candidates,
+            location: None,
+        }),
+        (None, _) => Err(BundlerError::ModuleFileNotFound {
+            module_path: module_path.to_path_buf(),
+            candidates,
+            location: None,
+        }),
+    }
+}
+
+// `.` と `..` を字面の上で解消します。
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) {
+                    result.pop();
+                } else {
+                    result.push("..");
+                }
+            }
+            component => result.push(component),
+        }
+    }
+    result
 }
 
+// ファイルシステムの上のクレートです。ファイルパスはクレートの `src` からの相対パスで、表示ではそれをつなげます。
 pub struct CrateResolver {
     root: PathBuf,
 }
@@ -24,39 +106,162 @@ impl CrateResolver {
 }
 impl Resolve for CrateResolver {
     type B = BufReader<File>;
-    // NOTE: mod.rs も探したい場合はここの実装も変えましょう！
-    fn resolve(&self, module_path: &Path) -> Result<Self::B> {
-        let mut buf = self.root.clone();
-        let is_root = module_path
-            .to_str()
-            .ok_or_else(|| BundlerError::InvalidPathConversion {
-                path: module_path.to_path_buf(),
-            })?
-            == ".";
-        buf.push("src");
-        buf.push(if is_root {
-            Path::new("lib")
-        } else {
-            module_path
-        });
-        buf.set_extension("rs");
-        
-        let file = File::open(&buf).map_err(|e| BundlerError::ModuleFileNotFound {
-            module_path: module_path.to_path_buf(),
-            file_path: buf,
+    fn file_exists(&self, file_path: &Path) -> bool {
+        self.root.join("src").join(file_path).is_file()
+    }
+    fn resolve_file(&self, file_path: &Path) -> Result<Self::B> {
+        let path = self.display_path(file_path);
+        if !path.is_file() {
+            return Err(BundlerError::ModuleFileNotFound {
+                module_path: file_path.to_path_buf(),
+                candidates: vec![path],
+                location: None,
+            });
+        }
+        let file = File::open(&path).map_err(|e| BundlerError::ModuleFileReadError {
+            path,
+            location: None,
             source: e,
         })?;
-        
         Ok(BufReader::new(file))
     }
+    fn display_path(&self, file_path: &Path) -> PathBuf {
+        self.root.join("src").join(file_path)
+    }
+    fn root_dir(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+// ファイルの内容を、`src` からの相対パス（`lib.rs`, `a/mod.rs` など）をキーとしてメモリ上に持つクレートです。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InMemoryResolver {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl InMemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // ファイルを加えるか置き換えて、前の内容を返します。
+    pub fn insert(&mut self, file_path: impl AsRef<Path>, content: impl Into<String>) -> Option<String> {
+        self.files.insert(normalize_path(file_path.as_ref()), content.into())
+    }
+    pub fn get(&self, file_path: &Path) -> Option<&str> {
+        self.files.get(&normalize_path(file_path)).map(String::as_str)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl<P: AsRef<Path>, S: Into<String>> FromIterator<(P, S)> for InMemoryResolver {
+    fn from_iter<I: IntoIterator<Item = (P, S)>>(iter: I) -> Self {
+        let mut resolver = Self::new();
+        for (file_path, content) in iter {
+            resolver.insert(file_path, content);
+        }
+        resolver
+    }
+}
+
+impl Resolve for InMemoryResolver {
+    type B = Cursor<String>;
+    fn file_exists(&self, file_path: &Path) -> bool {
+        self.get(file_path).is_some()
+    }
+    fn resolve_file(&self, file_path: &Path) -> Result<Self::B> {
+        match self.get(file_path) {
+            Some(content) => Ok(Cursor::new(content.to_owned())),
+            None => Err(BundlerError::ModuleFileNotFound {
+                module_path: file_path.to_path_buf(),
+                candidates: vec![file_path.to_path_buf()],
+                location: None,
+            }),
+        }
+    }
+}
+
+// ほかのリゾルバの上に、メモリ上のファイル（エディタで保存していないバッファなど）を重ねます。
+// 重ねたファイルが優先で、元のリゾルバにまだないファイルでも構いません。表示に使うパスは元のリゾルバのものです。
+#[derive(Clone, Debug)]
+pub struct OverlayResolver<R = CrateResolver> {
+    base: R,
+    overlay: InMemoryResolver,
+}
+
+impl<R: Resolve> OverlayResolver<R> {
+    pub fn new(base: R, overlay: InMemoryResolver) -> Self {
+        Self { base, overlay }
+    }
+}
+
+impl OverlayResolver<CrateResolver> {
+    // ディスク上のパスをキーとする `buffers` のうち、`crate_root` のクレートの `src` にあるものを重ねます。
+    // ほかのものは無視するので、どのクレートにも同じ `buffers` を渡せます。
+    pub fn with_buffers(crate_root: PathBuf, buffers: &BTreeMap<PathBuf, String>) -> Self {
+        let src = crate_root.join("src").canonicalize().ok();
+        let overlay = buffers
+            .iter()
+            .filter_map(|(path, content)| {
+                let relative = absolute_path(path)?.strip_prefix(src.as_ref()?).ok()?.to_path_buf();
+                Some((relative, content.clone()))
+            })
+            .collect();
+        Self::new(CrateResolver::new(crate_root), overlay)
+    }
+}
+
+// ディスク上のパスをキーとする `buffers` から、`path` のファイルの内容を探します。
+pub(crate) fn find_buffer<'a>(buffers: &'a BTreeMap<PathBuf, String>, path: &Path) -> Option<&'a String> {
+    if buffers.is_empty() {
+        return None;
+    }
+    let path = absolute_path(path)?;
+    buffers
+        .iter()
+        .find(|(buffer_path, _)| absolute_path(buffer_path).as_ref() == Some(&path))
+        .map(|(_, content)| content)
+}
+
+// まだディスクにないファイルも指せるよう、親ディレクトリまでで正規化します。
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Some(path),
+        Err(_) => Some(path.parent()?.canonicalize().ok()?.join(path.file_name()?)),
+    }
+}
+
+impl<R: Resolve> Resolve for OverlayResolver<R>
+where
+    R::B: 'static,
+{
+    type B = Box<dyn BufRead>;
+    fn file_exists(&self, file_path: &Path) -> bool {
+        self.overlay.file_exists(file_path) || self.base.file_exists(file_path)
+    }
+    fn resolve_file(&self, file_path: &Path) -> Result<Self::B> {
+        if self.overlay.file_exists(file_path) {
+            Ok(Box::new(self.overlay.resolve_file(file_path)?))
+        } else {
+            Ok(Box::new(self.base.resolve_file(file_path)?))
+        }
+    }
+    fn display_path(&self, file_path: &Path) -> PathBuf {
+        self.base.display_path(file_path)
+    }
+    fn root_dir(&self) -> Option<&Path> {
+        self.base.root_dir()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::{CrateResolver, Resolve},
-        crate::manual_resolver,
+        super::{normalize_path, CrateResolver, InMemoryResolver, OverlayResolver, Resolve},
+        crate::{manual_resolver, BundlerError},
         std::{
+            collections::BTreeMap,
             io::Read,
             path::{Path, PathBuf},
         },
@@ -103,4 +308,103 @@ mod tests {
             concat!("#[allow(dead_code)]\n", "pub type A = ();\n",)
         );
     }
+
+    #[test]
+    fn test_resolve_mod_rs() {
+        let mut s = String::new();
+
+        let crate_resolver = CrateResolver::new(PathBuf::from("../testcase/mod_rs"));
+        crate_resolver
+            .resolve(Path::new("./a"))
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s.as_str(), "mod b;\n");
+        s.clear();
+        crate_resolver
+            .resolve(Path::new("./a/b"))
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s.as_str(), "pub struct B;\n");
+    }
+
+    #[test]
+    fn test_resolve_ambiguous() {
+        let crate_resolver = CrateResolver::new(PathBuf::from("../testcase/mod_rs"));
+        match crate_resolver.resolve(Path::new("./ambiguous")) {
+            Err(BundlerError::AmbiguousModuleFile { candidates, .. }) => assert_eq!(
+                candidates,
+                vec![PathBuf::from("ambiguous.rs"), PathBuf::from("ambiguous/mod.rs")]
+            ),
+            _ => panic!("expected an ambiguity error"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_not_found() {
+        let crate_resolver = CrateResolver::new(PathBuf::from("../testcase/mod_rs"));
+        match crate_resolver.resolve(Path::new("./missing")) {
+            Err(BundlerError::ModuleFileNotFound { candidates, .. }) => assert_eq!(
+                candidates,
+                vec![PathBuf::from("missing.rs"), PathBuf::from("missing/mod.rs")]
+            ),
+            _ => panic!("expected a not-found error"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_file() {
+        let mut s = String::new();
+
+        let crate_resolver = CrateResolver::new(PathBuf::from("../testcase/mod_rs"));
+        assert_eq!(
+            crate_resolver.file_path(Path::new("./a")).unwrap(),
+            PathBuf::from("a/mod.rs")
+        );
+        crate_resolver
+            .resolve_file(Path::new("a/b.rs"))
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s.as_str(), "pub struct B;\n");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new("./a/b/../c.rs")), PathBuf::from("a/c.rs"));
+        assert_eq!(normalize_path(Path::new("a/../../c.rs")), PathBuf::from("../c.rs"));
+    }
+
+    fn read(resolver: &impl Resolve, module_path: &str) -> String {
+        let mut s = String::new();
+        resolver.resolve(Path::new(module_path)).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_in_memory_resolver() {
+        let resolver = InMemoryResolver::from_iter([("lib.rs", "mod a;"), ("./a/mod.rs", "mod b;"), ("a/b.rs", "in b")]);
+        assert_eq!(read(&resolver, "."), "mod a;");
+        assert_eq!(resolver.file_path(Path::new("./a")).unwrap(), PathBuf::from("a/mod.rs"));
+        assert_eq!(read(&resolver, "./a"), "mod b;");
+        assert_eq!(read(&resolver, "./a/b"), "in b");
+        assert!(matches!(resolver.resolve(Path::new("./c")), Err(BundlerError::ModuleFileNotFound { .. })));
+    }
+
+    #[test]
+    fn test_overlay_resolver() {
+        let root = PathBuf::from("../testcase/mod_rs");
+        let buffers = BTreeMap::from([
+            (root.join("src/a/b.rs"), "unsaved b".to_owned()),
+            (root.join("src/new.rs"), "not yet on disk".to_owned()),
+            (PathBuf::from("../procon-bundler-sample/src/lib.rs"), "another crate".to_owned()),
+        ]);
+        let resolver = OverlayResolver::with_buffers(root.clone(), &buffers);
+        assert_eq!(read(&resolver, "./a"), "mod b;\n");
+        assert_eq!(read(&resolver, "./a/b"), "unsaved b");
+        assert_eq!(read(&resolver, "./new"), "not yet on disk");
+        assert_eq!(resolver.display_path(Path::new("a/b.rs")), root.join("src/a/b.rs"));
+        assert!(matches!(resolver.resolve(Path::new("./ambiguous")), Err(BundlerError::AmbiguousModuleFile { .. })));
+    }
 }
@@ -0,0 +1,526 @@
+use {
+    crate::{
+        lexer::{Delim, TokenKind},
+        parse_item::{parse_items, Item, ItemKind, Tokens},
+        types::{push_lines, LiteralLines, Origins},
+        Crate, Module, Span,
+    },
+    std::{collections::HashSet, ops::Range},
+};
+
+// 起点から到達可能なアイテムだけを残して、クレートを刈り込みます。
+//
+// 起点は解答のソースやアイテムのパス（`seg_tree::SegTree` など）で、そこに現れる識別子から始めます。
+// 名前だけを見て到達可能性を判定するので、同名のアイテムはまとめて残ります（多めに残す分には安全です）。
+//
+// * 名前をもつアイテム（fn, struct, enum, trait, macro_rules! など）は、その名前が使われていれば残します。
+// * `use` は、持ち込む名前のどれかが使われていれば残します。glob と `as _` は常に残します。
+// * impl は、対象の型とトレイトのうちバンドルの中で定義されているものがすべて残るならば残します。
+// * 空になったモジュールとクレートは消します。
+pub fn shake_crates(crates: Vec<Crate>, roots: &[&str]) -> Vec<Crate> {
+    let mut texts = Vec::new();
+    for my_crate in &crates {
+        collect_texts(&my_crate.root, &mut texts);
+    }
+    let tokens = texts.iter().map(|text| Tokens::new(text).ok()).collect::<Vec<_>>();
+    let items = tokens
+        .iter()
+        .map(|tokens| match tokens {
+            Some(tokens) => parse_items(tokens, 0..tokens.len())
+                .iter()
+                .map(|item| ShakeItem::new(tokens, item))
+                .collect(),
+            None => Vec::new(),
+        })
+        .collect::<Vec<Vec<_>>>();
+
+    // 名前の集合が変わらなくなるまで、残すアイテムを増やしていきます。
+    let defined = items
+        .iter()
+        .flatten()
+        .filter_map(|item| match &item.role {
+            Role::Named(names) => Some(names),
+            _ => None,
+        })
+        .flatten()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let mut used = roots.iter().flat_map(|root| identifiers(root)).collect::<HashSet<_>>();
+    let mut kept = items.iter().map(|items| vec![false; items.len()]).collect::<Vec<_>>();
+    loop {
+        let mut changed = false;
+        for (items, kept) in items.iter().zip(&mut kept) {
+            for (item, kept) in items.iter().zip(kept) {
+                if !*kept && item.is_reachable(&used, &defined) {
+                    *kept = true;
+                    changed = true;
+                    used.extend(item.references.iter().cloned());
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut shaker = Shaker {
+        texts: &texts,
+        items: &items,
+        kept: &kept,
+        next: 0,
+    };
+    crates
+        .into_iter()
+        .filter_map(|my_crate| {
+            let root = shaker.shake_module(my_crate.root)?;
+            Some(Crate { root, ..my_crate })
+        })
+        .collect()
+}
+
+// 行のまとまりを、モジュールを深さ優先でたどる順に集めます。テストモジュールは出力されないので除きます。
+fn collect_texts(module: &Module, texts: &mut Vec<String>) {
+    if module.is_test {
+        return;
+    }
+    for span in &module.spans {
+        match span {
+            Span::Lines(lines, ..) => texts.push(lines.join("\n")),
+            Span::Item(item) => texts.push(item.lines.join("\n")),
+            Span::Module(module) => collect_texts(module, texts),
+        }
+    }
+}
+
+// 識別子らしい部分をすべて取り出します。起点はコメントや文字列を含むこともありますが、多めに拾う分には問題ありません。
+fn identifiers(src: &str) -> impl Iterator<Item = String> + '_ {
+    src.split(|c: char| !(c == '_' || c.is_alphanumeric()))
+        .filter(|word| word.chars().next().is_some_and(|c| !c.is_ascii_digit()))
+        .map(str::to_owned)
+}
+
+// 刈り込みの単位となるアイテムです。
+#[derive(Clone, Debug, Hash, PartialEq)]
+struct ShakeItem {
+    // 行のまとまりの中でのバイト範囲
+    span: Range<usize>,
+    role: Role,
+    // アイテムに現れる識別子
+    references: Vec<String>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq)]
+enum Role {
+    // 常に残します。
+    Always,
+    // 名前のどれかが使われていれば残します。
+    Named(Vec<String>),
+    // impl の対象の型とトレイトの名前
+    Impl(Vec<String>),
+    // 本体のない属性の並び（直後のモジュールの属性です。）
+    DanglingAttrs,
+}
+
+impl ShakeItem {
+    fn new(tokens: &Tokens, item: &Item) -> Self {
+        let range = item.start()..item.end;
+        let references = range
+            .clone()
+            .filter(|&i| tokens.tokens[i].kind == TokenKind::Ident)
+            .map(|i| tokens.text(i).to_owned())
+            .collect();
+        Self {
+            span: tokens.span(range),
+            role: classify(tokens, item),
+            references,
+        }
+    }
+    fn is_reachable(&self, used: &HashSet<String>, defined: &HashSet<String>) -> bool {
+        match &self.role {
+            Role::Always | Role::DanglingAttrs => true,
+            Role::Named(names) => names.iter().any(|name| used.contains(name)),
+            Role::Impl(subjects) => subjects
+                .iter()
+                .all(|subject| !defined.contains(subject) || used.contains(subject)),
+        }
+    }
+}
+
+// アイテムの種類と、定義する名前を調べます。
+fn classify(tokens: &Tokens, item: &Item) -> Role {
+    let end = item.end;
+    let next = |i: usize| tokens.skip_comments(i + 1, end);
+    let ident = |i: usize| (i < end && tokens.tokens[i].kind == TokenKind::Ident).then(|| tokens.text(i));
+    match item.kind {
+        ItemKind::InnerAttr | ItemKind::Module { .. } => return Role::Always,
+        ItemKind::ImplOrTrait { .. } | ItemKind::Other => {}
+    }
+    if item.head >= end {
+        return Role::DanglingAttrs;
+    }
+
+    let mut i = item.head;
+    if ident(i) == Some("pub") {
+        i = next(i);
+        if i < end && tokens.tokens[i].kind == TokenKind::Open(Delim::Paren) {
+            i = next(tokens.matching(i));
+        }
+    }
+    while let Some(keyword) = ident(i) {
+        match keyword {
+            "unsafe" | "async" | "default" | "auto" => i = next(i),
+            "extern" => {
+                i = next(i);
+                match ident(i) {
+                    Some("crate") => return Role::Always,
+                    Some(_) => {}
+                    None => i = next(i),
+                }
+            }
+            "const" if matches!(ident(next(i)), Some("fn" | "unsafe" | "async" | "extern")) => i = next(i),
+            "fn" | "struct" | "enum" | "union" | "trait" | "type" | "static" | "const" => {
+                let mut name = next(i);
+                if ident(name) == Some("mut") {
+                    name = next(name);
+                }
+                return match ident(name) {
+                    Some("_") | None => Role::Always,
+                    Some(name) => Role::Named(vec![name.to_owned()]),
+                };
+            }
+            "macro_rules" => {
+                let bang = next(i);
+                return match ident(next(bang)) {
+                    Some(name) if tokens.tokens[bang].kind == TokenKind::Punct('!') => {
+                        Role::Named(vec![name.to_owned()])
+                    }
+                    _ => Role::Always,
+                };
+            }
+            "impl" => return Role::Impl(impl_subjects(tokens, next(i), end)),
+            "use" => {
+                return match use_names(tokens, next(i), end) {
+                    Some(names) => Role::Named(names),
+                    None => Role::Always,
+                }
+            }
+            _ => return Role::Always,
+        }
+    }
+    Role::Always
+}
+
+// `impl` の直後から、トレイトと型のそれぞれについて、深さ 0 の最後の識別子を返します。
+fn impl_subjects(tokens: &Tokens, mut i: usize, end: usize) -> Vec<String> {
+    let next = |i: usize| tokens.skip_comments(i + 1, end);
+    // ジェネリクスを読み飛ばします。
+    if i < end && tokens.tokens[i].kind == TokenKind::Punct('<') {
+        let mut depth = 0;
+        while i < end {
+            match tokens.tokens[i].kind {
+                TokenKind::Punct('<') => depth += 1,
+                TokenKind::Punct('>') if !is_arrow(tokens, i) => depth -= 1,
+                TokenKind::Open(_) => i = tokens.matching(i),
+                _ => {}
+            }
+            i = next(i);
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut subjects = Vec::new();
+    let mut last = None;
+    let mut depth = 0;
+    while i < end {
+        let token = tokens.tokens[i];
+        match token.kind {
+            TokenKind::Open(Delim::Brace) if depth == 0 => break,
+            TokenKind::Ident if depth == 0 && tokens.text(i) == "where" => break,
+            TokenKind::Ident if depth == 0 && tokens.text(i) == "for" => subjects.extend(last.take()),
+            TokenKind::Ident if depth == 0 && !["dyn", "mut", "impl"].contains(&tokens.text(i)) => {
+                last = Some(tokens.text(i).to_owned());
+            }
+            TokenKind::Punct('<') => depth += 1,
+            TokenKind::Punct('>') if !is_arrow(tokens, i) => depth -= 1,
+            TokenKind::Open(_) => i = tokens.matching(i),
+            _ => {}
+        }
+        i = next(i);
+    }
+    subjects.extend(last);
+    subjects
+}
+
+fn is_arrow(tokens: &Tokens, i: usize) -> bool {
+    i > 0 && tokens.tokens[i - 1].kind == TokenKind::Punct('-') && tokens.tokens[i - 1].end == tokens.tokens[i].start
+}
+
+// `use` の直後から、持ち込む名前を返します。glob または `as _` があれば `None` を返します。
+fn use_names(tokens: &Tokens, mut i: usize, end: usize) -> Option<Vec<String>> {
+    let next = |i: usize| tokens.skip_comments(i + 1, end);
+    let is_path_sep = |i: usize| {
+        let j = next(i);
+        j < end && tokens.tokens[i].kind == TokenKind::Punct(':') && tokens.tokens[j].kind == TokenKind::Punct(':')
+    };
+    let mut names = Vec::new();
+    // 直前のパスの区切りの名前（`{self}` 用）を、中括弧の深さごとに覚えます。
+    let mut segments = vec![None::<String>];
+    while i < end {
+        match tokens.tokens[i].kind {
+            TokenKind::Punct('*') => return None,
+            TokenKind::Open(Delim::Brace) => {
+                let segment = segments.last().cloned().flatten();
+                segments.push(segment);
+            }
+            TokenKind::Close(Delim::Brace) => {
+                segments.pop();
+            }
+            TokenKind::Ident => {
+                let name = tokens.text(i);
+                let after = next(i);
+                if after < end && is_path_sep(after) {
+                    *segments.last_mut().unwrap() = Some(name.to_owned());
+                    i = next(after);
+                } else if after < end && tokens.text(after) == "as" {
+                    let alias = next(after);
+                    match tokens.text(alias) {
+                        "_" => return None,
+                        alias => names.push(alias.to_owned()),
+                    }
+                    i = alias;
+                } else if name == "self" {
+                    names.extend(segments.last().cloned().flatten());
+                } else {
+                    names.push(name.to_owned());
+                }
+            }
+            _ => {}
+        }
+        i = next(i);
+    }
+    Some(names)
+}
+
+// 判定結果にしたがって、クレートを組み立て直します。
+struct Shaker<'a> {
+    texts: &'a [String],
+    items: &'a [Vec<ShakeItem>],
+    kept: &'a [Vec<bool>],
+    // 次の行のまとまりの番号
+    next: usize,
+}
+
+impl Shaker<'_> {
+    // 空になったモジュールは `None` を返します。
+    fn shake_module(&mut self, module: Module) -> Option<Module> {
+        if module.is_test {
+            return None;
+        }
+        let mut spans = Vec::new();
+        // 直後のモジュールが消えるかどうかがわかるまで、書き出しを待っている行のまとまり
+        let mut pending = None;
+        for span in module.spans {
+            let lines = match span {
+                Span::Lines(_, origins, literal_lines) => (origins, literal_lines),
+                Span::Item(item) => (item.origins, item.literal_lines),
+                Span::Module(child) => {
+                    let child = self.shake_module(*child);
+                    if let Some((j, (origins, literal_lines))) = pending.take() {
+                        self.render(&mut spans, j, &origins, &literal_lines, child.is_none());
+                    }
+                    spans.extend(child.map(|child| Span::Module(Box::new(child))));
+                    continue;
+                }
+            };
+            if let Some((j, (origins, literal_lines))) = pending.replace((self.next, lines)) {
+                self.render(&mut spans, j, &origins, &literal_lines, false);
+            }
+            self.next += 1;
+        }
+        if let Some((j, (origins, literal_lines))) = pending {
+            self.render(&mut spans, j, &origins, &literal_lines, false);
+        }
+        (!spans.is_empty()).then_some(Module { spans, ..module })
+    }
+    // 残すアイテムの行だけを、元の位置を添えて書き出します。`drop_dangling` ならば末尾の属性の並びも消します。
+    // 行頭を消さなかった行は、リテラルの途中から始まるかどうかもそのままにします。
+    fn render(
+        &self,
+        spans: &mut Vec<Span>,
+        j: usize,
+        origins: &Origins,
+        literal_lines: &LiteralLines,
+        drop_dangling: bool,
+    ) {
+        let text = &self.texts[j];
+        let dropped = self.items[j]
+            .iter()
+            .zip(&self.kept[j])
+            .filter(|(item, &kept)| !kept || (drop_dangling && item.role == Role::DanglingAttrs))
+            .map(|(item, _)| item.span.clone())
+            .collect::<Vec<_>>();
+        let mut lines = Vec::new();
+        let mut kept_origins = Vec::new();
+        let mut kept_literal_lines = Vec::new();
+        let mut offset = 0;
+        for (k, line) in text.split('\n').enumerate() {
+            let origin = origins.get(k).cloned();
+            let range = offset..offset + line.len();
+            offset = range.end + 1;
+            let mut result = String::new();
+            let mut cursor = range.start;
+            for span in dropped.iter().filter(|span| span.start < range.end && range.start < span.end) {
+                result.push_str(&text[cursor..span.start.max(cursor)]);
+                cursor = span.end.min(range.end).max(cursor);
+            }
+            if cursor == range.start {
+                if literal_lines.contains(k) {
+                    kept_literal_lines.push(lines.len());
+                }
+                lines.push(line.to_owned());
+                kept_origins.extend(origin);
+                continue;
+            }
+            result.push_str(&text[cursor..range.end]);
+            if !result.trim().is_empty() {
+                let head_dropped = dropped.iter().any(|span| span.start <= range.start && range.start < span.end);
+                if literal_lines.contains(k) && !head_dropped {
+                    kept_literal_lines.push(lines.len());
+                }
+                lines.push(result.trim_end().to_owned());
+                kept_origins.extend(origin);
+            }
+        }
+        // 元の位置がわからなければ、わからないままにします。
+        if kept_origins.len() != lines.len() {
+            kept_origins.clear();
+        }
+        push_lines(spans, lines, Origins(kept_origins), LiteralLines(kept_literal_lines));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::shake_crates,
+        crate::{Crate, CrateInfo, Module, Span},
+        std::path::PathBuf,
+    };
+
+    fn lines(lines: &[&str]) -> Span {
+        Span::lines(lines.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn module(path: &str, spans: Vec<Span>) -> Module {
+        Module {
+            path: PathBuf::from(path),
+            spans,
+            is_test: false,
+            vis: None,
+        }
+    }
+
+    fn build_crates() -> Vec<Crate> {
+        vec![
+            Crate {
+                name: "util".to_owned(),
+                root: module(
+                    ".",
+                    vec![
+                        lines(&["pub trait Monoid {", "    fn op(a: u32, b: u32) -> u32;", "}"]),
+                        lines(&["pub struct Add;", "impl Monoid for Add {", "    fn op(a: u32, b: u32) -> u32 { a + b }", "}"]),
+                        lines(&["pub fn unused() {}"]),
+                    ],
+                ),
+                info: CrateInfo::default(),
+            },
+            Crate {
+                name: "seg_tree".to_owned(),
+                root: module(
+                    ".",
+                    vec![
+                        lines(&[
+                            "use crate::util::Monoid;",
+                            "use std::fmt;",
+                            "pub struct SegTree<M: Monoid> { values: Vec<u32>, m: M }",
+                            "impl<M: Monoid> SegTree<M> {",
+                            "    pub fn new(m: M) -> Self { helper(); Self { values: Vec::new(), m } }",
+                            "}",
+                            "impl<M: Monoid> fmt::Debug for SegTree<M> {",
+                            "    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { Ok(()) }",
+                            "}",
+                            "fn helper() {}",
+                            "#[macro_export]",
+                            "macro_rules! unused_macro { () => {}; }",
+                            "pub struct Fenwick; pub struct Unused;",
+                            "#[allow(dead_code)]",
+                        ]),
+                        Span::Module(Box::new(module("./unused_module", vec![lines(&["pub struct Nothing;"])]))),
+                        lines(&["impl Fenwick {}"]),
+                    ],
+                ),
+                info: CrateInfo::default(),
+            },
+            Crate {
+                name: "unused_crate".to_owned(),
+                root: module(".", vec![lines(&["pub struct Lonely;"])]),
+                info: CrateInfo::default(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_shake_crates() {
+        let result = shake_crates(build_crates(), &["use crate::seg_tree::SegTree;\nfn main() { SegTree::new(()); }"]);
+        let expected = vec![
+            Crate {
+                name: "util".to_owned(),
+                root: module(
+                    ".",
+                    vec![lines(&[
+                        "pub trait Monoid {",
+                        "    fn op(a: u32, b: u32) -> u32;",
+                        "}",
+                    ])],
+                ),
+                info: CrateInfo::default(),
+            },
+            Crate {
+                name: "seg_tree".to_owned(),
+                root: module(
+                    ".",
+                    vec![lines(&[
+                        "use crate::util::Monoid;",
+                        "use std::fmt;",
+                        "pub struct SegTree<M: Monoid> { values: Vec<u32>, m: M }",
+                        "impl<M: Monoid> SegTree<M> {",
+                        "    pub fn new(m: M) -> Self { helper(); Self { values: Vec::new(), m } }",
+                        "}",
+                        "impl<M: Monoid> fmt::Debug for SegTree<M> {",
+                        "    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { Ok(()) }",
+                        "}",
+                        "fn helper() {}",
+                    ])],
+                ),
+                info: CrateInfo::default(),
+            },
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_shake_crates_by_item_paths() {
+        let result = shake_crates(build_crates(), &["seg_tree::Fenwick", "util::Add"]);
+        let names = result.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["util", "seg_tree"]);
+        // Monoid はどこからも使われないので、impl Monoid for Add も消えます。
+        assert_eq!(result[0].root.spans, vec![lines(&["pub struct Add;"])]);
+        // 同じ行の Unused だけが消え、空になったモジュールとその属性も消えます。
+        assert_eq!(
+            result[1].root.spans,
+            vec![lines(&["pub struct Fenwick;", "impl Fenwick {}"])]
+        );
+    }
+}
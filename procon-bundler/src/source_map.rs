@@ -0,0 +1,168 @@
+use {
+    crate::{types::Origin, BundlerError, Result},
+    serde_json::{json, Value},
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+// ソースマップの形式の版です。
+const VERSION: u64 = 1;
+
+// 出力の各行の元の位置です。ヘッダーやフッターのように、元のファイルのない行は `None` です。
+//
+// JSON では、次のように出力の 1 行目から順に並べます。
+//
+// ```json
+// {
+//   "version": 1,
+//   "lines": [
+//     null,
+//     {"path": "libs/seg-tree/src/lib.rs", "line": 3},
+//     ...
+//   ]
+// }
+// ```
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct SourceMap {
+    pub lines: Vec<Option<Origin>>,
+}
+
+impl SourceMap {
+    // 出力の `line` 行目（1 始まり）の元の位置です。
+    pub fn locate(&self, line: usize) -> Result<Option<&Origin>> {
+        match line.checked_sub(1).and_then(|index| self.lines.get(index)) {
+            Some(origin) => Ok(origin.as_ref()),
+            None => Err(BundlerError::LineOutOfRange {
+                line,
+                len: self.lines.len(),
+            }),
+        }
+    }
+    // 後ろに `other` の行を続けます。
+    pub(crate) fn append(&mut self, other: SourceMap) {
+        self.lines.extend(other.lines);
+    }
+    // 差分が見やすいよう、一行にひとつずつ書きます。
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\n  \"version\": {},\n  \"lines\": [", VERSION);
+        for (i, origin) in self.lines.iter().enumerate() {
+            let value = match origin {
+                Some(origin) => json!({
+                    "path": origin.path.to_string_lossy(),
+                    "line": origin.line,
+                }),
+                None => Value::Null,
+            };
+            json.push_str(if i == 0 { "\n    " } else { ",\n    " });
+            json.push_str(&value.to_string());
+        }
+        json.push_str(if self.lines.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        json
+    }
+    // `path` はエラーの表示に使います。
+    pub fn from_json(json: &str, path: &Path) -> Result<Self> {
+        let invalid = |message: String| BundlerError::InvalidSourceMap {
+            path: path.to_path_buf(),
+            message,
+        };
+        let value = serde_json::from_str::<Value>(json).map_err(|e| invalid(e.to_string()))?;
+        match value.get("version").and_then(Value::as_u64) {
+            Some(VERSION) => {}
+            version => return Err(invalid(format!("unsupported version {:?}", version))),
+        }
+        let lines = value
+            .get("lines")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("`lines` is not an array".to_owned()))?;
+        let lines = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| match line {
+                Value::Null => Ok(None),
+                _ => {
+                    let path = line.get("path").and_then(Value::as_str);
+                    let number = line.get("line").and_then(Value::as_u64);
+                    match (path, number) {
+                        (Some(path), Some(number)) => Ok(Some(Origin {
+                            path: PathBuf::from(path),
+                            line: number as usize,
+                        })),
+                        _ => Err(invalid(format!("invalid entry for output line {}: {}", i + 1, line))),
+                    }
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { lines })
+    }
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).map_err(|e| BundlerError::SourceMapReadError {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        Self::from_json(&json, path)
+    }
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()).map_err(|e| BundlerError::SourceMapWriteError {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::SourceMap,
+        crate::{types::Origin, BundlerError},
+        std::path::{Path, PathBuf},
+    };
+
+    fn sample() -> SourceMap {
+        SourceMap {
+            lines: vec![
+                None,
+                Some(Origin {
+                    path: PathBuf::from("src/lib.rs"),
+                    line: 3,
+                }),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = sample().to_json();
+        assert_eq!(
+            json,
+            concat!(
+                "{\n",
+                "  \"version\": 1,\n",
+                "  \"lines\": [\n",
+                "    null,\n",
+                "    {\"line\":3,\"path\":\"src/lib.rs\"}\n",
+                "  ]\n",
+                "}\n",
+            )
+        );
+        assert_eq!(SourceMap::from_json(&json, Path::new("map.json")).unwrap(), sample());
+        let empty = SourceMap::default().to_json();
+        assert_eq!(SourceMap::from_json(&empty, Path::new("map.json")).unwrap(), SourceMap::default());
+    }
+
+    #[test]
+    fn test_locate() {
+        let map = sample();
+        assert_eq!(map.locate(1).unwrap(), None);
+        assert_eq!(map.locate(2).unwrap().map(|origin| origin.line), Some(3));
+        assert!(matches!(map.locate(3), Err(BundlerError::LineOutOfRange { line: 3, len: 2 })));
+        assert!(matches!(map.locate(0), Err(BundlerError::LineOutOfRange { line: 0, .. })));
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let err = SourceMap::from_json("{\"version\": 2, \"lines\": []}", Path::new("map.json")).unwrap_err();
+        assert!(matches!(err, BundlerError::InvalidSourceMap { .. }));
+    }
+}
@@ -0,0 +1,221 @@
+use {
+    crate::{diagnostic::Location, BundlerError, Crate, Result},
+    std::{
+        env,
+        path::Path,
+        process::Command,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+// ヘッダーとフッターのテンプレートで使えるプレースホルダーです。
+//
+// * `{name}`: アンダースコアつなぎのクレート名
+// * `{package}`: Cargo.toml に書かれたままのパッケージ名
+// * `{version}`: パッケージのバージョン
+// * `{commit}`: クレートのあるリポジトリの HEAD のコミット（短い形）
+// * `{path}`: クレートのルートのディレクトリ
+// * `{date}`: バンドルした日付（UTC、`SOURCE_DATE_EPOCH` があればそれを使います。）
+pub const PLACEHOLDERS: &[&str] = &["name", "package", "version", "commit", "path", "date"];
+
+pub const DEFAULT_HEADER: &str = concat!(
+    "// {name} {",
+    "{{\n",
+    "// https://ngtkana.github.io/ac-adapter-rs/{name}/index.html",
+);
+pub const DEFAULT_FOOTER: &str = concat!("// }", "}}");
+pub const DEFAULT_ALLOW: &[&str] = &["unused_imports", "dead_code"];
+
+// 値のわからないプレースホルダーは、これに置き換えます。
+const UNKNOWN: &str = "unknown";
+
+// 出力の体裁の設定です。`None` のものは、ほかの設定か既定値を使います。
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct OutputConfig {
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    // クレートの先頭に `#[allow(...)]` として書く lint
+    pub allow: Option<Vec<String>>,
+}
+
+impl OutputConfig {
+    // `self` で指定されていないものを `other` で補います。
+    pub(crate) fn or(&self, other: &Self) -> Self {
+        Self {
+            header: self.header.clone().or_else(|| other.header.clone()),
+            footer: self.footer.clone().or_else(|| other.footer.clone()),
+            allow: self.allow.clone().or_else(|| other.allow.clone()),
+        }
+    }
+    pub(crate) fn header(&self) -> &str {
+        self.header.as_deref().unwrap_or(DEFAULT_HEADER)
+    }
+    pub(crate) fn footer(&self) -> &str {
+        self.footer.as_deref().unwrap_or(DEFAULT_FOOTER)
+    }
+    pub(crate) fn allow(&self) -> Vec<&str> {
+        match &self.allow {
+            Some(allow) => allow.iter().map(String::as_str).collect(),
+            None => DEFAULT_ALLOW.to_vec(),
+        }
+    }
+}
+
+// テンプレートに知らないプレースホルダーがあれば、エラーにします。
+pub fn check_template(template: &str, location: impl FnOnce() -> Option<Location>) -> Result<()> {
+    match find_unknown_placeholder(template) {
+        None => Ok(()),
+        Some(placeholder) => Err(BundlerError::UnknownPlaceholder {
+            template: template.to_owned(),
+            placeholder: placeholder.to_owned(),
+            location: location(),
+        }),
+    }
+}
+
+fn find_unknown_placeholder(template: &str) -> Option<&str> {
+    placeholders(template)
+        .map(|(_, name)| name)
+        .find(|name| !PLACEHOLDERS.contains(name))
+}
+
+// テンプレートのプレースホルダーを、クレートの情報で置き換えます。
+pub fn render_template(template: &str, my_crate: &Crate) -> String {
+    expand(template, |name| match name {
+        "name" => Some(my_crate.name.replace('-', "_")),
+        "package" => Some(my_crate.name.clone()),
+        "version" => Some(my_crate.info.version.clone().unwrap_or_else(|| UNKNOWN.to_owned())),
+        "commit" => Some(
+            my_crate
+                .info
+                .root_dir
+                .as_deref()
+                .and_then(git_commit)
+                .unwrap_or_else(|| UNKNOWN.to_owned()),
+        ),
+        "path" => Some(
+            my_crate
+                .info
+                .root_dir
+                .as_ref()
+                .map_or_else(|| UNKNOWN.to_owned(), |dir| dir.display().to_string()),
+        ),
+        "date" => Some(today()),
+        _ => None,
+    })
+}
+
+// `{name}` の形の部分を `lookup` の値に置き換えます。
+// それ以外の `{` はそのまま残すので、折りたたみの目印も書けます。
+fn expand(template: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for (range, name) in placeholders(template) {
+        if let Some(value) = lookup(name) {
+            result.push_str(&template[last..range.start]);
+            result.push_str(&value);
+            last = range.end;
+        }
+    }
+    result.push_str(&template[last..]);
+    result
+}
+
+// `{` と `}` で識別子を囲んだ部分の、範囲と識別子を列挙します。
+fn placeholders(template: &str) -> impl Iterator<Item = (std::ops::Range<usize>, &str)> {
+    template.match_indices('{').filter_map(|(start, _)| {
+        let rest = &template[start + 1..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        (len > 0 && rest[len..].starts_with('}')).then(|| (start..start + len + 2, &rest[..len]))
+    })
+}
+
+fn git_commit(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    (output.status.success() && !commit.is_empty()).then_some(commit)
+}
+
+fn today() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+    let (year, month, day) = civil_from_days(secs / 86400);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// 1970-01-01 からの日数を、グレゴリオ暦の年月日にします。
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // 3 月はじまりの 400 年周期で数えます。
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{civil_from_days, expand, find_unknown_placeholder, render_template},
+        crate::{types::CrateInfo, Crate},
+        std::path::PathBuf,
+        test_case::test_case,
+    };
+
+    #[test_case(concat!("// {name} {", "{{") => concat!("// my_crate {", "{{"); "fold marker")]
+    #[test_case("{{name}}" => "{my_crate}"; "braces around")]
+    #[test_case("{name}-{version}" => "my_crate-1.0.0"; "adjacent")]
+    #[test_case("{nam e} {}" => "{nam e} {}"; "not a placeholder")]
+    #[test_case("{other}" => "{other}"; "unknown")]
+    fn test_expand(template: &str) -> String {
+        expand(template, |name| match name {
+            "name" => Some("my_crate".to_owned()),
+            "version" => Some("1.0.0".to_owned()),
+            _ => None,
+        })
+    }
+
+    #[test_case(concat!("// {name} {", "{{") => None; "known")]
+    #[test_case("// {name} {vresion}" => Some("vresion"); "typo")]
+    fn test_find_unknown_placeholder(template: &str) -> Option<&str> {
+        find_unknown_placeholder(template)
+    }
+
+    #[test]
+    fn test_render_template() {
+        let my_crate = Crate {
+            name: "seg-tree".to_owned(),
+            info: CrateInfo {
+                version: Some("0.1.0".to_owned()),
+                root_dir: Some(PathBuf::from("libs/seg-tree")),
+                ..CrateInfo::default()
+            },
+            ..Crate::default()
+        };
+        assert_eq!(
+            render_template("// {package} ({name}) v{version} at {path}", &my_crate),
+            "// seg-tree (seg_tree) v0.1.0 at libs/seg-tree"
+        );
+        assert_eq!(render_template("{version} {commit}", &Crate::default()), "unknown unknown");
+    }
+
+    #[test_case(0 => (1970, 1, 1); "epoch")]
+    #[test_case(11_016 => (2000, 2, 29); "leap day")]
+    #[test_case(20_744 => (2026, 10, 18); "recent")]
+    fn test_civil_from_days(days: u64) -> (u64, u64, u64) {
+        civil_from_days(days)
+    }
+}
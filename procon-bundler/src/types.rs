@@ -1,9 +1,37 @@
-use std::path::PathBuf;
+use {
+    crate::template::OutputConfig,
+    std::{
+        hash::{Hash, Hasher},
+        path::PathBuf,
+    },
+};
 
 #[derive(Clone, Debug, Default, Hash, PartialEq)]
 pub struct Crate {
     pub name: String,
     pub root: Module,
+    pub info: CrateInfo,
+}
+
+// 出力のヘッダーとフッターに使う、クレートの情報です。
+#[derive(Clone, Debug, Default, Hash, PartialEq)]
+pub struct CrateInfo {
+    // [package] セクションの version
+    pub version: Option<String>,
+    // クレートのルートのディレクトリ
+    pub root_dir: Option<PathBuf>,
+    // [package.metadata.procon-bundler] セクションの設定
+    pub output: OutputConfig,
+    // モジュールにするときに消した、クレートのルートにしかつけられない内部属性
+    pub dropped_attrs: Vec<DroppedAttr>,
+}
+
+// クレートのルートにしかつけられないために消した内部属性と、その元の位置です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DroppedAttr {
+    // `#![no_std]` などの属性のテキスト
+    pub attr: String,
+    pub origin: Origin,
 }
 
 #[derive(Clone, Debug, Default, Hash, PartialEq)]
@@ -11,6 +39,8 @@ pub struct Module {
     pub path: PathBuf,
     pub spans: Vec<Span>,
     pub is_test: bool,
+    // 宣言に書かれた `pub`, `pub(crate)` などの可視性（クレートのルートは `None`）
+    pub vis: Option<String>,
 }
 impl Module {
     pub fn new(path: PathBuf) -> Self {
@@ -18,11 +48,131 @@ impl Module {
             path,
             spans: Vec::new(),
             is_test: false,
+            vis: None,
         }
     }
 }
+impl Module {
+    // アイテムをすべて行のまとまりに戻したものです。テストで行だけを比べるのに使います。
+    #[cfg(test)]
+    pub fn into_lines(self) -> Self {
+        let mut spans = Vec::new();
+        for span in self.spans {
+            match span {
+                Span::Lines(lines, origins, literal_lines) => push_lines(&mut spans, lines, origins, literal_lines),
+                Span::Item(item) => push_lines(&mut spans, item.lines, item.origins, item.literal_lines),
+                Span::Module(module) => spans.push(Span::Module(Box::new(module.into_lines()))),
+            }
+        }
+        Self { spans, ..self }
+    }
+}
 #[derive(Clone, Debug, Hash, PartialEq)]
 pub enum Span {
-    Lines(Vec<String>),
+    // 行と、それぞれの行の元の位置と、リテラルの途中から始まる行（アイテムに分けられなかったもの）
+    Lines(Vec<String>, Origins, LiteralLines),
+    Item(Box<ItemSpan>),
     Module(Box<Module>),
 }
+impl Span {
+    // 元の位置のわからない行です。
+    #[cfg(test)]
+    pub fn lines(lines: Vec<String>) -> Self {
+        Span::Lines(lines, Origins::default(), LiteralLines::default())
+    }
+}
+
+// モジュール直下のアイテムひとつ分の行です。
+#[derive(Clone, Debug, Hash, PartialEq)]
+pub struct ItemSpan {
+    pub kind: ItemSpanKind,
+    // 名前（impl, use のように名前のないものは `None`）
+    pub name: Option<String>,
+    // `pub`, `pub(crate)` などの可視性
+    pub vis: Option<String>,
+    // 外部属性（`cfg` を評価したあとのもの）
+    pub attrs: Vec<String>,
+    // 属性を含めた行と、それぞれの行の元の位置と、リテラルの途中から始まる行
+    pub lines: Vec<String>,
+    pub origins: Origins,
+    pub literal_lines: LiteralLines,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ItemSpanKind {
+    Fn,
+    Struct,
+    Enum,
+    Impl,
+    Trait,
+    Use,
+    MacroRules,
+    Const,
+    Static,
+    Type,
+    // マクロ呼出しなど、その他のアイテム
+    Other,
+    // アイテムの間のコメント
+    Comment,
+    // 本体のない属性の並び（モジュールの属性など）
+    Attrs,
+}
+
+// 行のまとまりを加えます。直前も行のまとまりならば、ひとつにします。
+// どちらかの元の位置がわからなければ、合わせたものもわからないとします。
+pub fn push_lines(spans: &mut Vec<Span>, lines: Vec<String>, origins: Origins, literal_lines: LiteralLines) {
+    if lines.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(Span::Lines(last, last_origins, last_literal_lines)) => {
+            if last_origins.0.len() == last.len() && origins.0.len() == lines.len() {
+                last_origins.0.extend(origins.0);
+            } else {
+                last_origins.0.clear();
+            }
+            last_literal_lines.0.extend(literal_lines.0.into_iter().map(|k| last.len() + k));
+            last.extend(lines);
+        }
+        _ => spans.push(Span::Lines(lines, origins, literal_lines)),
+    }
+}
+
+// 行の元のファイルと行番号（1 始まり）です。
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Origin {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+// 行のまとまりの、それぞれの行の元の位置です。わからなければ空です。
+// 出力の内容には影響しないので、比較では（構文木の位置情報と同様に）無視します。
+#[derive(Clone, Debug, Default)]
+pub struct Origins(pub Vec<Origin>);
+
+impl Origins {
+    pub fn get(&self, index: usize) -> Option<&Origin> {
+        self.0.get(index)
+    }
+}
+
+impl PartialEq for Origins {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Hash for Origins {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+// 行のまとまりのうち、複数行にわたる文字列リテラルの途中から始まる行の番号（昇順）です。
+// これらの行はインデントを変えるとリテラルの値が変わるので、そのまま出力します。
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct LiteralLines(pub Vec<usize>);
+
+impl LiteralLines {
+    pub fn contains(&self, index: usize) -> bool {
+        self.0.binary_search(&index).is_ok()
+    }
+}
@@ -0,0 +1,239 @@
+use {
+    crate::{
+        config_toml::{parse_toml, Manifest},
+        diagnostic::Location,
+        normalize_path, BundlerError, ConfigToml, Result,
+    },
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+    toml::Value,
+};
+
+// ワークスペースのメンバーのクレートです。
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WorkspaceMember {
+    // [package] セクションの name（ハイフンつなぎのまま）
+    pub name: String,
+    pub root: PathBuf,
+}
+
+// ワークスペースの Cargo.toml の `[workspace]` セクションから、メンバーのクレートを名前順に列挙します。
+//
+// * `members` にはグロブを書くことができます。
+// * `exclude` に書かれたディレクトリとその下にあるクレートは除きます。
+// * Cargo.toml のないディレクトリは無視します。
+pub fn workspace_members(workspace_root: &Path) -> Result<Vec<WorkspaceMember>> {
+    let config_path = workspace_root.join("Cargo.toml");
+    if !config_path.is_file() {
+        return Err(BundlerError::CargoTomlNotFound {
+            path: config_path,
+            location: None,
+        });
+    }
+    let buf = fs::read_to_string(&config_path).map_err(|e| BundlerError::CargoTomlReadError {
+        path: config_path.clone(),
+        location: None,
+        source: e,
+    })?;
+    let value = parse_toml(&buf, &config_path)?;
+    let manifest = Manifest {
+        path: &config_path,
+        content: &buf,
+    };
+    let Some(workspace) = value.get("workspace") else {
+        return Err(BundlerError::NotAWorkspace { path: config_path });
+    };
+    let patterns = |key: &str| -> Result<Vec<&str>> {
+        match workspace.get(key) {
+            None => Ok(Vec::new()),
+            Some(value) => value
+                .as_array()
+                .and_then(|values| values.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+                .ok_or_else(|| BundlerError::InvalidWorkspaceMember {
+                    pattern: format!("{:?}", value),
+                    message: format!("`workspace.{}` is not an array of strings", key),
+                    location: manifest.locate("workspace", Some(key)),
+                }),
+        }
+    };
+    let excludes = patterns("exclude")?
+        .into_iter()
+        .map(|exclude| normalize_path(&workspace_root.join(exclude)))
+        .collect::<Vec<_>>();
+
+    // ワークスペースのルート自身もパッケージであれば、メンバーです。
+    let mut roots = Vec::new();
+    if value.get("package").is_some() {
+        roots.push(workspace_root.to_path_buf());
+    }
+    for pattern in patterns("members")? {
+        let full_pattern = workspace_root.join(pattern);
+        let invalid = |message: String| BundlerError::InvalidWorkspaceMember {
+            pattern: pattern.to_owned(),
+            message,
+            location: manifest.locate("workspace", Some("members")),
+        };
+        let full_pattern = full_pattern
+            .to_str()
+            .ok_or_else(|| invalid("not a valid UTF-8 path".to_owned()))?;
+        let paths = glob::glob(full_pattern).map_err(|e| invalid(e.to_string()))?;
+        roots.extend(paths.filter_map(std::result::Result::ok));
+    }
+
+    let mut members = Vec::new();
+    for root in roots {
+        let normalized = normalize_path(&root);
+        if excludes.iter().any(|exclude| normalized.starts_with(exclude)) || !root.join("Cargo.toml").is_file() {
+            continue;
+        }
+        if let Some(name) = ConfigToml::from_crate_root(&root)?.name {
+            members.push(WorkspaceMember { name, root });
+        }
+    }
+    members.sort();
+    members.dedup();
+    Ok(members)
+}
+
+// ワークスペースから、パッケージ名がハイフンつなぎでもアンダースコアつなぎでも一致するクレートを探します。
+// 見つからなければ、名前の近いメンバーを添えてエラーにします。
+pub fn find_crate(workspace_root: &Path, crate_name: &str) -> Result<WorkspaceMember> {
+    let members = workspace_members(workspace_root)?;
+    let normalize = |name: &str| name.replace('-', "_");
+    let target = normalize(crate_name);
+    // 綴りまで一致するものを優先します。
+    if let Some(member) = members
+        .iter()
+        .find(|member| member.name == crate_name)
+        .or_else(|| members.iter().find(|member| normalize(&member.name) == target))
+    {
+        return Ok(member.clone());
+    }
+
+    let mut close_matches = members
+        .iter()
+        .filter_map(|member| {
+            let name = normalize(&member.name);
+            let distance = edit_distance(&name, &target);
+            let close = distance <= (target.chars().count() / 3).max(1)
+                || name.contains(&target)
+                || target.contains(&name);
+            close.then_some((distance, member.name.clone()))
+        })
+        .collect::<Vec<_>>();
+    close_matches.sort();
+    Err(BundlerError::CrateNotInWorkspace {
+        name: crate_name.to_owned(),
+        workspace_root: workspace_root.to_path_buf(),
+        close_matches: close_matches.into_iter().map(|(_, name)| name).collect(),
+        location: members_location(workspace_root).map(Box::new),
+    })
+}
+
+// `[workspace]` セクションの `members`（なければヘッダー）の位置です。
+fn members_location(workspace_root: &Path) -> Option<Location> {
+    let path = workspace_root.join("Cargo.toml");
+    let content = fs::read_to_string(&path).ok()?;
+    let manifest = Manifest {
+        path: &path,
+        content: &content,
+    };
+    manifest
+        .locate("workspace", Some("members"))
+        .or_else(|| manifest.locate("workspace", None))
+}
+
+// 文字単位の編集距離です。
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut dp = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut prev = dp[0];
+        dp[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            let next = (prev + usize::from(x != y)).min(dp[j] + 1).min(dp[j + 1] + 1);
+            prev = dp[j + 1];
+            dp[j + 1] = next;
+        }
+    }
+    dp[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{edit_distance, find_crate, workspace_members},
+        crate::{diagnostic::render, BundlerError},
+        std::path::{Path, PathBuf},
+        test_case::test_case,
+    };
+
+    const WORKSPACE: &str = "../testcase/workspace";
+
+    #[test]
+    fn test_workspace_members() {
+        let members = workspace_members(Path::new(WORKSPACE)).unwrap();
+        let members = members
+            .iter()
+            .map(|member| (member.name.as_str(), member.root.as_path()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            vec![
+                ("app", Path::new("../testcase/workspace/tools/app")),
+                ("seg-tree", Path::new("../testcase/workspace/libs/segtree")),
+                ("union_find", Path::new("../testcase/workspace/libs/union_find")),
+            ]
+        );
+    }
+
+    #[test_case("seg-tree" => PathBuf::from("../testcase/workspace/libs/segtree"); "exact")]
+    #[test_case("seg_tree" => PathBuf::from("../testcase/workspace/libs/segtree"); "snake case")]
+    #[test_case("union-find" => PathBuf::from("../testcase/workspace/libs/union_find"); "chain case")]
+    #[test_case("app" => PathBuf::from("../testcase/workspace/tools/app"); "not a glob")]
+    fn test_find_crate(crate_name: &str) -> PathBuf {
+        find_crate(Path::new(WORKSPACE), crate_name).unwrap().root
+    }
+
+    #[test_case("segtre" => vec!["seg-tree".to_owned()]; "typo")]
+    #[test_case("union" => vec!["union_find".to_owned()]; "prefix")]
+    #[test_case("old" => Vec::<String>::new(); "excluded")]
+    fn test_find_crate_close_matches(crate_name: &str) -> Vec<String> {
+        match find_crate(Path::new(WORKSPACE), crate_name).unwrap_err() {
+            BundlerError::CrateNotInWorkspace { close_matches, .. } => close_matches,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_find_crate_location() {
+        let err = find_crate(Path::new(WORKSPACE), "segtre").unwrap_err();
+        assert_eq!(
+            render(&err),
+            concat!(
+                "error: Crate \"segtre\" is not a member of the workspace at \"../testcase/workspace\"",
+                " (did you mean seg-tree?)\n",
+                " --> ../testcase/workspace/Cargo.toml:2:1\n",
+                "  |\n",
+                "2 | members = [\"libs/*\", \"tools/app\"]\n",
+                "  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^",
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_a_workspace() {
+        let err = workspace_members(Path::new("../testcase/deps/leaf")).unwrap_err();
+        assert!(matches!(err, BundlerError::NotAWorkspace { .. }));
+    }
+
+    #[test_case("abc", "abc" => 0; "same")]
+    #[test_case("abc", "abd" => 1; "substitution")]
+    #[test_case("abc", "ac" => 1; "deletion")]
+    #[test_case("", "ab" => 2; "empty")]
+    fn test_edit_distance(a: &str, b: &str) -> usize {
+        edit_distance(a, b)
+    }
+}
@@ -0,0 +1,105 @@
+// tests/fixtures 以下のディレクトリごとに procon-bundler を実行し、出力を期待値と比べます。
+//
+// それぞれのディレクトリには次のものを置きます。
+//
+// - `cmd`: procon-bundler に渡す引数（空白区切り、`#` で始まる行は無視）。パスはディレクトリからの相対パスです。
+// - `expected.rs`: 成功したときの標準出力
+// - `expected.stderr`: 失敗したときの標準エラー出力
+//
+// `BLESS=1 cargo test --test fixtures` で、実際の出力で期待値を書き換えます。
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+};
+
+const EXPECTED_STDOUT: &str = "expected.rs";
+const EXPECTED_STDERR: &str = "expected.stderr";
+
+#[test]
+fn fixtures() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let bless = env::var_os("BLESS").is_some_and(|bless| bless != "0");
+    let mut dirs = fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("cmd").is_file())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    assert!(!dirs.is_empty(), "no fixtures found in {}", root.display());
+
+    let failures = dirs.iter().filter_map(|dir| run_fixture(dir, bless).err()).collect::<Vec<_>>();
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} fixtures failed (rerun with BLESS=1 to accept the actual output):\n\n{}",
+            failures.len(),
+            dirs.len(),
+            failures.join("\n\n")
+        );
+    }
+}
+
+fn run_fixture(dir: &Path, bless: bool) -> Result<(), String> {
+    let name = dir.file_name().unwrap().to_string_lossy().into_owned();
+    let cmd = fs::read_to_string(dir.join("cmd")).unwrap();
+    let args = cmd
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>();
+    let output = Command::new(env!("CARGO_BIN_EXE_procon-bundler"))
+        .args(&args)
+        .current_dir(dir)
+        // 環境によって出力が変わらないようにします。
+        .env_remove("RUSTC")
+        .output()
+        .unwrap();
+    let (file, actual, other) = if output.status.success() {
+        (EXPECTED_STDOUT, output.stdout, EXPECTED_STDERR)
+    } else {
+        (EXPECTED_STDERR, output.stderr, EXPECTED_STDOUT)
+    };
+    let actual = String::from_utf8(actual).unwrap();
+    let path = dir.join(file);
+
+    if bless {
+        fs::write(&path, &actual).unwrap();
+        let _ = fs::remove_file(dir.join(other));
+        return Ok(());
+    }
+    if dir.join(other).is_file() {
+        return Err(format!(
+            "[{name}] `procon-bundler {}` {}, but {other} exists:\n{actual}",
+            args.join(" "),
+            if output.status.success() { "succeeded" } else { "failed" },
+        ));
+    }
+    let expected = fs::read_to_string(&path).map_err(|e| format!("[{name}] cannot read {file}: {e}"))?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("[{name}] {} differs:\n{}", file, diff(&expected, &actual)))
+    }
+}
+
+// 最初に異なる行と、その前後を表示します。
+fn diff(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+    let Some(i) = (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i)) else {
+        return "only line endings differ".to_owned();
+    };
+    let context = |lines: &[&str]| {
+        lines[i.saturating_sub(2).min(lines.len())..(i + 3).min(lines.len())]
+            .iter()
+            .map(|line| format!("  | {line}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    format!(
+        "first difference at line {}\nexpected:\n{}\nactual:\n{}",
+        i + 1,
+        context(&expected),
+        context(&actual)
+    )
+}
@@ -0,0 +1,6 @@
+use util::num::gcd;
+
+/// Least common multiple.
+pub fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
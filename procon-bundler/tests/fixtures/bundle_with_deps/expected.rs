@@ -0,0 +1,27 @@
+// util {{{
+// https://ngtkana.github.io/ac-adapter-rs/util/index.html
+#[allow(unused_imports)]
+#[allow(dead_code)]
+mod util {
+    pub mod num {
+        pub fn gcd(a: u64, b: u64) -> u64 {
+            if b == 0 {
+                a
+            } else {
+                gcd(b, a % b)
+            }
+        }
+    }
+}
+// }}}
+// app {{{
+// https://ngtkana.github.io/ac-adapter-rs/app/index.html
+#[allow(unused_imports)]
+#[allow(dead_code)]
+mod app {
+    use crate::util::num::gcd;
+    pub fn lcm(a: u64, b: u64) -> u64 {
+        a / gcd(a, b) * b
+    }
+}
+// }}}
@@ -0,0 +1,15 @@
+pub fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_gcd() {
+        assert_eq!(super::gcd(4, 6), 2);
+    }
+}
@@ -0,0 +1,5 @@
+use lib1::shapes::square::Square;
+
+pub fn area() -> u32 {
+    lib1::sub::g() + Square(2).area()
+}
@@ -0,0 +1 @@
+The bundle compiles with rustc (edition 2021)
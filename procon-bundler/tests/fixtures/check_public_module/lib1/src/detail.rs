@@ -0,0 +1,3 @@
+pub fn base() -> u32 {
+    41
+}
@@ -0,0 +1,3 @@
+pub mod shapes;
+pub mod sub;
+pub(crate) mod detail;
@@ -0,0 +1,3 @@
+pub fn g() -> u32 {
+    crate::detail::base() + 1
+}
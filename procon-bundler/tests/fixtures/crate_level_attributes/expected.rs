@@ -0,0 +1,12 @@
+// no_std_lib {{{
+// https://ngtkana.github.io/ac-adapter-rs/no_std_lib/index.html
+#[allow(unused_imports)]
+#[allow(dead_code)]
+mod no_std_lib {
+    #![warn(missing_docs)]
+    #![cfg_attr(docsrs, allow(unused))]
+    pub fn one() -> u32 {
+        1
+    }
+}
+// }}}
@@ -0,0 +1,7 @@
+#![cfg(feature = "alloc")]
+//! Needs an allocator.
+
+/// Returns two.
+pub fn two() -> u32 {
+    2
+}
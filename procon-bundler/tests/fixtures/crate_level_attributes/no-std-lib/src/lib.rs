@@ -0,0 +1,13 @@
+//! A `no_std` library.
+#![no_std]
+#![warn(missing_docs)]
+#![cfg_attr(docsrs, feature(doc_cfg), allow(unused))]
+#![doc(html_root_url = "https://example.com/no-std-lib")]
+
+pub mod alloc_only;
+pub mod testing;
+
+/// Returns one.
+pub fn one() -> u32 {
+    1
+}
@@ -0,0 +1,7 @@
+use fenwick::Fenwick;
+
+fn main() {
+    let mut fenwick = Fenwick::new(3);
+    fenwick.add(1, 2);
+    println!("{}", fenwick.sum(2));
+}
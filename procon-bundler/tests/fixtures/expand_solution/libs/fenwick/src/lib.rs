@@ -0,0 +1,24 @@
+pub struct Fenwick(Vec<i64>);
+
+impl Fenwick {
+    pub fn new(n: usize) -> Self {
+        Self(vec![0; n + 1])
+    }
+    pub fn add(&mut self, mut i: usize, x: i64) {
+        i += 1;
+        while i < self.0.len() {
+            self.0[i] += x;
+            i += i & i.wrapping_neg();
+        }
+    }
+    pub fn sum(&self, mut i: usize) -> i64 {
+        let mut result = 0;
+        while i > 0 {
+            result += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        result
+    }
+}
+
+pub fn unused() {}
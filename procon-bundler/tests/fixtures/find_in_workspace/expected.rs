@@ -0,0 +1,4 @@
+// seg-tree v0.2.1
+mod seg_tree {
+    pub struct SegTree;
+}
@@ -0,0 +1,2 @@
+pub use leaf::Leaf;
+pub struct Mid(pub Leaf);
@@ -0,0 +1,6 @@
+use leaf::Leaf;
+use mid::Mid;
+
+pub fn build() -> Mid {
+    Mid(Leaf)
+}
@@ -0,0 +1,5 @@
+use root::build;
+
+fn main() {
+    let _ = build();
+}
@@ -0,0 +1,3 @@
+pub use base::*;
+#[cfg(feature = "big")]
+pub use extra::Extra;
@@ -0,0 +1,6 @@
+#[cfg(feature = "small")]
+pub const SMALL: bool = true;
+#[cfg(feature = "fast")]
+pub fn fast() {}
+#[cfg(feature = "big")]
+pub fn big() {}
@@ -0,0 +1 @@
+pub struct App(pub fenwick_tree::Fenwick, pub seg::Seg);
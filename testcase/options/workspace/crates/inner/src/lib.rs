@@ -0,0 +1,9 @@
+//! Two-space indented crate.
+
+/// A module.
+pub mod a {
+  /// A function.
+  pub fn f() -> u32 {
+    42
+  }
+}
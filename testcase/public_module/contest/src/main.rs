@@ -0,0 +1,5 @@
+use geometry::point::{dot, Point};
+
+fn main() {
+    println!("{}", dot(&Point(1, 2), &Point(3, 4)));
+}
@@ -0,0 +1,5 @@
+pub struct Point(pub i64, pub i64);
+
+pub fn dot(a: &Point, b: &Point) -> i64 {
+    a.0 * b.0 + a.1 * b.1
+}
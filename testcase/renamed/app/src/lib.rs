@@ -0,0 +1,4 @@
+use fenwick::Fenwick;
+pub fn make() -> user::User {
+    user::User(Fenwick)
+}
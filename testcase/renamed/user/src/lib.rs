@@ -0,0 +1 @@
+pub struct User(pub fw::Fenwick);